@@ -1,3 +1,4 @@
+#![allow(deprecated)]
 use criterion::{criterion_group, criterion_main, BatchSize, Criterion, ParameterizedBenchmark};
 use rand::prelude::*;
 
@@ -28,13 +29,12 @@ impl SledStore {
 
     fn get(&mut self, key: String) -> Result<Option<String>> {
         let tree: &Tree = &self.0;
-        Ok(tree
-            .get(key)
+        tree.get(key)
             .map_err(|e| TinkvError::Custom(format!("{}", e)))?
             .map(|i_vec| AsRef::<[u8]>::as_ref(&i_vec).to_vec())
             .map(String::from_utf8)
             .transpose()
-            .map_err(|e| TinkvError::Custom(format!("{}", e)))?)
+            .map_err(|e| TinkvError::Custom(format!("{}", e)))
     }
 }
 
@@ -45,7 +45,7 @@ fn set_benchmark(c: &mut Criterion) {
             b.iter_batched(
                 || {
                     let tmpdir = TempDir::new().unwrap();
-                    (Store::open(&tmpdir.path()).unwrap(), tmpdir)
+                    (Store::open(tmpdir.path()).unwrap(), tmpdir)
                 },
                 |(mut store, _tmpdir)| {
                     for i in 1..(1 << 12) {
@@ -63,7 +63,7 @@ fn set_benchmark(c: &mut Criterion) {
         b.iter_batched(
             || {
                 let tmpdir = TempDir::new().unwrap();
-                (SledStore::open(&tmpdir.path()), tmpdir)
+                (SledStore::open(tmpdir.path()), tmpdir)
             },
             |(mut db, _tmpdir)| {
                 for i in 1..(1 << 12) {
@@ -82,7 +82,7 @@ fn get_benchmark(c: &mut Criterion) {
         "tinkv-store",
         |b, i| {
             let tempdir = TempDir::new().unwrap();
-            let mut store = Store::open(&tempdir.path()).unwrap();
+            let mut store = Store::open(tempdir.path()).unwrap();
             for key_i in 1..(1 << i) {
                 store
                     .set(format!("key_{}", key_i).as_bytes(), b"value")
@@ -100,7 +100,7 @@ fn get_benchmark(c: &mut Criterion) {
     )
     .with_function("sled_store", |b, i| {
         let tmpdir = TempDir::new().unwrap();
-        let mut db = SledStore::open(&tmpdir.path());
+        let mut db = SledStore::open(tmpdir.path());
         for key_i in 1..(1 << i) {
             db.set(format!("key_{}", key_i), "value".to_owned())
                 .unwrap();
@@ -1,4 +1,3 @@
-use pretty_env_logger;
 use std::time;
 use tinkv::{self};
 
@@ -38,8 +37,8 @@ fn main() -> tinkv::Result<()> {
 
         println!(
             "key={}, value={}",
-            String::from_utf8_lossy(&k),
-            String::from_utf8_lossy(&v)
+            String::from_utf8_lossy(k),
+            String::from_utf8_lossy(v)
         );
 
         if index > 5 {
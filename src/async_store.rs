@@ -0,0 +1,291 @@
+//! An async facade of datastore, enabled by the `async` feature.
+//!
+//! Store operations block on file IO, they are executed on a dedicated
+//! IO thread owning the datastore instead of the executor threads. All
+//! the operations of a datastore need exclusive access to it, so one
+//! thread is enough, and operations are applied in order of submission.
+use crate::error::{Result, TinkvError};
+use crate::store::{OpenOptions, Stats, Store};
+use futures_core::Stream;
+use log::debug;
+use std::future::Future;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::task::{Context, Poll};
+use std::thread;
+use tokio::sync::oneshot;
+
+/// Number of key value pairs fetched by a range stream at a time.
+const RANGE_BATCH_SIZE: usize = 128;
+
+type Job = Box<dyn FnOnce(&mut Store) + Send>;
+
+/// A handle of datastore for async code, operations return futures.
+///
+/// It can be cloned and shared by tasks, the datastore is closed
+/// after all the handles are dropped.
+#[derive(Debug, Clone)]
+pub struct AsyncStore {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl AsyncStore {
+    /// Open a datastore with the given path on a new IO thread.
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_options(path, &OpenOptions::new()).await
+    }
+
+    /// Open a datastore with the given path and custom options
+    /// on a new IO thread.
+    pub async fn open_with_options<P: AsRef<Path>>(path: P, options: &OpenOptions) -> Result<Self> {
+        let path: PathBuf = path.as_ref().to_owned();
+        let options = options.clone();
+        let (tx, rx) = oneshot::channel();
+        thread::Builder::new()
+            .name("tinkv-io".to_owned())
+            .spawn(move || {
+                let store = match options.open(&path) {
+                    Ok(store) => store,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                };
+                let (handle, worker) = Self::with_worker(store);
+                let _ = tx.send(Ok(handle));
+                worker.run();
+            })?;
+        rx.await.map_err(|_| io_thread_gone())?
+    }
+
+    /// Move an opened datastore to a new IO thread.
+    pub fn new(store: Store) -> Result<Self> {
+        let (handle, worker) = Self::with_worker(store);
+        thread::Builder::new()
+            .name("tinkv-io".to_owned())
+            .spawn(move || worker.run())?;
+        Ok(handle)
+    }
+
+    fn with_worker(store: Store) -> (Self, Worker) {
+        let (jobs, rx) = mpsc::channel();
+        (AsyncStore { jobs }, Worker { store, jobs: rx })
+    }
+
+    /// Run function `f` with the datastore on the IO thread,
+    /// and return its result.
+    pub async fn call<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Store) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.submit(f).await
+    }
+
+    fn submit<F, T>(&self, f: F) -> Call<T>
+    where
+        F: FnOnce(&mut Store) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move |store| {
+            let _ = tx.send(f(store));
+        });
+        // the receiver reports the error if the job is not accepted.
+        let _ = self.jobs.send(job);
+        Call { rx }
+    }
+
+    /// Get value of a key, `None` if key not found.
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = key.to_vec();
+        self.call(move |store| store.get(&key)).await
+    }
+
+    /// Save key value pair.
+    pub async fn set(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let (key, value) = (key.to_vec(), value.to_vec());
+        self.call(move |store| store.set(&key, &value)).await
+    }
+
+    /// Remove a key, `TinkvError::KeyNotFound` is returned if key not found.
+    pub async fn remove(&self, key: &[u8]) -> Result<()> {
+        let key = key.to_vec();
+        self.call(move |store| store.remove(&key)).await
+    }
+
+    /// Return `true` if datastore contains the given key.
+    pub async fn contains_key(&self, key: &[u8]) -> Result<bool> {
+        let key = key.to_vec();
+        self.call(move |store| Ok(store.contains_key(&key))).await
+    }
+
+    /// Return total number of keys in datastore.
+    pub async fn len(&self) -> Result<u64> {
+        self.call(|store| Ok(store.len())).await
+    }
+
+    /// Check datastore is empty or not.
+    pub async fn is_empty(&self) -> Result<bool> {
+        self.call(|store| Ok(store.is_empty())).await
+    }
+
+    /// Return current stats of datastore.
+    pub async fn stats(&self) -> Result<Stats> {
+        self.call(|store| Ok(*store.stats())).await
+    }
+
+    /// Reclaim space of stale entries.
+    pub async fn compact(&self) -> Result<()> {
+        self.call(|store| store.compact()).await
+    }
+
+    /// Force flushing any pending writes to disk.
+    pub async fn sync(&self) -> Result<()> {
+        self.call(|store| store.sync()).await
+    }
+
+    /// Return a stream of key value pairs within the given range,
+    /// in key order.
+    ///
+    /// Pairs are fetched from the IO thread in batches, writes made in
+    /// the meantime are visible to the batches not fetched yet.
+    pub fn range<R: RangeBounds<Vec<u8>>>(&self, range: R) -> RangeStream {
+        RangeStream {
+            store: self.clone(),
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+            buffer: Vec::new().into_iter(),
+            pending: None,
+            done: false,
+        }
+    }
+
+    /// Return a stream of all the key value pairs, in key order.
+    pub fn iter(&self) -> RangeStream {
+        self.range(..)
+    }
+}
+
+/// The IO thread, it applies jobs to the datastore one by one.
+struct Worker {
+    store: Store,
+    jobs: mpsc::Receiver<Job>,
+}
+
+impl Worker {
+    fn run(mut self) {
+        debug!("io thread started, path: {}", self.store.path().display());
+        while let Ok(job) = self.jobs.recv() {
+            job(&mut self.store);
+        }
+        debug!("all handles are dropped, close datastore");
+    }
+}
+
+fn io_thread_gone() -> TinkvError {
+    TinkvError::Custom("io thread of datastore is gone".to_owned())
+}
+
+/// Future of an operation submitted to the IO thread.
+#[derive(Debug)]
+struct Call<T> {
+    rx: oneshot::Receiver<Result<T>>,
+}
+
+impl<T> Future for Call<T> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.rx).poll(cx) {
+            Poll::Ready(Ok(r)) => Poll::Ready(r),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(io_thread_gone())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Pairs fetched, and the last key examined if there may be more.
+type Batch = (Vec<(Vec<u8>, Vec<u8>)>, Option<Vec<u8>>);
+
+/// A stream of key value pairs of `AsyncStore`.
+#[derive(Debug)]
+pub struct RangeStream {
+    store: AsyncStore,
+    // keys after the last fetched one are fetched next.
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    buffer: std::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+    pending: Option<Call<Batch>>,
+    done: bool,
+}
+
+impl RangeStream {
+    /// Fetch the next batch of pairs.
+    fn fetch(&self) -> Call<Batch> {
+        let bounds = (self.start.clone(), self.end.clone());
+        self.store.submit(move |store| {
+            let keys = store
+                .keys_range(bounds)
+                .take(RANGE_BATCH_SIZE)
+                .cloned()
+                .collect::<Vec<_>>();
+            let last_key = match keys.len() {
+                RANGE_BATCH_SIZE => keys.last().cloned(),
+                _ => None,
+            };
+            let mut pairs = Vec::with_capacity(keys.len());
+            for key in keys {
+                // expired keys are skipped.
+                if let Some(value) = store.get(&key)? {
+                    pairs.push((key, value));
+                }
+            }
+            Ok((pairs, last_key))
+        })
+    }
+}
+
+impl Stream for RangeStream {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(pair) = self.buffer.next() {
+                return Poll::Ready(Some(Ok(pair)));
+            }
+            if self.done {
+                return Poll::Ready(None);
+            }
+
+            if self.pending.is_none() {
+                let call = self.fetch();
+                self.pending = Some(call);
+            }
+            let r = match self.pending.as_mut() {
+                Some(call) => match Pin::new(call).poll(cx) {
+                    Poll::Ready(r) => r,
+                    Poll::Pending => return Poll::Pending,
+                },
+                None => unreachable!(),
+            };
+            self.pending = None;
+
+            match r {
+                Ok((pairs, last_key)) => {
+                    match last_key {
+                        Some(key) => self.start = Bound::Excluded(key),
+                        None => self.done = true,
+                    }
+                    self.buffer = pairs.into_iter();
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
+}
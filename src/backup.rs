@@ -0,0 +1,192 @@
+//! Backup helpers, a backup directory is described by a manifest file.
+//!
+//! Segment files are immutable once sealed and their ids only grow, so an
+//! incremental backup only needs to ship segment files which are not in
+//! the previous backup.
+use crate::config;
+use crate::error::{Result, TinkvError};
+use log::{debug, info};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// A manifest lists all the segment files of datastore at backup time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    /// creation time in milliseconds since unix epoch.
+    pub created_at: u64,
+    /// segment files (data files and hint files) of datastore.
+    pub files: Vec<ManifestFile>,
+    /// names of segment files in the previous backup, which
+    /// have been removed (by compaction) since then.
+    pub removed: Vec<String>,
+}
+
+/// A segment file recorded in the manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestFile {
+    /// file name, e.g. `000000000001.tinkv.data`.
+    pub name: String,
+    /// file size in bytes.
+    pub size: u64,
+    /// `true` if the file is not copied into this backup,
+    /// it can be found in the previous backups.
+    pub inherited: bool,
+}
+
+impl Manifest {
+    /// Load manifest file from the given backup directory.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(config::MANIFEST_FILE_NAME);
+        let reader = BufReader::new(fs::File::open(&path)?);
+
+        let mut manifest = Manifest::default();
+        for line in reader.lines() {
+            let line = line?;
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                [] => {}
+                ["created_at", value] => manifest.created_at = value.parse()?,
+                [kind @ "file", name, size] | [kind @ "inherited", name, size] => {
+                    manifest.files.push(ManifestFile {
+                        name: (*name).to_owned(),
+                        size: size.parse()?,
+                        inherited: *kind == "inherited",
+                    })
+                }
+                ["removed", name] => manifest.removed.push((*name).to_owned()),
+                _ => {
+                    return Err(TinkvError::Custom(format!(
+                        "invalid manifest line '{}' in {}",
+                        line,
+                        path.display()
+                    )))
+                }
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Save manifest file into the given backup directory.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(config::MANIFEST_FILE_NAME);
+        let f = fs::File::create(&path)?;
+        let mut w = BufWriter::new(&f);
+        write!(w, "{}", self)?;
+        w.flush()?;
+        f.sync_all()?;
+        Ok(())
+    }
+
+    /// Return the file with the given name.
+    pub fn get(&self, name: &str) -> Option<&ManifestFile> {
+        self.files.iter().find(|f| f.name == name)
+    }
+
+    /// Return total size (bytes) of the files copied into this backup.
+    pub fn total_size(&self) -> u64 {
+        self.files
+            .iter()
+            .filter(|f| !f.inherited)
+            .map(|f| f.size)
+            .sum()
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "created_at {}", self.created_at)?;
+        for file in self.files.iter() {
+            let kind = if file.inherited { "inherited" } else { "file" };
+            writeln!(f, "{} {} {}", kind, file.name, file.size)?;
+        }
+        for name in self.removed.iter() {
+            writeln!(f, "removed {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Restore a datastore into directory `target` from a full backup and
+/// the following incremental backups (`backups` must be ordered from the
+/// oldest to the newest one).
+///
+/// Files listed in manifest of the newest backup are picked from the newest
+/// backup which contains them.
+pub fn restore_backup<P: AsRef<Path>>(target: P, backups: &[PathBuf]) -> Result<Manifest> {
+    let target = target.as_ref();
+    if backups.is_empty() {
+        return Err(TinkvError::Custom("no backup to restore".to_owned()));
+    }
+
+    if target.exists() && fs::read_dir(target)?.next().is_some() {
+        return Err(TinkvError::Custom(format!(
+            "restore directory '{}' is not empty",
+            target.display()
+        )));
+    }
+    fs::create_dir_all(target)?;
+
+    let manifests = backups
+        .iter()
+        .map(|dir| Manifest::load(dir))
+        .collect::<Result<Vec<_>>>()?;
+    let latest = manifests.last().expect("manifest not found");
+
+    for file in latest.files.iter() {
+        let found = backups.iter().zip(manifests.iter()).rev().find(|(_, m)| {
+            m.get(&file.name)
+                .map(|f| !f.inherited && f.size == file.size)
+                .unwrap_or(false)
+        });
+
+        let (dir, _) = found.ok_or_else(|| {
+            TinkvError::Custom(format!("segment file '{}' not found in backups", file.name))
+        })?;
+
+        debug!("restore {} from {}", &file.name, dir.display());
+        fs::copy(dir.join(&file.name), target.join(&file.name))?;
+    }
+
+    info!(
+        "restored {} files from {} backups into {}",
+        latest.files.len(),
+        backups.len(),
+        target.display()
+    );
+
+    Ok(latest.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_manifest_save_and_load() {
+        let tmpdir = TempDir::new().unwrap();
+        let manifest = Manifest {
+            created_at: 1592475604853,
+            files: vec![
+                ManifestFile {
+                    name: "000000000001.tinkv.data".to_owned(),
+                    size: 1024,
+                    inherited: true,
+                },
+                ManifestFile {
+                    name: "000000000002.tinkv.data".to_owned(),
+                    size: 64,
+                    inherited: false,
+                },
+            ],
+            removed: vec!["000000000000.tinkv.data".to_owned()],
+        };
+
+        manifest.save(tmpdir.path()).unwrap();
+        assert_eq!(Manifest::load(tmpdir.path()).unwrap(), manifest);
+        assert_eq!(manifest.total_size(), 64);
+    }
+}
@@ -2,10 +2,11 @@
 use clap_verbosity_flag::Verbosity;
 use std::error::Error;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use log::debug;
 use structopt::StructOpt;
-use tinkv::{config, OpenOptions, Server};
+use tinkv::{config, EvictionPolicy, MemoryEngine, OpenOptions, Server, SyncPolicy};
 
 const DEFAULT_DATASTORE_PATH: &str = "/usr/local/var/tinkv";
 const DEFAULT_LISTENING_ADDR: &str = "127.0.0.1:7379";
@@ -42,6 +43,41 @@ struct Opt {
     /// Sync all pending writes to disk after each writing operation (default to false).
     #[structopt(long, value_name = "SYNC")]
     sync: bool,
+    /// Set when to sync writes to disk: always, never, <N>ms or <N>bytes.
+    #[structopt(long, value_name = "POLICY", conflicts_with = "sync")]
+    sync_policy: Option<SyncPolicy>,
+    /// Set max number of keys, keys are evicted by eviction policy once it's reached.
+    #[structopt(long, value_name = "KEYS")]
+    max_keys: Option<u64>,
+    /// Set max size (in bytes) of live entries, keys are evicted by eviction policy once it's reached.
+    #[structopt(long, value_name = "SIZE")]
+    max_total_size: Option<u64>,
+    /// Set eviction policy: noeviction, allkeys-lru, allkeys-lfu, allkeys-random or volatile-ttl.
+    #[structopt(long, value_name = "POLICY", default_value = "noeviction")]
+    eviction_policy: EvictionPolicy,
+    /// Scrub sealed data files in background every <SECS> seconds to find corrupted records.
+    #[structopt(long, value_name = "SECS")]
+    scrub_interval: Option<u64>,
+    /// Set max bytes per second read and written by compaction, hint generation and scrubbing.
+    #[structopt(long, value_name = "BYTES")]
+    background_io_rate: Option<u64>,
+    /// Keep all key value pairs in memory only, run as a pure cache. Options of
+    /// the datastore, replication and scrubbing are not supported.
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "max-key-size",
+            "max-value-size",
+            "max-data-file-size",
+            "sync",
+            "sync-policy",
+            "max-keys",
+            "max-total-size",
+            "scrub-interval",
+            "background-io-rate",
+        ],
+    )]
+    in_memory: bool,
 }
 fn main() -> Result<(), Box<dyn Error>> {
     let opt = Opt::from_args();
@@ -51,23 +87,42 @@ fn main() -> Result<(), Box<dyn Error>> {
     pretty_env_logger::init_timed();
 
     debug!("get tinkv server config from command line: {:?}", &opt);
-    let store = OpenOptions::new()
-        .max_key_size(
-            opt.max_key_size
-                .unwrap_or_else(|| config::DEFAULT_MAX_KEY_SIZE),
-        )
-        .max_value_size(
-            opt.max_value_size
-                .unwrap_or_else(|| config::DEFAULT_MAX_VALUE_SIZE),
-        )
+    if opt.in_memory {
+        Server::new(MemoryEngine::new()).run(opt.addr)?;
+        return Ok(());
+    }
+
+    let sync_policy = match opt.sync_policy {
+        Some(policy) => policy,
+        None if opt.sync => SyncPolicy::Always,
+        None => SyncPolicy::Never,
+    };
+    let mut options = OpenOptions::new();
+    if let Some(max_keys) = opt.max_keys {
+        options.max_keys(max_keys);
+    }
+    if let Some(max_total_size) = opt.max_total_size {
+        options.max_total_size(max_total_size);
+    }
+    if let Some(rate) = opt.background_io_rate {
+        options.background_io_rate(rate);
+    }
+    let store = options
+        .eviction_policy(opt.eviction_policy)
+        .max_key_size(opt.max_key_size.unwrap_or(config::DEFAULT_MAX_KEY_SIZE))
+        .max_value_size(opt.max_value_size.unwrap_or(config::DEFAULT_MAX_VALUE_SIZE))
         .max_data_file_size(
             opt.max_data_file_size
-                .unwrap_or_else(|| config::DEFAULT_MAX_DATA_FILE_SIZE),
+                .unwrap_or(config::DEFAULT_MAX_DATA_FILE_SIZE),
         )
-        .sync(opt.sync)
+        .sync_policy(sync_policy)
         .open(DEFAULT_DATASTORE_PATH)?;
 
-    Server::new(store).run(opt.addr)?;
+    let mut server = Server::new(store);
+    if let Some(secs) = opt.scrub_interval {
+        server.scrub_in_background(Duration::from_secs(secs));
+    }
+    server.run(opt.addr)?;
 
     Ok(())
 }
@@ -1,9 +1,14 @@
 //! TinKV command line app.
 use clap_verbosity_flag::Verbosity;
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
 use std::process;
 use structopt::{self, StructOpt};
-use tinkv::{self, Store};
+use tinkv::{self, DumpOptions, Encoding, ExportOptions, Format, OpenOptions, Store};
+
+/// Number of the most common key prefixes displayed by `stats --detailed`.
+const MAX_PREFIXES_DISPLAYED: usize = 20;
 
 #[derive(Debug, StructOpt)]
 enum SubCommand {
@@ -21,7 +26,102 @@ enum SubCommand {
     /// Compact data files in datastore and reclaim disk space.
     Compact,
     /// Display statistics of the datastore.
-    Stats,
+    Stats {
+        /// Also display distribution of key sizes, value sizes and key prefixes.
+        #[structopt(long)]
+        detailed: bool,
+    },
+    /// Display statistics of each data file.
+    Segments {
+        /// Sort data files by `id` or `garbage` ratio (the highest first).
+        #[structopt(long, default_value = "id", possible_values = &["id", "garbage"])]
+        sort: String,
+    },
+    /// Create a checkpoint of the datastore in the target directory.
+    Backup {
+        #[structopt(parse(from_os_str))]
+        target: PathBuf,
+        /// Only copy segments not found in this previous backup.
+        #[structopt(long, value_name = "BACKUP", parse(from_os_str))]
+        incremental_from: Option<PathBuf>,
+    },
+    /// Export key value pairs in JSON Lines or CSV.
+    Export {
+        #[structopt(flatten)]
+        transfer: TransferOpt,
+        /// Write to this file instead of stdout.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Import key value pairs from JSON Lines or CSV.
+    Import {
+        #[structopt(flatten)]
+        transfer: TransferOpt,
+        /// Read from this file instead of stdin.
+        #[structopt(short, long, parse(from_os_str))]
+        input: Option<PathBuf>,
+        /// Number of pairs written before syncing to disk.
+        #[structopt(long, default_value = "1000")]
+        batch_size: usize,
+    },
+    /// Import string keys from a Redis RDB dump file.
+    ImportRdb {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Dump records of a data file or hint file, the file path is relative to the datastore.
+    DumpSegment {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Output one JSON object per record per line.
+        #[structopt(long)]
+        json: bool,
+        /// Only dump records with keys matching the glob pattern.
+        #[structopt(long, value_name = "PATTERN")]
+        key: Option<String>,
+        /// Only dump records starting at or after the offset.
+        #[structopt(long, value_name = "OFFSET", default_value = "0")]
+        from_offset: u64,
+        /// Only dump records starting before the offset.
+        #[structopt(long, value_name = "OFFSET")]
+        to_offset: Option<u64>,
+    },
+    /// Restore the datastore from a full backup and the following incremental backups.
+    Restore {
+        /// Backup directories, from the oldest to the newest one.
+        #[structopt(required = true, parse(from_os_str))]
+        backups: Vec<PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct TransferOpt {
+    /// Text format, `jsonl` or `csv`.
+    #[structopt(long, default_value = "jsonl")]
+    format: Format,
+    /// Encoding of keys and values, `base64` or `hex`.
+    #[structopt(long, default_value = "base64")]
+    encoding: Encoding,
+    /// Only transfer keys with this prefix.
+    #[structopt(long)]
+    prefix: Option<String>,
+    /// Keyspace to transfer.
+    #[structopt(long, default_value = "default")]
+    keyspace: String,
+}
+
+impl TransferOpt {
+    fn options(&self) -> ExportOptions {
+        let mut options = ExportOptions::new();
+        options
+            .format(self.format)
+            .encoding(self.encoding)
+            .keyspace(&self.keyspace);
+        if let Some(prefix) = &self.prefix {
+            options.prefix(prefix.as_bytes());
+        }
+        options
+    }
 }
 
 #[derive(Debug, StructOpt)]
@@ -61,7 +161,41 @@ fn main() {
 }
 
 fn dispatch(opt: &Opt) -> tinkv::Result<()> {
-    let mut store = Store::open(&opt.path)?;
+    // restore must be done before opening the datastore.
+    if let SubCommand::Restore { backups } = &opt.cmd {
+        return handle_restore_command(&opt.path, backups);
+    }
+    // segment files are read directly, datastore may be broken.
+    if let SubCommand::DumpSegment {
+        file,
+        json,
+        key,
+        from_offset,
+        to_offset,
+    } = &opt.cmd
+    {
+        let mut options = DumpOptions::new();
+        options
+            .json(*json)
+            .offsets(*from_offset..to_offset.unwrap_or(u64::MAX));
+        if let Some(pattern) = key {
+            options.key_pattern(pattern)?;
+        }
+        return handle_dump_segment_command(&opt.path.join(file), &options);
+    }
+
+    // datastore is not touched by read commands, so that they can
+    // be used along with a running server.
+    let read_only = matches!(
+        &opt.cmd,
+        SubCommand::Get { .. }
+            | SubCommand::Keys
+            | SubCommand::Scan { .. }
+            | SubCommand::Stats { .. }
+            | SubCommand::Segments { .. }
+            | SubCommand::Export { .. }
+    );
+    let mut store = OpenOptions::new().read_only(read_only).open(&opt.path)?;
 
     // dispacth subcommand handler.
     match &opt.cmd {
@@ -83,9 +217,34 @@ fn dispatch(opt: &Opt) -> tinkv::Result<()> {
         SubCommand::Scan { prefix } => {
             handle_scan_command(&mut store, prefix.as_bytes())?;
         }
-        SubCommand::Stats => {
-            handle_stats_command(&mut store)?;
+        SubCommand::Stats { detailed } => {
+            handle_stats_command(&mut store, *detailed)?;
+        }
+        SubCommand::Segments { sort } => {
+            handle_segments_command(&mut store, sort)?;
+        }
+        SubCommand::Backup {
+            target,
+            incremental_from,
+        } => {
+            handle_backup_command(&mut store, target, incremental_from.as_deref())?;
         }
+        SubCommand::Export { transfer, output } => {
+            handle_export_command(&mut store, &transfer.options(), output.as_deref())?;
+        }
+        SubCommand::Import {
+            transfer,
+            input,
+            batch_size,
+        } => {
+            let mut options = transfer.options();
+            options.batch_size(*batch_size);
+            handle_import_command(&mut store, &options, input.as_deref())?;
+        }
+        SubCommand::ImportRdb { file } => {
+            handle_import_rdb_command(&mut store, file)?;
+        }
+        SubCommand::Restore { .. } | SubCommand::DumpSegment { .. } => unreachable!(),
     }
     Ok(())
 }
@@ -140,7 +299,7 @@ fn handle_scan_command(store: &mut Store, prefix: &[u8]) -> tinkv::Result<()> {
     Ok(())
 }
 
-fn handle_stats_command(store: &mut Store) -> tinkv::Result<()> {
+fn handle_stats_command(store: &mut Store, detailed: bool) -> tinkv::Result<()> {
     let stats = store.stats();
     println!(
         "size of stale entries = {}
@@ -154,5 +313,148 @@ size of all data files = {}",
         stats.total_data_files,
         bytefmt::format(stats.size_of_all_data_files),
     );
+    if !detailed {
+        return Ok(());
+    }
+
+    let key_stats = store.key_stats();
+    for (name, histogram) in &[
+        ("key sizes", &key_stats.key_sizes),
+        ("value sizes", &key_stats.value_sizes),
+    ] {
+        println!(
+            "\n{} (p50 <= {}, p99 <= {}):",
+            name,
+            bytefmt::format(histogram.percentile(50.0)),
+            bytefmt::format(histogram.percentile(99.0)),
+        );
+        for (min, max, count) in histogram.buckets() {
+            println!(
+                "{:>10} - {:<10} {}",
+                bytefmt::format(min),
+                bytefmt::format(max),
+                count
+            );
+        }
+    }
+
+    let mut prefixes = key_stats.prefixes.iter().collect::<Vec<_>>();
+    prefixes.sort_by(|a, b| b.1.cmp(a.1));
+    println!("\nkey prefixes ({} in total):", prefixes.len());
+    for (prefix, count) in prefixes.iter().take(MAX_PREFIXES_DISPLAYED) {
+        let prefix = if prefix.is_empty() {
+            "(none)".into()
+        } else {
+            String::from_utf8_lossy(prefix)
+        };
+        println!("{:>24} {}", prefix, count);
+    }
+    if prefixes.len() > MAX_PREFIXES_DISPLAYED {
+        println!("{:>24}", "...");
+    }
+    Ok(())
+}
+
+fn handle_segments_command(store: &mut Store, sort: &str) -> tinkv::Result<()> {
+    let mut segments = store.segments()?;
+    if sort == "garbage" {
+        segments.sort_by(|a, b| b.garbage_ratio().total_cmp(&a.garbage_ratio()));
+    }
+
+    println!(
+        "{:>12} {:>10} {:>10} {:>10} {:>8} {:>8} {:>5} {:>12}",
+        "id", "size", "live", "stale", "keys", "garbage", "hint", "created"
+    );
+    for segment in segments {
+        let created = segment
+            .created_at
+            .map(|ts| (ts / 1_000_000_000).to_string())
+            .unwrap_or_else(|| "-".to_owned());
+        println!(
+            "{:>12} {:>10} {:>10} {:>10} {:>8} {:>7.1}% {:>5} {:>12}{}",
+            segment.id,
+            bytefmt::format(segment.size),
+            bytefmt::format(segment.size_of_active_entries),
+            bytefmt::format(segment.size_of_stale_entries),
+            segment.total_active_entries,
+            segment.garbage_ratio() * 100.0,
+            if segment.has_hint { "yes" } else { "no" },
+            created,
+            if segment.writeable { " (active)" } else { "" },
+        );
+    }
+    Ok(())
+}
+
+fn handle_dump_segment_command(file: &Path, options: &DumpOptions) -> tinkv::Result<()> {
+    let count = tinkv::dump_segment(file, io::stdout().lock(), options)?;
+    eprintln!("{} records dumped", count);
+    Ok(())
+}
+
+fn handle_backup_command(
+    store: &mut Store,
+    target: &Path,
+    incremental_from: Option<&Path>,
+) -> tinkv::Result<()> {
+    let manifest = match incremental_from {
+        None => store.checkpoint(target)?,
+        Some(base) => store.incremental_backup(target, &tinkv::Manifest::load(base)?)?,
+    };
+    println!(
+        "backup created at '{}', {} files ({} copied), {} files removed since base",
+        target.display(),
+        manifest.files.len(),
+        bytefmt::format(manifest.total_size()),
+        manifest.removed.len(),
+    );
+    Ok(())
+}
+
+fn handle_restore_command(path: &Path, backups: &[PathBuf]) -> tinkv::Result<()> {
+    let manifest = tinkv::restore_backup(path, backups)?;
+    println!(
+        "restored {} files into '{}'",
+        manifest.files.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn handle_export_command(
+    store: &mut Store,
+    options: &ExportOptions,
+    output: Option<&Path>,
+) -> tinkv::Result<()> {
+    let count = match output {
+        None => store.export(io::stdout().lock(), options)?,
+        Some(path) => store.export(BufWriter::new(File::create(path)?), options)?,
+    };
+    eprintln!("{} pairs exported", count);
+    Ok(())
+}
+
+fn handle_import_command(
+    store: &mut Store,
+    options: &ExportOptions,
+    input: Option<&Path>,
+) -> tinkv::Result<()> {
+    let count = match input {
+        None => store.import(io::stdin().lock(), options)?,
+        Some(path) => store.import(File::open(path)?, options)?,
+    };
+    eprintln!("{} pairs imported", count);
+    Ok(())
+}
+
+fn handle_import_rdb_command(store: &mut Store, file: &Path) -> tinkv::Result<()> {
+    let stats = store.import_rdb(File::open(file)?)?;
+    println!(
+        "{} keys imported, {} keys expired, {} keys failed",
+        stats.imported, stats.expired, stats.failed
+    );
+    for (type_name, count) in stats.skipped.iter() {
+        println!("{} keys of unsupported type '{}' skipped", count, type_name);
+    }
     Ok(())
 }
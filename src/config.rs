@@ -1,6 +1,15 @@
-pub const REMOVE_TOMESTONE: &[u8] = b"%TINKV_REMOVE_TOMESTOME%";
 pub const DATA_FILE_SUFFIX: &str = ".tinkv.data";
 pub const HINT_FILE_SUFFIX: &str = ".tinkv.hint";
 pub const DEFAULT_MAX_DATA_FILE_SIZE: u64 = 1024 * 1024 * 10; // 10MB
 pub const DEFAULT_MAX_KEY_SIZE: u64 = 64;
 pub const DEFAULT_MAX_VALUE_SIZE: u64 = 65536;
+pub const DEFAULT_KEYSPACE: &str = "default";
+/// Change events buffered for a subscription before it's cancelled.
+pub const SUBSCRIPTION_BUFFER_SIZE: usize = 4096;
+/// Compaction releases the datastore after copying this many bytes.
+pub const COMPACTION_STEP_SIZE: u64 = 1024 * 1024; // 1MB
+/// Times to retry refreshing a read-only datastore if data files
+/// are removed in the meantime.
+pub const REFRESH_RETRIES: usize = 3;
+pub const DEFAULT_KEY_PREFIX_DELIMITER: u8 = b':';
+pub const MANIFEST_FILE_NAME: &str = "MANIFEST";
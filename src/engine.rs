@@ -0,0 +1,215 @@
+//! Pluggable storage engines behind the server.
+use crate::error::{Result, TinkvError};
+use crate::store::{Stats, Store};
+use std::collections::BTreeMap;
+
+/// A key value storage engine.
+///
+/// The trait covers the basic commands only. Features built on data
+/// files, i.e. replication, group commit, scrubbing, compaction in
+/// steps, background IO rate and INFO of persistence and keyspaces,
+/// need the datastore returned by `as_store`, they are not supported
+/// by other engines.
+pub trait KvEngine: Send + 'static {
+    /// Get value of a key, `None` if key not found.
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Save key value pair.
+    fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()>;
+
+    /// Remove a key, `TinkvError::KeyNotFound` is returned if key not found.
+    fn remove(&mut self, key: &[u8]) -> Result<()>;
+
+    /// Return `true` if the engine contains the given key.
+    fn contains_key(&self, key: &[u8]) -> bool;
+
+    /// Return total number of keys.
+    fn len(&self) -> u64;
+
+    /// Check the engine is empty or not.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return all the keys with the given prefix, in order.
+    fn scan(&self, prefix: &[u8]) -> Vec<Vec<u8>>;
+
+    /// Reclaim space of stale entries.
+    fn compact(&mut self) -> Result<()>;
+
+    /// Return current stats of the engine.
+    fn stats(&self) -> Stats;
+
+    /// Save key value pair only if the key is absent (`present` is
+    /// `false`) or present. Return `true` if the value is saved.
+    fn set_if(&mut self, key: &[u8], value: &[u8], present: bool) -> Result<bool> {
+        if self.contains_key(key) != present {
+            return Ok(false);
+        }
+        self.set(key, value)?;
+        Ok(true)
+    }
+
+    /// Remove all the keys.
+    fn clear(&mut self) -> Result<()> {
+        for key in self.scan(b"") {
+            self.remove(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Return the underlying datastore if the engine is backed by one,
+    /// features of data files (see above) are only supported if it's
+    /// not `None`.
+    fn as_store(&mut self) -> Option<&mut Store> {
+        None
+    }
+}
+
+impl KvEngine for Store {
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Store::get(self, key)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        Store::set(self, key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Result<()> {
+        Store::remove(self, key)
+    }
+
+    fn contains_key(&self, key: &[u8]) -> bool {
+        Store::contains_key(self, key)
+    }
+
+    fn len(&self) -> u64 {
+        Store::len(self)
+    }
+
+    fn scan(&self, prefix: &[u8]) -> Vec<Vec<u8>> {
+        self.keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        Store::compact(self)
+    }
+
+    fn stats(&self) -> Stats {
+        *Store::stats(self)
+    }
+
+    fn set_if(&mut self, key: &[u8], value: &[u8], present: bool) -> Result<bool> {
+        if present {
+            self.set_if_present(key, value)
+        } else {
+            self.set_if_absent(key, value)
+        }
+    }
+
+    fn as_store(&mut self) -> Option<&mut Store> {
+        Some(self)
+    }
+}
+
+/// An engine keeps all key value pairs in memory, nothing is persisted.
+///
+/// It's useful for running server as a pure cache, and for testing.
+/// Only basic commands are supported, see `KvEngine`.
+#[derive(Debug, Default)]
+pub struct MemoryEngine {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KvEngine for MemoryEngine {
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.entries.insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Result<()> {
+        match self.entries.remove(key) {
+            Some(_) => Ok(()),
+            None => Err(TinkvError::KeyNotFound(key.into())),
+        }
+    }
+
+    fn contains_key(&self, key: &[u8]) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    fn len(&self) -> u64 {
+        self.entries.len() as u64
+    }
+
+    fn scan(&self, prefix: &[u8]) -> Vec<Vec<u8>> {
+        self.entries
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            total_active_entries: self.len(),
+            ..Default::default()
+        }
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.entries.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_engine() {
+        let mut engine = MemoryEngine::new();
+        assert!(engine.is_empty());
+
+        engine.set(b"a", b"1").unwrap();
+        engine.set(b"ab", b"2").unwrap();
+        engine.set(b"b", b"3").unwrap();
+        assert_eq!(engine.get(b"a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.len(), 3);
+        assert_eq!(engine.stats().total_active_entries, 3);
+        assert_eq!(engine.scan(b"a"), vec![b"a".to_vec(), b"ab".to_vec()]);
+
+        assert!(!engine.set_if(b"a", b"x", false).unwrap());
+        assert!(engine.set_if(b"c", b"4", false).unwrap());
+        assert!(!engine.set_if(b"d", b"5", true).unwrap());
+
+        engine.remove(b"a").unwrap();
+        assert!(matches!(
+            engine.remove(b"a"),
+            Err(TinkvError::KeyNotFound(_))
+        ));
+        assert_eq!(engine.get(b"a").unwrap(), None);
+        assert!(engine.as_store().is_none());
+
+        engine.clear().unwrap();
+        assert!(engine.is_empty());
+    }
+}
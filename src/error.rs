@@ -21,6 +21,10 @@ pub enum TinkvError {
     Pattern(#[from] glob::PatternError),
     #[error(transparent)]
     Codec(#[from] Box<bincode::ErrorKind>),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
     /// Custom error definitions.
     #[error("crc check failed, data entry (key='{}', file_id={}, offset={}) was corrupted", String::from_utf8_lossy(.key), .file_id, .offset)]
     DataEntryCorrupted {
@@ -30,8 +34,27 @@ pub enum TinkvError {
     },
     #[error("key '{}' not found", String::from_utf8_lossy(.0))]
     KeyNotFound(Vec<u8>),
+    #[error("keyspace '{}' not found", .0)]
+    KeyspaceNotFound(String),
     #[error("file '{}' is not writeable", .0.display())]
     FileNotWriteable(PathBuf),
+    #[error("file '{}' is not a segment file, file id not found in its name", .0.display())]
+    UnknownFile(PathBuf),
+    #[error("segment file '{}' is in format version {}, which is not supported (expected version {}), it may be written by an older version of tinkv", .path.display(), .version, crate::segment::FORMAT_VERSION)]
+    UnsupportedFormat { path: PathBuf, version: u16 },
+    #[error("data file {} not found", .0)]
+    SegmentNotFound(u64),
+    #[error("failed to read file '{}' at offset {}: {}", .path.display(), .offset, .source)]
+    SegmentRead {
+        path: PathBuf,
+        offset: u64,
+        #[source]
+        source: Box<bincode::ErrorKind>,
+    },
+    #[error("datastore is opened in read-only mode")]
+    ReadOnly,
+    #[error("datastore is full and no key can be evicted")]
+    StoreFull,
     #[error("key is too large")]
     KeyIsTooLarge,
     #[error("value is too large")]
@@ -1,12 +1,33 @@
 //! A simple key-value storage.
+#[cfg(feature = "async")]
+mod async_store;
+mod backup;
 pub mod config;
+mod engine;
 mod error;
+mod rdb;
+mod replication;
 mod resp;
 mod segment;
 mod server;
 mod store;
+mod typed;
 pub mod util;
+mod vfs;
 
+#[cfg(feature = "async")]
+pub use async_store::{AsyncStore, RangeStream};
+pub use backup::{restore_backup, Manifest, ManifestFile};
+pub use engine::{KvEngine, MemoryEngine};
 pub use error::{Result, TinkvError};
+pub use segment::{dump_segment, DumpOptions};
 pub use server::Server;
-pub use store::{OpenOptions, Store};
+pub use store::{
+    AddOperator, AppendOperator, ChangeEvent, ChangeKind, CommitHandle, CompareAndSwapError,
+    CorruptedRecord, Encoding, EntryMeta, EvictionPolicy, ExportOptions, Format, KeyStats,
+    Keyspace, KeyspaceOptions, KeyspaceStats, MergeOperator, OpenOptions, RateLimiter,
+    RdbImportStats, ScrubReport, SegmentScrub, SegmentStats, SizeHistogram, Stats, Store,
+    Subscription, SyncPolicy, Version,
+};
+pub use typed::{BincodeCodec, Codec, JsonCodec, OrderedCodec, Range, TypedStore};
+pub use vfs::{DiskFs, FaultFs, MemFs, Vfs, VfsFile};
@@ -0,0 +1,633 @@
+//! A minimal parser of Redis RDB dump files.
+//!
+//! Only string values are decoded, values of other types (including
+//! streams and module values) are skipped.
+//! Ref: https://rdb.fnordig.de/file_format.html
+use crate::error::{Result, TinkvError};
+use std::io::Read;
+
+const OPCODE_SLOT_INFO: u8 = 0xF4;
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_MODULE_AUX: u8 = 0xF7;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_MODULE: u8 = 6;
+const TYPE_MODULE_2: u8 = 7;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+const MODULE_OPCODE_EOF: u64 = 0;
+const MODULE_OPCODE_SINT: u64 = 1;
+const MODULE_OPCODE_UINT: u64 = 2;
+const MODULE_OPCODE_FLOAT: u64 = 3;
+const MODULE_OPCODE_DOUBLE: u64 = 4;
+const MODULE_OPCODE_STRING: u64 = 5;
+
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+/// A key value pair (or a skipped key) read from RDB file.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Item {
+    /// A string key value pair.
+    String {
+        db: u64,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        /// expiration time in milliseconds since unix epoch.
+        expires_at: Option<u64>,
+    },
+    /// A key with a value type not supported by tinkv.
+    Skipped { db: u64, type_name: &'static str },
+}
+
+/// Read items of RDB file one by one.
+pub(crate) struct Parser<R> {
+    reader: R,
+    db: u64,
+    done: bool,
+}
+
+impl<R: Read> Parser<R> {
+    /// Create a new parser, RDB header is checked at first.
+    pub(crate) fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 9];
+        reader.read_exact(&mut header)?;
+        if &header[..5] != b"REDIS" {
+            return Err(invalid("magic string 'REDIS' not found"));
+        }
+
+        Ok(Self {
+            reader,
+            db: 0,
+            done: false,
+        })
+    }
+
+    fn next_item(&mut self) -> Result<Option<Item>> {
+        let mut expires_at = None;
+        loop {
+            let opcode = self.read_u8()?;
+            match opcode {
+                OPCODE_EOF => return Ok(None),
+                OPCODE_SELECTDB => self.db = self.read_length()?,
+                OPCODE_RESIZEDB => {
+                    self.read_length()?;
+                    self.read_length()?;
+                }
+                OPCODE_AUX => {
+                    self.read_string()?;
+                    self.read_string()?;
+                }
+                OPCODE_EXPIRETIME_MS => {
+                    let mut buf = [0u8; 8];
+                    self.reader.read_exact(&mut buf)?;
+                    expires_at = Some(u64::from_le_bytes(buf));
+                }
+                OPCODE_EXPIRETIME => {
+                    let mut buf = [0u8; 4];
+                    self.reader.read_exact(&mut buf)?;
+                    expires_at = Some(u64::from(u32::from_le_bytes(buf)) * 1000);
+                }
+                OPCODE_IDLE => {
+                    self.read_length()?;
+                }
+                OPCODE_FREQ => {
+                    self.read_u8()?;
+                }
+                OPCODE_SLOT_INFO => {
+                    self.read_length()?;
+                    self.read_length()?;
+                    self.read_length()?;
+                }
+                OPCODE_FUNCTION2 => {
+                    self.read_string()?;
+                }
+                OPCODE_MODULE_AUX => {
+                    // module id, `when` opcode and `when`.
+                    self.read_length()?;
+                    self.read_length()?;
+                    self.read_length()?;
+                    self.skip_module_value()?;
+                }
+                value_type => {
+                    let key = self.read_string()?;
+                    if value_type == TYPE_STRING {
+                        let value = self.read_string()?;
+                        return Ok(Some(Item::String {
+                            db: self.db,
+                            key,
+                            value,
+                            expires_at,
+                        }));
+                    }
+
+                    let type_name = self.skip_value(value_type)?;
+                    return Ok(Some(Item::Skipped {
+                        db: self.db,
+                        type_name,
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Skip value of the given type, return name of the type.
+    fn skip_value(&mut self, value_type: u8) -> Result<&'static str> {
+        match value_type {
+            TYPE_LIST | TYPE_SET => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                }
+            }
+            TYPE_ZSET => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                    // scores are saved as strings with a length byte.
+                    let len = self.read_u8()?;
+                    if len < 253 {
+                        self.skip_bytes(u64::from(len))?;
+                    }
+                }
+            }
+            TYPE_ZSET_2 => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                    self.skip_bytes(8)?;
+                }
+            }
+            TYPE_HASH => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                    self.read_string()?;
+                }
+            }
+            TYPE_LIST_QUICKLIST => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                }
+            }
+            TYPE_LIST_QUICKLIST_2 => {
+                for _ in 0..self.read_length()? {
+                    self.read_length()?;
+                    self.read_string()?;
+                }
+            }
+            TYPE_HASH_ZIPMAP | TYPE_LIST_ZIPLIST | TYPE_SET_INTSET | TYPE_ZSET_ZIPLIST
+            | TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK | TYPE_ZSET_LISTPACK | TYPE_SET_LISTPACK => {
+                self.read_string()?;
+            }
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                self.skip_stream(value_type)?;
+            }
+            TYPE_MODULE_2 => {
+                // module id.
+                self.read_length()?;
+                self.skip_module_value()?;
+            }
+            _ => {
+                return Err(invalid(&format!(
+                    "value type {} cannot be skipped",
+                    value_type
+                )))
+            }
+        }
+
+        Ok(type_name(value_type))
+    }
+
+    /// Skip a stream value, the layout depends on the stream type version.
+    fn skip_stream(&mut self, value_type: u8) -> Result<()> {
+        // listpacks, each with its master entry id.
+        for _ in 0..self.read_length()? {
+            self.read_string()?;
+            self.read_string()?;
+        }
+        // number of entries and last entry id.
+        self.skip_lengths(3)?;
+        if value_type >= TYPE_STREAM_LISTPACKS_2 {
+            // first entry id, max deleted entry id and entries added.
+            self.skip_lengths(5)?;
+        }
+
+        // consumer groups.
+        for _ in 0..self.read_length()? {
+            self.read_string()?;
+            // last delivered id.
+            self.skip_lengths(2)?;
+            if value_type >= TYPE_STREAM_LISTPACKS_2 {
+                // entries read.
+                self.read_length()?;
+            }
+            // pending entries: raw id, delivery time and delivery count.
+            for _ in 0..self.read_length()? {
+                self.skip_bytes(16 + 8)?;
+                self.read_length()?;
+            }
+            // consumers.
+            for _ in 0..self.read_length()? {
+                self.read_string()?;
+                // seen time, and active time since version 3.
+                if value_type >= TYPE_STREAM_LISTPACKS_3 {
+                    self.skip_bytes(16)?;
+                } else {
+                    self.skip_bytes(8)?;
+                }
+                // pending entry ids.
+                let pending = self.read_length()?;
+                self.skip_bytes(pending.saturating_mul(16))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip opcode tagged values saved by a module, until the EOF opcode.
+    fn skip_module_value(&mut self) -> Result<()> {
+        loop {
+            match self.read_length()? {
+                MODULE_OPCODE_EOF => return Ok(()),
+                MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => {
+                    self.read_length()?;
+                }
+                MODULE_OPCODE_FLOAT => self.skip_bytes(4)?,
+                MODULE_OPCODE_DOUBLE => self.skip_bytes(8)?,
+                MODULE_OPCODE_STRING => {
+                    self.read_string()?;
+                }
+                opcode => {
+                    return Err(invalid(&format!("unknown module opcode {}", opcode)));
+                }
+            }
+        }
+    }
+
+    fn skip_lengths(&mut self, n: usize) -> Result<()> {
+        for _ in 0..n {
+            self.read_length()?;
+        }
+        Ok(())
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.reader.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn skip_bytes(&mut self, n: u64) -> Result<()> {
+        let skipped = std::io::copy(&mut (&mut self.reader).take(n), &mut std::io::sink())?;
+        if skipped != n {
+            return Err(invalid("unexpected end of file"));
+        }
+        Ok(())
+    }
+
+    /// Read exactly `n` bytes. The buffer grows with the bytes actually read,
+    /// so a corrupted length can not make us allocate a huge buffer upfront.
+    fn read_bytes(&mut self, n: u64) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        (&mut self.reader).take(n).read_to_end(&mut buf)?;
+        if buf.len() as u64 != n {
+            return Err(invalid("unexpected end of file"));
+        }
+        Ok(buf)
+    }
+
+    /// Read a length, return `Err` if it's a special encoded string.
+    fn read_length(&mut self) -> Result<u64> {
+        match self.read_length_or_encoding()? {
+            (len, false) => Ok(len),
+            (_, true) => Err(invalid("unexpected string encoding")),
+        }
+    }
+
+    /// Return the length (or the encoding type), and a flag indicates
+    /// it's an encoding type or not.
+    fn read_length_or_encoding(&mut self) -> Result<(u64, bool)> {
+        let first = self.read_u8()?;
+        match first >> 6 {
+            0 => Ok((u64::from(first & 0x3F), false)),
+            1 => {
+                let next = self.read_u8()?;
+                Ok(((u64::from(first & 0x3F) << 8) | u64::from(next), false))
+            }
+            2 => match first {
+                0x80 => {
+                    let mut buf = [0u8; 4];
+                    self.reader.read_exact(&mut buf)?;
+                    Ok((u64::from(u32::from_be_bytes(buf)), false))
+                }
+                0x81 => {
+                    let mut buf = [0u8; 8];
+                    self.reader.read_exact(&mut buf)?;
+                    Ok((u64::from_be_bytes(buf), false))
+                }
+                _ => Err(invalid("invalid length encoding")),
+            },
+            _ => Ok((u64::from(first & 0x3F), true)),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Vec<u8>> {
+        let (len, encoded) = self.read_length_or_encoding()?;
+        if !encoded {
+            return self.read_bytes(len);
+        }
+
+        match len as u8 {
+            ENC_INT8 => Ok((self.read_u8()? as i8).to_string().into_bytes()),
+            ENC_INT16 => {
+                let mut buf = [0u8; 2];
+                self.reader.read_exact(&mut buf)?;
+                Ok(i16::from_le_bytes(buf).to_string().into_bytes())
+            }
+            ENC_INT32 => {
+                let mut buf = [0u8; 4];
+                self.reader.read_exact(&mut buf)?;
+                Ok(i32::from_le_bytes(buf).to_string().into_bytes())
+            }
+            ENC_LZF => {
+                let compressed_len = self.read_length()?;
+                let len = self.read_length()?;
+                let compressed = self.read_bytes(compressed_len)?;
+                lzf_decompress(&compressed, len as usize)
+            }
+            _ => Err(invalid("unknown string encoding")),
+        }
+    }
+}
+
+impl<R: Read> Iterator for Parser<R> {
+    type Item = Result<Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let item = self.next_item().transpose();
+        if !matches!(item, Some(Ok(_))) {
+            self.done = true;
+        }
+        item
+    }
+}
+
+fn type_name(value_type: u8) -> &'static str {
+    match value_type {
+        TYPE_LIST | TYPE_LIST_ZIPLIST | TYPE_LIST_QUICKLIST | TYPE_LIST_QUICKLIST_2 => "list",
+        TYPE_SET | TYPE_SET_INTSET | TYPE_SET_LISTPACK => "set",
+        TYPE_ZSET | TYPE_ZSET_2 | TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => "zset",
+        TYPE_HASH | TYPE_HASH_ZIPMAP | TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK => "hash",
+        TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => "stream",
+        TYPE_MODULE | TYPE_MODULE_2 => "module",
+        _ => "unknown",
+    }
+}
+
+fn invalid(msg: &str) -> TinkvError {
+    TinkvError::Custom(format!("invalid rdb file: {}", msg))
+}
+
+/// Decompress LZF compressed data.
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>> {
+    // `len` comes from the file, don't trust it for preallocation.
+    let mut output = Vec::with_capacity(len.min(input.len().saturating_mul(2)));
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+
+        if ctrl < 32 {
+            // literal run of `ctrl + 1` bytes.
+            let end = i + ctrl + 1;
+            if end > input.len() {
+                return Err(invalid("corrupted lzf data"));
+            }
+            if output.len() + ctrl + 1 > len {
+                return Err(invalid("lzf decompressed length mismatch"));
+            }
+            output.extend_from_slice(&input[i..end]);
+            i = end;
+        } else {
+            // back reference.
+            let mut ref_len = ctrl >> 5;
+            if ref_len == 7 {
+                ref_len += *input.get(i).ok_or_else(|| invalid("corrupted lzf data"))? as usize;
+                i += 1;
+            }
+            let low = *input.get(i).ok_or_else(|| invalid("corrupted lzf data"))? as usize;
+            i += 1;
+
+            let back = ((ctrl & 0x1F) << 8) + low + 1;
+            if back > output.len() {
+                return Err(invalid("corrupted lzf data"));
+            }
+            let start = output.len() - back;
+            if output.len() + ref_len + 2 > len {
+                return Err(invalid("lzf decompressed length mismatch"));
+            }
+            for k in 0..ref_len + 2 {
+                let b = output[start + k];
+                output.push(b);
+            }
+        }
+    }
+
+    if output.len() != len {
+        return Err(invalid("lzf decompressed length mismatch"));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rdb(body: &[u8]) -> Vec<u8> {
+        let mut data = b"REDIS0009".to_vec();
+        data.extend_from_slice(body);
+        data.push(OPCODE_EOF);
+        data.extend_from_slice(&[0u8; 8]);
+        data
+    }
+
+    #[test]
+    fn test_lzf_decompress() {
+        let compressed = [0x00, b'a', 0xE0, 0x00, 0x00];
+        assert_eq!(lzf_decompress(&compressed, 10).unwrap(), b"aaaaaaaaaa");
+        assert!(lzf_decompress(&compressed, 9).is_err());
+        assert!(lzf_decompress(&[0x20, 0x00], 3).is_err());
+    }
+
+    #[test]
+    fn test_parse_rdb() {
+        let mut body = vec![];
+        // aux field.
+        body.extend_from_slice(&[OPCODE_AUX, 3, b'v', b'e', b'r', 3, b'6', b'.', b'0']);
+        body.extend_from_slice(&[OPCODE_SELECTDB, 0, OPCODE_RESIZEDB, 3, 1]);
+        // plain string.
+        body.extend_from_slice(&[TYPE_STRING, 1, b'a', 2, b'h', b'i']);
+        // integer encoded string with expiration time.
+        body.push(OPCODE_EXPIRETIME_MS);
+        body.extend_from_slice(&1_600_000_000_000u64.to_le_bytes());
+        body.extend_from_slice(&[TYPE_STRING, 1, b'b', 0xC1, 0x39, 0x30]);
+        // a list to be skipped.
+        body.extend_from_slice(&[TYPE_LIST, 1, b'l', 2, 1, b'x', 1, b'y']);
+        // lzf compressed string in another db.
+        body.extend_from_slice(&[OPCODE_SELECTDB, 2]);
+        body.extend_from_slice(&[
+            TYPE_STRING,
+            1,
+            b'c',
+            0xC3,
+            5,
+            10,
+            0x00,
+            b'a',
+            0xE0,
+            0x00,
+            0x00,
+        ]);
+
+        let items = Parser::new(rdb(&body).as_slice())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            items,
+            vec![
+                Item::String {
+                    db: 0,
+                    key: b"a".to_vec(),
+                    value: b"hi".to_vec(),
+                    expires_at: None,
+                },
+                Item::String {
+                    db: 0,
+                    key: b"b".to_vec(),
+                    value: b"12345".to_vec(),
+                    expires_at: Some(1_600_000_000_000),
+                },
+                Item::Skipped {
+                    db: 0,
+                    type_name: "list",
+                },
+                Item::String {
+                    db: 2,
+                    key: b"c".to_vec(),
+                    value: b"aaaaaaaaaa".to_vec(),
+                    expires_at: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_rdb() {
+        assert!(Parser::new(&b"RADIS0009"[..]).is_err());
+
+        // legacy module types cannot be skipped.
+        let data = rdb(&[TYPE_MODULE, 1, b'm']);
+        let mut parser = Parser::new(data.as_slice()).unwrap();
+        assert!(parser.next().unwrap().is_err());
+        assert!(parser.next().is_none());
+
+        // huge lengths are not trusted.
+        let mut body = vec![TYPE_STRING, 1, b'a', 0x81];
+        body.extend_from_slice(&u64::MAX.to_be_bytes());
+        let data = rdb(&body);
+        assert!(Parser::new(data.as_slice())
+            .unwrap()
+            .next()
+            .unwrap()
+            .is_err());
+
+        let mut body = vec![TYPE_STRING, 1, b'c', 0xC3, 5, 0x81];
+        body.extend_from_slice(&u64::MAX.to_be_bytes());
+        body.extend_from_slice(&[0x00, b'a', 0xE0, 0x00, 0x00]);
+        let data = rdb(&body);
+        assert!(Parser::new(data.as_slice())
+            .unwrap()
+            .next()
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn test_skip_streams_and_modules() {
+        let mut body = vec![];
+        // module aux data.
+        body.extend_from_slice(&[OPCODE_MODULE_AUX, 5, 2, 2, 2, 9, 0]);
+        // an empty stream.
+        body.extend_from_slice(&[TYPE_STREAM_LISTPACKS, 1, b's', 0, 0, 0, 0, 0]);
+        // a stream with a listpack and a consumer group.
+        body.extend_from_slice(&[TYPE_STREAM_LISTPACKS_3, 1, b't', 1, 1, b'k', 2, 1, 2]);
+        body.extend_from_slice(&[3, 1, 1, 1, 0, 1, 0, 3]);
+        body.extend_from_slice(&[1, 1, b'g', 1, 0, 3, 1]);
+        body.extend_from_slice(&[0u8; 24]);
+        body.extend_from_slice(&[1, 1, 1, b'c']);
+        body.extend_from_slice(&[0u8; 16]);
+        body.push(1);
+        body.extend_from_slice(&[0u8; 16]);
+        // a module value.
+        body.extend_from_slice(&[TYPE_MODULE_2, 1, b'm', 5, 2, 7, 5, 1, b'x', 4]);
+        body.extend_from_slice(&1.5f64.to_le_bytes());
+        body.extend_from_slice(&[3, 0, 0, 0, 0, 0]);
+        body.extend_from_slice(&[TYPE_STRING, 1, b'a', 2, b'h', b'i']);
+
+        let items = Parser::new(rdb(&body).as_slice())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            items,
+            vec![
+                Item::Skipped {
+                    db: 0,
+                    type_name: "stream",
+                },
+                Item::Skipped {
+                    db: 0,
+                    type_name: "stream",
+                },
+                Item::Skipped {
+                    db: 0,
+                    type_name: "module",
+                },
+                Item::String {
+                    db: 0,
+                    key: b"a".to_vec(),
+                    value: b"hi".to_vec(),
+                    expires_at: None,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,504 @@
+//! Primary/replica replication over TCP.
+//!
+//! A replica connects to its primary and sends `SYNC [offset]`. The primary
+//! tells which merge operator it uses, replication stops if the replica
+//! doesn't use the same one, since merge operands can't be applied without
+//! it. Then the primary replays changes after the offset if they are still
+//! retained in its data files, or sends a full snapshot (segment files of a
+//! checkpoint) otherwise, then keeps tailing new writes of its datastore as
+//! change events. Replication offset is the sequence number of the last
+//! change applied from primary.
+use crate::config;
+use crate::engine::KvEngine;
+use crate::error::{Result, TinkvError};
+use crate::resp::{deserialize_from_reader, serialize_to_writer, Value};
+use crate::server::not_supported;
+use crate::store::{ChangeEvent, ChangeKind, Store};
+use crate::util::{current_millis, to_utf8_string};
+use log::{debug, error, info};
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Directory (in datastore directory) to receive snapshot from primary.
+const SNAPSHOT_DIR_NAME: &str = "replication-snapshot";
+/// Primary sends a heartbeat if there are no writes within this interval.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// Replica reconnects if nothing is received from primary within this timeout.
+const PRIMARY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Replica waits for a while before reconnecting to primary.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+/// Snapshot files are sent in chunks of this size.
+const FILE_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Replication state of a server.
+#[derive(Debug, Default)]
+pub(crate) struct ReplicationState {
+    /// address of primary (host, port), `None` if the server is a primary.
+    pub primary: Option<(String, u16)>,
+    /// `true` if replica is connected to primary and snapshot is loaded.
+    pub link_up: bool,
+    /// sequence number of the last change applied from primary.
+    pub offset: u64,
+    /// total replicas connected to this server.
+    pub connected_replicas: u64,
+    /// error stopped replication, it's not retried until the next `REPLICAOF`.
+    pub error: Option<String>,
+    // bumped on each `REPLICAOF`, replication threads of
+    // previous epochs exit on seeing a newer one.
+    epoch: u64,
+}
+
+impl ReplicationState {
+    /// Turn into a replica of the given primary, and start replicating
+    /// in a background thread.
+    pub(crate) fn replicate_from<E: KvEngine>(
+        state: &Arc<Mutex<ReplicationState>>,
+        store: &Arc<Mutex<E>>,
+        host: &str,
+        port: u16,
+    ) {
+        let epoch = {
+            let mut state = state.lock().unwrap();
+            state.epoch += 1;
+            state.primary = Some((host.to_owned(), port));
+            state.link_up = false;
+            state.offset = 0;
+            state.error = None;
+            state.epoch
+        };
+
+        let state = state.clone();
+        let store = store.clone();
+        let addr = format!("{}:{}", host, port);
+        thread::spawn(move || loop {
+            if state.lock().unwrap().epoch != epoch {
+                break;
+            }
+
+            info!("replicate from primary {}", &addr);
+            if let Err(e) = replicate(&store, &state, epoch, &addr) {
+                error!("replication from primary {} broken: {}", &addr, e);
+            }
+
+            let mut state = state.lock().unwrap();
+            if state.epoch != epoch {
+                break;
+            }
+            state.link_up = false;
+            if state.error.is_some() {
+                break;
+            }
+            drop(state);
+
+            thread::sleep(RECONNECT_INTERVAL);
+        });
+    }
+
+    /// Stop replicating and turn into a primary, data is kept.
+    pub(crate) fn stop(&mut self) {
+        self.epoch += 1;
+        self.primary = None;
+        self.link_up = false;
+        self.offset = 0;
+        self.error = None;
+    }
+}
+
+/// Receive snapshot and change events from primary, until connection
+/// is broken or replication is stopped.
+fn replicate<E: KvEngine>(
+    store: &Mutex<E>,
+    state: &Mutex<ReplicationState>,
+    epoch: u64,
+    addr: &str,
+) -> Result<()> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(PRIMARY_TIMEOUT))?;
+
+    // resume from the last change applied on reconnecting.
+    let offset = state.lock().unwrap().offset;
+    let mut argv = vec![Value::new_bulk_string(b"SYNC".to_vec())];
+    if offset > 0 {
+        argv.push(Value::new_bulk_string(offset.to_string().into_bytes()));
+    }
+    let mut writer = BufWriter::new(&stream);
+    serialize_to_writer(&mut writer, &Value::new_array(argv))?;
+    writer.flush()?;
+
+    let snapshot_dir = with_store(store, |store| Ok(store.path().join(SNAPSHOT_DIR_NAME)))?;
+    if snapshot_dir.exists() {
+        fs::remove_dir_all(&snapshot_dir)?;
+    }
+    fs::create_dir_all(&snapshot_dir)?;
+
+    for value in deserialize_from_reader(BufReader::new(&stream)) {
+        if state.lock().unwrap().epoch != epoch {
+            return Ok(());
+        }
+
+        let value = value?;
+        let argv = value
+            .as_array()
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|v| v.as_bulk_string())
+                    .collect::<Vec<_>>()
+            })
+            .ok_or_else(|| invalid_message(&value))?;
+
+        match argv.first().cloned().flatten() {
+            Some(b"merge-operator") => {
+                let expected = argv.get(1).cloned().flatten().map(to_utf8_string);
+                let actual = with_store(store, |store| {
+                    Ok(store.merge_operator_name().map(|name| name.to_owned()))
+                })?;
+                if let Some(expected) = expected.filter(|name| Some(name) != actual.as_ref()) {
+                    let msg = format!(
+                        "primary uses merge operator '{}', but replica uses {}",
+                        expected,
+                        actual
+                            .map(|name| format!("'{}'", name))
+                            .unwrap_or_else(|| "none".to_owned())
+                    );
+                    state.lock().unwrap().error = Some(msg.clone());
+                    return Err(TinkvError::Custom(msg));
+                }
+            }
+            Some(b"file") => {
+                let (name, content) = match argv.as_slice() {
+                    [_, Some(name), Some(content)] => (to_utf8_string(name), content),
+                    _ => return Err(invalid_message(&value)),
+                };
+                if !is_segment_file_name(&name) {
+                    return Err(invalid_message(&value));
+                }
+                debug!(
+                    "receive {} bytes of snapshot file {} from primary",
+                    content.len(),
+                    &name
+                );
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(snapshot_dir.join(&name))?
+                    .write_all(content)?;
+            }
+            Some(b"snapshot") => {
+                let seq = integer_at(&value, 1)?;
+                with_store(store, |store| store.replace_with(&snapshot_dir))?;
+                fs::remove_dir_all(&snapshot_dir)?;
+
+                let mut state = state.lock().unwrap();
+                state.link_up = true;
+                state.offset = seq;
+                info!("snapshot loaded from primary {}, offset {}", addr, seq);
+            }
+            Some(b"continue") => {
+                let seq = integer_at(&value, 1)?;
+                let mut state = state.lock().unwrap();
+                state.link_up = true;
+                state.offset = seq;
+                info!("resume replication from primary {}, offset {}", addr, seq);
+            }
+            Some(b"put") | Some(b"merge") | Some(b"del") | Some(b"drop") => {
+                let event = decode_event(&value)?;
+                let (commit, seq) = with_store(store, |store| {
+                    store.apply(&event)?;
+                    Ok((store.commit_handle(), store.last_seq()))
+                })?;
+                commit.wait(seq)?;
+                state.lock().unwrap().offset = event.seq;
+            }
+            Some(b"ping") => {}
+            _ => return Err(invalid_message(&value)),
+        }
+    }
+
+    Err(TinkvError::Custom(
+        "connection closed by primary".to_owned(),
+    ))
+}
+
+/// Send changes after `offset` (or a snapshot) and the following change
+/// events to a replica, until the replica is disconnected.
+pub(crate) fn serve_replica<E: KvEngine, W: Write>(
+    store: &Mutex<E>,
+    state: &Mutex<ReplicationState>,
+    name: &str,
+    offset: Option<u64>,
+    mut writer: W,
+) -> Result<()> {
+    // checkpoint (or replay) and subscription are created atomically,
+    // so that no changes are lost or sent twice.
+    let (snapshot, subscription, seq, merge_operator) = with_store(store, |store| {
+        let merge_operator = store.merge_operator_name().map(|name| name.to_owned());
+        if let Some(offset) = offset.filter(|&offset| store.can_replay_from(offset)) {
+            let subscription = store.subscribe_from(offset)?;
+            return Ok((None, subscription, offset, merge_operator));
+        }
+
+        let dir = store
+            .path()
+            .join(format!("sync-{}-{}", current_millis(), name));
+        let manifest = store.checkpoint(&dir)?;
+        Ok((
+            Some((dir, manifest)),
+            store.subscribe(),
+            store.last_seq(),
+            merge_operator,
+        ))
+    })?;
+
+    match snapshot.as_ref() {
+        Some((_, manifest)) => info!(
+            "full sync to replica {}, {} files, offset {}",
+            name,
+            manifest.files.len(),
+            seq
+        ),
+        None => info!("partial sync to replica {} from offset {}", name, seq),
+    }
+    state.lock().unwrap().connected_replicas += 1;
+
+    let mut send_all = || -> Result<()> {
+        let value = Value::new_array(vec![
+            Value::new_bulk_string(b"merge-operator".to_vec()),
+            merge_operator
+                .as_ref()
+                .map(|name| Value::new_bulk_string(name.as_bytes().to_vec()))
+                .unwrap_or_else(Value::new_null_bulk_string),
+        ]);
+        serialize_to_writer(&mut writer, &value)?;
+
+        let value = match snapshot.as_ref() {
+            Some((dir, manifest)) => {
+                for file in manifest.files.iter() {
+                    send_file(&mut writer, &dir.join(&file.name), &file.name)?;
+                }
+                fs::remove_dir_all(dir)?;
+                Value::new_array(vec![
+                    Value::new_bulk_string(b"snapshot".to_vec()),
+                    Value::new_integer(seq as i64),
+                ])
+            }
+            None => Value::new_array(vec![
+                Value::new_bulk_string(b"continue".to_vec()),
+                Value::new_integer(seq as i64),
+            ]),
+        };
+        serialize_to_writer(&mut writer, &value)?;
+        writer.flush()?;
+
+        loop {
+            let value = match subscription.recv_timeout(HEARTBEAT_INTERVAL) {
+                Some(event) => encode_event(&event),
+                None if subscription.is_cancelled() => {
+                    return Err(TinkvError::Custom(format!(
+                        "replica {} falls behind, its change events are dropped",
+                        name
+                    )));
+                }
+                None => Value::new_array(vec![Value::new_bulk_string(b"ping".to_vec())]),
+            };
+            serialize_to_writer(&mut writer, &value)?;
+            writer.flush()?;
+        }
+    };
+    let r = send_all();
+
+    state.lock().unwrap().connected_replicas -= 1;
+    if let Some((dir, _)) = snapshot.filter(|(dir, _)| dir.exists()) {
+        let _ = fs::remove_dir_all(dir);
+    }
+    info!("replica {} disconnected", name);
+
+    r
+}
+
+/// Send a snapshot file in chunks, the replica appends them in order.
+fn send_file<W: Write>(writer: &mut W, path: &Path, name: &str) -> Result<()> {
+    let mut file = fs::File::open(path)?;
+    loop {
+        let mut chunk = vec![];
+        io::copy(&mut (&mut file).take(FILE_CHUNK_SIZE), &mut chunk)?;
+        let is_last = (chunk.len() as u64) < FILE_CHUNK_SIZE;
+        let value = Value::new_array(vec![
+            Value::new_bulk_string(b"file".to_vec()),
+            Value::new_bulk_string(name.as_bytes().to_vec()),
+            Value::new_bulk_string(chunk),
+        ]);
+        serialize_to_writer(writer, &value)?;
+        if is_last {
+            return Ok(());
+        }
+    }
+}
+
+/// Call `f` with the datastore backing the storage engine.
+fn with_store<E, F, T>(engine: &Mutex<E>, f: F) -> Result<T>
+where
+    E: KvEngine,
+    F: FnOnce(&mut Store) -> Result<T>,
+{
+    let mut engine = engine.lock().unwrap();
+    let store = engine
+        .as_store()
+        .ok_or_else(|| not_supported("replication"))?;
+    f(store)
+}
+
+fn encode_event(event: &ChangeEvent) -> Value {
+    let mut values = vec![
+        Value::new_bulk_string(vec![]),
+        Value::new_integer(event.seq as i64),
+        Value::new_bulk_string(event.keyspace.as_bytes().to_vec()),
+        Value::new_bulk_string(event.key.clone()),
+    ];
+
+    match &event.kind {
+        ChangeKind::Put { value, expires_at } => {
+            values[0] = Value::new_bulk_string(b"put".to_vec());
+            values.push(Value::new_bulk_string(value.clone()));
+            values.push(
+                expires_at
+                    .map(|t| Value::new_integer(t as i64))
+                    .unwrap_or_else(Value::new_null_bulk_string),
+            );
+        }
+        ChangeKind::Merge { operand } => {
+            values[0] = Value::new_bulk_string(b"merge".to_vec());
+            values.push(Value::new_bulk_string(operand.clone()));
+        }
+        ChangeKind::Delete => values[0] = Value::new_bulk_string(b"del".to_vec()),
+        ChangeKind::DropKeyspace => values[0] = Value::new_bulk_string(b"drop".to_vec()),
+    }
+
+    Value::new_array(values)
+}
+
+fn decode_event(value: &Value) -> Result<ChangeEvent> {
+    let values = value.as_array().ok_or_else(|| invalid_message(value))?;
+    let bulk_string_at = |i: usize| {
+        values
+            .get(i)
+            .and_then(|v| v.as_bulk_string())
+            .ok_or_else(|| invalid_message(value))
+    };
+
+    let kind = match bulk_string_at(0)? {
+        b"put" => ChangeKind::Put {
+            value: bulk_string_at(4)?.to_vec(),
+            expires_at: values.get(5).and_then(|v| v.as_integer()).map(|t| t as u64),
+        },
+        b"merge" => ChangeKind::Merge {
+            operand: bulk_string_at(4)?.to_vec(),
+        },
+        b"del" => ChangeKind::Delete,
+        b"drop" => ChangeKind::DropKeyspace,
+        _ => return Err(invalid_message(value)),
+    };
+
+    Ok(ChangeEvent {
+        seq: integer_at(value, 1)?,
+        keyspace: to_utf8_string(bulk_string_at(2)?),
+        key: bulk_string_at(3)?.to_vec(),
+        kind,
+    })
+}
+
+fn integer_at(value: &Value, i: usize) -> Result<u64> {
+    value
+        .as_array()
+        .and_then(|values| values.get(i))
+        .and_then(|v| v.as_integer())
+        .map(|v| v as u64)
+        .ok_or_else(|| invalid_message(value))
+}
+
+fn is_segment_file_name(name: &str) -> bool {
+    !name.contains('/')
+        && !name.contains('\\')
+        && (name.ends_with(config::DATA_FILE_SUFFIX) || name.ends_with(config::HINT_FILE_SUFFIX))
+}
+
+fn invalid_message(value: &Value) -> TinkvError {
+    TinkvError::Custom(format!("invalid replication message: {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_and_decode_event() {
+        let events = vec![
+            ChangeEvent {
+                seq: 1,
+                keyspace: "default".to_owned(),
+                key: b"a".to_vec(),
+                kind: ChangeKind::Put {
+                    value: b"1".to_vec(),
+                    expires_at: Some(1592475604853),
+                },
+            },
+            ChangeEvent {
+                seq: 2,
+                keyspace: "default".to_owned(),
+                key: b"a".to_vec(),
+                kind: ChangeKind::Merge {
+                    operand: b"2".to_vec(),
+                },
+            },
+            ChangeEvent {
+                seq: 3,
+                keyspace: "users".to_owned(),
+                key: b"a".to_vec(),
+                kind: ChangeKind::Delete,
+            },
+            ChangeEvent {
+                seq: 4,
+                keyspace: "users".to_owned(),
+                key: vec![],
+                kind: ChangeKind::DropKeyspace,
+            },
+        ];
+
+        for event in events {
+            assert_eq!(decode_event(&encode_event(&event)).unwrap(), event);
+        }
+        assert!(decode_event(&Value::new_integer(1)).is_err());
+    }
+
+    #[test]
+    fn test_send_file_in_chunks() {
+        let tmpdir = tempfile::TempDir::new().unwrap();
+        let path = tmpdir.path().join("000000000001.tinkv.data");
+        let content = (0..FILE_CHUNK_SIZE * 2 + 10)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        fs::write(&path, &content).unwrap();
+
+        let mut buf = vec![];
+        send_file(&mut buf, &path, "000000000001.tinkv.data").unwrap();
+        let chunks = deserialize_from_reader(BufReader::new(&buf[..]))
+            .map(|value| value.unwrap().as_array().unwrap()[2].clone())
+            .map(|value| value.as_bulk_string().unwrap().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks.concat(), content);
+    }
+
+    #[test]
+    fn test_is_segment_file_name() {
+        assert!(is_segment_file_name("000000000001.tinkv.data"));
+        assert!(is_segment_file_name("000000000001.tinkv.hint"));
+        assert!(!is_segment_file_name("../000000000001.tinkv.data"));
+        assert!(!is_segment_file_name("MANIFEST"));
+    }
+}
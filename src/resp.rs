@@ -50,7 +50,7 @@ impl<'s> Error<'s> {
 
 /// RESP value types. In RESP, different parts
 /// of the protocol are always terminated with `\r\n`.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub(crate) enum Value {
     /// Simple strings are used to transmit non binary safee strings
     /// with minimal overhead.
@@ -63,6 +63,7 @@ pub(crate) enum Value {
     /// binary safe string up to 512 MB in length.
     BulkString(Vec<u8>),
     /// Signal non-existence of a value, length is set to -1.
+    #[default]
     NullBulkString,
     /// Client send commands to the server using RESP arrays.
     /// Commands returning collections of elements to the client
@@ -74,12 +75,6 @@ pub(crate) enum Value {
     NullArray,
 }
 
-impl Default for Value {
-    fn default() -> Self {
-        Self::NullBulkString
-    }
-}
-
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -146,7 +141,7 @@ impl Value {
 
     pub fn as_simple_string(&self) -> Option<&str> {
         match self {
-            Value::SimpleString(s) => Some(&s),
+            Value::SimpleString(s) => Some(s),
             _ => None,
         }
     }
@@ -156,7 +151,7 @@ impl Value {
         self.as_error().is_some()
     }
 
-    pub fn as_error(&self) -> Option<Error> {
+    pub fn as_error(&self) -> Option<Error<'_>> {
         match self {
             Value::Error { name, msg } => Some(Error::new(name, msg)),
             _ => None,
@@ -297,7 +292,6 @@ pub(crate) fn deserialize_from_reader<B: BufRead>(
 #[derive(Debug)]
 pub(crate) struct Deserializer<B> {
     inner: ByteLineReader<B>,
-    buf: Vec<u8>,
 }
 
 impl<B> Deserializer<B>
@@ -308,7 +302,6 @@ where
     pub fn from_reader(inner: B) -> Self {
         Self {
             inner: ByteLineReader::new(inner),
-            buf: Vec::new(),
         }
     }
 
@@ -452,7 +445,7 @@ where
         match value {
             Value::SimpleString(s) => self.serialize_simple_string(s.as_ref()),
             Value::Integer(i) => self.serialize_integer(i.to_owned()),
-            Value::Error { name, msg } => self.serialize_error(&name, &msg),
+            Value::Error { name, msg } => self.serialize_error(name, msg),
             Value::BulkString(s) => self.serialize_bulk_string(s.as_ref()),
             Value::Array(v) => self.serialize_array(v.as_ref()),
             Value::NullArray => self.serialize_null_array(),
@@ -468,7 +461,7 @@ where
 
     pub fn serialize_error(&mut self, name: &str, msg: &str) -> Result<()> {
         self.write(WRITE_ERROR_PREFIX)?;
-        if name == "" {
+        if name.is_empty() {
             self.write(b"ERR")?;
         } else {
             self.write(name.as_bytes())?;
@@ -624,7 +617,7 @@ mod tests {
         assert!(v.is_array());
         let r = v.as_array().unwrap();
         assert_eq!(r.len(), 1);
-        assert_eq!(r.get(0).unwrap().as_integer().unwrap(), 1);
+        assert_eq!(r.first().unwrap().as_integer().unwrap(), 1);
 
         let r = parse_value("*2\r\n:1\r\n$5\r\ntinkv\r\n");
         assert!(r.is_ok());
@@ -632,7 +625,7 @@ mod tests {
         assert!(v.is_array());
         let r = v.as_array().unwrap();
         assert_eq!(r.len(), 2);
-        assert_eq!(r.get(0).unwrap().as_integer().unwrap(), 1);
+        assert_eq!(r.first().unwrap().as_integer().unwrap(), 1);
         assert_eq!(
             r.get(1).unwrap().as_bulk_string().unwrap(),
             "tinkv".as_bytes()
@@ -644,7 +637,7 @@ mod tests {
         assert!(v.is_array());
         let r = v.as_array().unwrap();
         assert_eq!(r.len(), 3);
-        assert_eq!(r.get(0).unwrap().as_integer().unwrap(), 1);
+        assert_eq!(r.first().unwrap().as_integer().unwrap(), 1);
         assert_eq!(
             r.get(1).unwrap().as_bulk_string().unwrap(),
             "tinkv".as_bytes()
@@ -1,39 +1,107 @@
 //! Maintain data files.
+use super::header::{check_header, write_header, SegmentKind, HEADER_SIZE};
 use crate::error::{Result, TinkvError};
-use crate::util::{checksum, parse_file_id, BufReaderWithOffset, FileWithBufWriter};
+use crate::util::{
+    current_timestamp, parse_file_id, BufReaderWithOffset, ChecksumWriter, FileWithBufWriter,
+};
 use serde::{Deserialize, Serialize};
 
+use crate::vfs::{Vfs, VfsFile};
 use log::{error, trace};
 use std::fmt;
-use std::fs::{self, File};
-use std::io::{copy, Read, Seek, SeekFrom, Write};
+use std::io::{self, copy, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Kind of a record in segment files.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub(crate) enum RecordKind {
+    /// A key value pair.
+    Put,
+    /// A tomestone of removed key, its value is empty.
+    Remove,
+    /// A merge operand, to be folded onto the value of key.
+    Merge,
+    /// A tomestone of dropped keyspace, its key and value are empty.
+    DropKeyspace,
+    /// Sequence number of the last write, written by compaction
+    /// since tomestones are not copied. Its keyspace, key and value
+    /// are empty.
+    SeqMark,
+}
+
+impl RecordKind {
+    /// Return name of the kind, e.g. `drop-keyspace`.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            RecordKind::Put => "put",
+            RecordKind::Remove => "remove",
+            RecordKind::Merge => "merge",
+            RecordKind::DropKeyspace => "drop-keyspace",
+            RecordKind::SeqMark => "seq-mark",
+        }
+    }
+}
 
 /// Data entry definition.
 /// It will be serialized and saved to data file.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 struct InnerEntry {
+    // sequence number of the write, increases monotonically in datastore.
+    seq: u64,
+    kind: RecordKind,
+    // write time in nanoseconds since unix epoch.
+    timestamp: u64,
+    // name of the keyspace which the key belongs to.
+    keyspace: String,
     key: Vec<u8>,
     value: Vec<u8>,
-    // crc32 checksum
+    // expiration time in milliseconds since unix epoch.
+    expires_at: Option<u64>,
+    // crc32 checksum of the other fields.
     checksum: u32,
 }
 
 impl InnerEntry {
     /// New data entry with given key and value.
     /// Checksum will be updated internally.
-    fn new(key: &[u8], value: &[u8]) -> Self {
+    fn new(
+        seq: u64,
+        kind: RecordKind,
+        keyspace: &str,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Self {
         let mut ent = InnerEntry {
+            seq,
+            kind,
+            timestamp: current_timestamp() as u64,
+            keyspace: keyspace.to_owned(),
             key: key.into(),
             value: value.into(),
+            expires_at,
             checksum: 0,
         };
         ent.checksum = ent.fresh_checksum();
         ent
     }
 
+    /// Checksum of all the fields but `checksum`, so that a corrupted
+    /// kind, key or expiration time is detected as well as the value.
     fn fresh_checksum(&self) -> u32 {
-        checksum(&self.value)
+        let mut w = ChecksumWriter::new();
+        let fields = (
+            self.seq,
+            self.kind,
+            self.timestamp,
+            &self.keyspace,
+            &self.key,
+            &self.value,
+            self.expires_at,
+        );
+        bincode::serialize_into(&mut w, &fields).expect("failed to serialize record");
+        w.sum()
     }
 
     /// Check data entry is corrupted or not.
@@ -46,7 +114,10 @@ impl fmt::Display for InnerEntry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "DataInnerEntry(key='{}', checksum={})",
+            "DataInnerEntry(seq={}, kind={}, keyspace='{}', key='{}', checksum={})",
+            self.seq,
+            self.kind.name(),
+            self.keyspace,
             String::from_utf8_lossy(self.key.as_ref()),
             self.checksum,
         )
@@ -80,6 +151,26 @@ impl Entry {
         self.inner.is_valid()
     }
 
+    /// Return sequence number of the inner entry.
+    pub(crate) fn seq(&self) -> u64 {
+        self.inner.seq
+    }
+
+    /// Return kind of the inner entry.
+    pub(crate) fn kind(&self) -> RecordKind {
+        self.inner.kind
+    }
+
+    /// Return write time (in nanoseconds) of the inner entry.
+    pub(crate) fn timestamp(&self) -> u64 {
+        self.inner.timestamp
+    }
+
+    /// Return keyspace name of the inner entry.
+    pub(crate) fn keyspace(&self) -> &str {
+        &self.inner.keyspace
+    }
+
     /// Return key of the inner entry.
     pub(crate) fn key(&self) -> &[u8] {
         &self.inner.key
@@ -89,15 +180,21 @@ impl Entry {
     pub(crate) fn value(&self) -> &[u8] {
         &self.inner.value
     }
+
+    /// Return expiration time (in milliseconds) of the inner entry.
+    pub(crate) fn expires_at(&self) -> Option<u64> {
+        self.inner.expires_at
+    }
 }
 
 impl fmt::Display for Entry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "DataEntry(file_id={}, key='{}', offset={}, size={})",
+            "DataEntry(file_id={}, keyspace='{}', key='{}', offset={}, size={})",
             self.file_id,
-            String::from_utf8_lossy(self.key().as_ref()),
+            self.keyspace(),
+            String::from_utf8_lossy(self.key()),
             self.offset,
             self.size,
         )
@@ -118,32 +215,39 @@ pub(crate) struct DataFile {
     writer: Option<FileWithBufWriter>,
 
     /// File handle of current data file for reading.
-    reader: BufReaderWithOffset<File>,
+    reader: BufReaderWithOffset<Box<dyn VfsFile>>,
     /// Data file size.
     pub size: u64,
+    /// Filesystem of the data file.
+    vfs: Arc<dyn Vfs>,
 }
 
 impl DataFile {
     /// Create a new data file instance.
     /// It parses data id from file path, which wraps an optional
     /// writer (only for writeable segement file) and reader.
-    pub(crate) fn new(path: &Path, writeable: bool) -> Result<Self> {
+    pub(crate) fn new(vfs: Arc<dyn Vfs>, path: &Path, writeable: bool) -> Result<Self> {
         // Data name must starts with valid file id.
-        let file_id = parse_file_id(path).expect("file id not found in file path");
-
-        let w = if writeable {
-            let f = fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .append(true)
-                .open(path)?;
-            Some(FileWithBufWriter::from(f)?)
+        let file_id =
+            parse_file_id(path).ok_or_else(|| TinkvError::UnknownFile(path.to_path_buf()))?;
+
+        let w = if writeable && vfs.file_size(path).unwrap_or(0) == 0 {
+            // header of a new data file is synced along with it.
+            let mut w = FileWithBufWriter::from(vfs.open_append(path)?)?;
+            write_header(&mut w, SegmentKind::Data)?;
+            w.sync_data()?;
+            Some(w)
         } else {
-            None
+            check_header(&mut vfs.open(path)?, path, SegmentKind::Data)?;
+            if writeable {
+                Some(FileWithBufWriter::from(vfs.open_append(path)?)?)
+            } else {
+                None
+            }
         };
 
-        let file = fs::File::open(path)?;
-        let size = file.metadata()?.len();
+        let file = vfs.open(path)?;
+        let size = vfs.file_size(path)?;
         let df = DataFile {
             path: path.to_path_buf(),
             id: file_id,
@@ -151,14 +255,23 @@ impl DataFile {
             reader: BufReaderWithOffset::new(file)?,
             writer: w,
             size,
+            vfs,
         };
 
         Ok(df)
     }
 
-    /// Save key-value pair to segement file.
-    pub(crate) fn write(&mut self, key: &[u8], value: &[u8]) -> Result<Entry> {
-        let inner = InnerEntry::new(key, value);
+    /// Save key-value pair of the given keyspace to segement file.
+    pub(crate) fn write(
+        &mut self,
+        seq: u64,
+        kind: RecordKind,
+        keyspace: &str,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<Entry> {
+        let inner = InnerEntry::new(seq, kind, keyspace, key, value, expires_at);
         trace!("append {} to segement file {}", &inner, self.path.display());
         // avoid immutable borrowing issue.
         let path = self.path.as_path();
@@ -227,27 +340,40 @@ impl DataFile {
         Ok(offset)
     }
 
-    /// Return an entry iterator.
-    pub(crate) fn entry_iter(&self) -> EntryIter {
-        // TODO: refactor entry iter.
-        EntryIter {
+    /// Return an entry iterator over a new handle of the data file.
+    pub(crate) fn entry_iter(&self) -> Result<EntryIter> {
+        let mut reader = BufReaderWithOffset::new(self.vfs.open(&self.path)?)?;
+        reader.seek(SeekFrom::Start(HEADER_SIZE))?;
+        Ok(EntryIter {
             path: self.path.clone(),
-            reader: fs::File::open(self.path.clone()).unwrap(),
+            reader,
             file_id: self.id,
-        }
+            done: false,
+        })
+    }
+
+    /// Return a new handle of the writeable data file.
+    pub(crate) fn try_clone_file(&self) -> Result<Box<dyn VfsFile>> {
+        let w = self
+            .writer
+            .as_ref()
+            .ok_or_else(|| TinkvError::FileNotWriteable(self.path.clone()))?;
+        Ok(w.inner().try_clone()?)
     }
 
     /// Flush all pending writes to disk.
     pub(crate) fn sync(&mut self) -> Result<()> {
         self.flush()?;
-        if self.writer.is_some() {
-            self.writer.as_mut().unwrap().sync()?;
+        if let Some(w) = self.writer.as_mut() {
+            // data files are append only, file size is
+            // the only metadata needed to read them back.
+            w.sync_data()?;
         }
         Ok(())
     }
 
     /// Flush buf writer.
-    fn flush(&mut self) -> Result<()> {
+    pub(crate) fn flush(&mut self) -> Result<()> {
         if self.writeable {
             self.writer.as_mut().unwrap().flush()?;
         }
@@ -265,28 +391,52 @@ impl Drop for DataFile {
             );
         }
 
-        // auto clean up if file size is zero.
-        if self.writeable && self.size == 0 && fs::remove_file(self.path.as_path()).is_ok() {
+        // auto clean up if there are no records.
+        if self.writeable
+            && self.size <= HEADER_SIZE
+            && self.vfs.remove_file(self.path.as_path()).is_ok()
+        {
             trace!("data file '{}' is empty, remove it.", self.path.display());
         }
     }
 }
 
 /// An iterator over a data file, return data entries.
+///
+/// It stops at the end of file or at a partially written entry
+/// left by a crash, other read errors are yielded once.
 #[derive(Debug)]
 pub(crate) struct EntryIter {
     path: PathBuf,
-    reader: fs::File,
+    reader: BufReaderWithOffset<Box<dyn VfsFile>>,
     file_id: u64,
+    done: bool,
 }
 
 impl Iterator for EntryIter {
-    type Item = Entry;
+    type Item = Result<Entry>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let offset = self.reader.seek(SeekFrom::Current(0)).unwrap();
-        let inner: InnerEntry = bincode::deserialize_from(&self.reader).ok()?;
-        let new_offset = self.reader.seek(SeekFrom::Current(0)).unwrap();
+        if self.done {
+            return None;
+        }
+
+        let offset = self.reader.offset();
+        let inner: InnerEntry = match bincode::deserialize_from(&mut self.reader) {
+            Ok(inner) => inner,
+            Err(e) => {
+                self.done = true;
+                if is_unexpected_eof(&e) {
+                    return None;
+                }
+                return Some(Err(TinkvError::SegmentRead {
+                    path: self.path.clone(),
+                    offset,
+                    source: e,
+                }));
+            }
+        };
+        let new_offset = self.reader.offset();
 
         let entry = Entry::new(self.file_id, inner, new_offset - offset, offset);
 
@@ -296,30 +446,77 @@ impl Iterator for EntryIter {
             self.path.display()
         );
 
-        Some(entry)
+        Some(Ok(entry))
     }
 }
 
+/// Return size of value of an entry, which is `size` bytes in data file.
+pub(crate) fn value_size_of(size: u64, keyspace: &str, key: &[u8], expires_at: Option<u64>) -> u64 {
+    // seq, kind, timestamp, checksum, length of keyspace, key
+    // and value, and tag of expiration time.
+    let overhead = 8 + 4 + 8 + 4 + 8 * 3 + 1 + expires_at.map_or(0, |_| 8);
+    size.saturating_sub(overhead + keyspace.len() as u64 + key.len() as u64)
+}
+
+/// Return `true` if the end of file is reached before an entry is decoded.
+pub(crate) fn is_unexpected_eof(e: &bincode::ErrorKind) -> bool {
+    matches!(e, bincode::ErrorKind::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_new_entry() {
-        let ent = InnerEntry::new(&b"key".to_vec(), &b"value".to_vec());
-        assert_eq!(ent.checksum, 494360628);
+        let mut ent = InnerEntry::new(1, RecordKind::Put, "default", b"key", b"value", None);
+        assert_eq!(ent.checksum, ent.fresh_checksum());
+
+        // the checksum depends on the timestamp, pin it to check the value.
+        ent.timestamp = 1592475604853000000;
+        assert_eq!(ent.fresh_checksum(), 991221426);
+    }
+
+    #[test]
+    fn test_value_size_of() {
+        for &expires_at in &[None, Some(1592475604853)] {
+            let ent = InnerEntry::new(1, RecordKind::Put, "default", b"key", b"value", expires_at);
+            let size = bincode::serialized_size(&ent).unwrap();
+            assert_eq!(value_size_of(size, "default", b"key", expires_at), 5);
+        }
     }
 
     #[test]
     fn test_checksum_valid() {
-        let ent = InnerEntry::new(&b"key".to_vec(), &b"value".to_vec());
-        assert_eq!(ent.is_valid(), true);
+        let ent = InnerEntry::new(1, RecordKind::Put, "default", b"key", b"value", None);
+        assert!(ent.is_valid());
     }
 
     #[test]
     fn test_checksum_invalid() {
-        let mut ent = InnerEntry::new(&b"key".to_vec(), &b"value".to_vec());
+        let mut ent = InnerEntry::new(1, RecordKind::Put, "default", b"key", b"value", None);
         ent.value = b"value_changed".to_vec();
-        assert_eq!(ent.is_valid(), false);
+        assert!(!ent.is_valid());
+    }
+
+    #[test]
+    fn test_checksum_covers_all_fields() {
+        let ent = InnerEntry::new(1, RecordKind::Put, "default", b"key", b"value", None);
+
+        let mut changed = ent.clone();
+        changed.key = b"kez".to_vec();
+        assert!(!changed.is_valid());
+
+        let mut changed = ent.clone();
+        changed.kind = RecordKind::Remove;
+        assert!(!changed.is_valid());
+
+        let mut changed = ent.clone();
+        changed.keyspace = "other".to_owned();
+        assert!(!changed.is_valid());
+
+        let mut changed = ent;
+        changed.expires_at = Some(1);
+        assert!(!changed.is_valid());
     }
 }
@@ -0,0 +1,209 @@
+//! Dump records of data files and hint files in readable text or
+//! JSON Lines, for debugging.
+use super::{DataFile, HintFile};
+use crate::config;
+use crate::error::{Result, TinkvError};
+use crate::vfs::DiskFs;
+use serde::Serialize;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Max bytes of value shown in a dumped record.
+const VALUE_PREVIEW_SIZE: usize = 32;
+
+/// Options of `dump_segment`.
+#[derive(Debug, Clone)]
+pub struct DumpOptions {
+    json: bool,
+    key_pattern: Option<glob::Pattern>,
+    offsets: Range<u64>,
+}
+
+impl Default for DumpOptions {
+    fn default() -> Self {
+        Self {
+            json: false,
+            key_pattern: None,
+            offsets: 0..u64::MAX,
+        }
+    }
+}
+
+impl DumpOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Dump one JSON object per line instead of readable text.
+    pub fn json(&mut self, value: bool) -> &mut Self {
+        self.json = value;
+        self
+    }
+
+    /// Only records with keys matching the glob pattern are dumped.
+    pub fn key_pattern(&mut self, value: &str) -> Result<&mut Self> {
+        self.key_pattern = Some(glob::Pattern::new(value)?);
+        Ok(self)
+    }
+
+    /// Only records starting within the offset range are dumped.
+    pub fn offsets(&mut self, value: Range<u64>) -> &mut Self {
+        self.offsets = value;
+        self
+    }
+}
+
+/// A dumped record.
+#[derive(Debug, Serialize)]
+struct Record {
+    offset: u64,
+    size: u64,
+    kind: &'static str,
+    seq: u64,
+    keyspace: String,
+    key: String,
+    // size of value, `None` for hint records.
+    value_size: Option<u64>,
+    value: Option<String>,
+    expires_at: Option<u64>,
+    // `None` for hint records, they have no checksum.
+    checksum_valid: Option<bool>,
+}
+
+/// Write records of the data file or hint file at `path` to `w`,
+/// return number of records written.
+///
+/// Records before an undecodable one are written before its error
+/// is returned.
+pub fn dump_segment<P: AsRef<Path>, W: Write>(
+    path: P,
+    mut w: W,
+    options: &DumpOptions,
+) -> Result<u64> {
+    let path = path.as_ref();
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut count = 0;
+    let mut dump = |record: Record| -> Result<bool> {
+        if record.offset >= options.offsets.end {
+            return Ok(false);
+        }
+        let matched = options
+            .key_pattern
+            .as_ref()
+            .map(|pattern| pattern.matches(&record.key))
+            .unwrap_or(true);
+        if record.offset >= options.offsets.start && matched {
+            write_record(&mut w, &record, options.json)?;
+            count += 1;
+        }
+        Ok(true)
+    };
+
+    if name.ends_with(config::DATA_FILE_SUFFIX) {
+        let df = DataFile::new(Arc::new(DiskFs), path, false)?;
+        for entry in df.entry_iter()? {
+            let entry = entry?;
+            let record = Record {
+                offset: entry.offset,
+                size: entry.size,
+                kind: entry.kind().name(),
+                seq: entry.seq(),
+                keyspace: entry.keyspace().to_owned(),
+                key: escape(entry.key()),
+                value_size: Some(entry.value().len() as u64),
+                value: Some(preview(entry.value())),
+                expires_at: entry.expires_at(),
+                checksum_valid: Some(entry.is_valid()),
+            };
+            if !dump(record)? {
+                break;
+            }
+        }
+    } else if name.ends_with(config::HINT_FILE_SUFFIX) {
+        let mut hint_file = HintFile::new(Arc::new(DiskFs), path, false)?;
+        for entry in hint_file.entry_iter() {
+            let entry = entry?;
+            let record = Record {
+                offset: entry.offset,
+                size: entry.size,
+                kind: entry.kind.name(),
+                seq: entry.seq,
+                keyspace: entry.keyspace,
+                key: escape(&entry.key),
+                value_size: None,
+                value: None,
+                expires_at: entry.expires_at,
+                checksum_valid: None,
+            };
+            // hint records are ordered by offset in data file as well.
+            if !dump(record)? {
+                break;
+            }
+        }
+    } else {
+        return Err(TinkvError::UnknownFile(path.to_path_buf()));
+    }
+
+    w.flush()?;
+    Ok(count)
+}
+
+fn write_record<W: Write>(w: &mut W, record: &Record, json: bool) -> Result<()> {
+    if json {
+        serde_json::to_writer(&mut *w, record)?;
+        writeln!(w)?;
+        return Ok(());
+    }
+
+    write!(
+        w,
+        "offset={} size={} kind={} seq={} keyspace='{}' key=\"{}\"",
+        record.offset, record.size, record.kind, record.seq, record.keyspace, record.key
+    )?;
+    if let (Some(value), Some(value_size)) = (&record.value, record.value_size) {
+        write!(w, " value=\"{}\" value_size={}", value, value_size)?;
+    }
+    if let Some(expires_at) = record.expires_at {
+        write!(w, " expires_at={}", expires_at)?;
+    }
+    match record.checksum_valid {
+        Some(true) => writeln!(w, " checksum=ok")?,
+        Some(false) => writeln!(w, " checksum=BAD")?,
+        None => writeln!(w)?,
+    }
+    Ok(())
+}
+
+/// Escape non-printable bytes, e.g. `\x00`.
+fn escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|&b| std::ascii::escape_default(b))
+        .map(char::from)
+        .collect()
+}
+
+fn preview(value: &[u8]) -> String {
+    if value.len() <= VALUE_PREVIEW_SIZE {
+        return escape(value);
+    }
+    format!("{}...", escape(&value[..VALUE_PREVIEW_SIZE]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(b"key"), "key");
+        assert_eq!(escape(b"a\x00\"\n"), "a\\x00\\\"\\n");
+        assert_eq!(preview(&[b'a'; 40]), format!("{}...", "a".repeat(32)));
+    }
+}
@@ -0,0 +1,112 @@
+//! Header of segment files, it tells what the file is and in which
+//! format its records are written.
+use crate::error::{Result, TinkvError};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Format version of records in segment files, it must be bumped on
+/// any change of record layout.
+pub(crate) const FORMAT_VERSION: u16 = 2;
+/// Size (bytes) of the header, the first record starts right after it.
+pub(crate) const HEADER_SIZE: u64 = 8;
+const MAGIC: &[u8; 5] = b"TINKV";
+
+/// Kind of segment file.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum SegmentKind {
+    Data,
+    Hint,
+}
+
+impl SegmentKind {
+    fn tag(self) -> u8 {
+        match self {
+            SegmentKind::Data => b'D',
+            SegmentKind::Hint => b'H',
+        }
+    }
+}
+
+/// Encode header: magic, kind tag and format version (little endian).
+fn encode(kind: SegmentKind, version: u16) -> [u8; HEADER_SIZE as usize] {
+    let mut header = [0; HEADER_SIZE as usize];
+    header[..5].copy_from_slice(MAGIC);
+    header[5] = kind.tag();
+    header[6..].copy_from_slice(&version.to_le_bytes());
+    header
+}
+
+/// Write header to a newly created segment file.
+pub(crate) fn write_header<W: Write>(w: &mut W, kind: SegmentKind) -> Result<()> {
+    w.write_all(&encode(kind, FORMAT_VERSION))?;
+    Ok(())
+}
+
+/// Check header of the segment file, return error if it's in another
+/// format. An empty file or a partially written header means there
+/// are no records at all.
+pub(crate) fn check_header<R: Read>(r: &mut R, path: &Path, kind: SegmentKind) -> Result<()> {
+    let mut buf = [0; HEADER_SIZE as usize];
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(len) => n += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let expected = encode(kind, FORMAT_VERSION);
+    if buf[..n] == expected[..n] {
+        return Ok(());
+    }
+
+    if n < buf.len() || &buf[..5] != MAGIC {
+        // files written before headers were introduced.
+        return Err(TinkvError::UnsupportedFormat {
+            path: path.to_path_buf(),
+            version: 0,
+        });
+    }
+    if buf[5] != kind.tag() {
+        return Err(TinkvError::UnknownFile(path.to_path_buf()));
+    }
+    Err(TinkvError::UnsupportedFormat {
+        path: path.to_path_buf(),
+        version: u16::from_le_bytes([buf[6], buf[7]]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(bytes: &[u8], kind: SegmentKind) -> Result<()> {
+        check_header(&mut &bytes[..], Path::new("1.tinkv.data"), kind)
+    }
+
+    #[test]
+    fn test_check_header() {
+        let mut header = vec![];
+        write_header(&mut header, SegmentKind::Data).unwrap();
+        assert_eq!(header.len() as u64, HEADER_SIZE);
+        assert!(check(&header, SegmentKind::Data).is_ok());
+        assert!(check(&header[..3], SegmentKind::Data).is_ok());
+        assert!(check(b"", SegmentKind::Data).is_ok());
+        assert!(matches!(
+            check(&header, SegmentKind::Hint),
+            Err(TinkvError::UnknownFile(_))
+        ));
+
+        header[6] = 99;
+        assert!(matches!(
+            check(&header, SegmentKind::Data),
+            Err(TinkvError::UnsupportedFormat { version: 99, .. })
+        ));
+        assert!(matches!(
+            check(&[3, 0, 0, 0, 0, 0, 0, 0, b'k'], SegmentKind::Data),
+            Err(TinkvError::UnsupportedFormat { version: 0, .. })
+        ));
+    }
+}
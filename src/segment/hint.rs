@@ -1,28 +1,38 @@
 //! Maintain hint files. Each compacted data file
 //! should bind with a hint file for faster loading.
-use crate::error::Result;
+use super::data::{is_unexpected_eof, RecordKind};
+use super::header::{check_header, write_header, SegmentKind, HEADER_SIZE};
+use crate::error::{Result, TinkvError};
 use crate::util::{parse_file_id, FileWithBufWriter};
+use crate::vfs::{Vfs, VfsFile};
 use log::{error, trace};
 use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::fs::{self, File};
 use std::io::prelude::*;
 use std::io::{BufReader, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// Entry in the hint file.
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Entry {
+    pub seq: u64,
+    pub kind: RecordKind,
+    pub keyspace: String,
     pub key: Vec<u8>,
     pub offset: u64,
     pub size: u64,
+    pub expires_at: Option<u64>,
 }
 
 impl fmt::Display for Entry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "HintEntry(key='{}', offset={}, size={})",
+            "HintEntry(seq={}, kind={}, keyspace='{}', key='{}', offset={}, size={})",
+            self.seq,
+            self.kind.name(),
+            self.keyspace,
             String::from_utf8_lossy(self.key.as_ref()),
             self.offset,
             self.size,
@@ -40,24 +50,27 @@ pub struct HintFile {
     entries_written: u64,
     writeable: bool,
     writer: Option<FileWithBufWriter>,
-    reader: BufReader<File>,
+    reader: BufReader<Box<dyn VfsFile>>,
+    vfs: Arc<dyn Vfs>,
 }
 
 impl HintFile {
-    pub(crate) fn new(path: &Path, writeable: bool) -> Result<Self> {
+    pub(crate) fn new(vfs: Arc<dyn Vfs>, path: &Path, writeable: bool) -> Result<Self> {
         // File name must starts with valid file id.
-        let file_id = parse_file_id(path).expect("file id not found in file path");
+        let file_id =
+            parse_file_id(path).ok_or_else(|| TinkvError::UnknownFile(path.to_path_buf()))?;
 
-        let w = if writeable {
-            let f = fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .append(true)
-                .open(path)?;
-
-            Some(FileWithBufWriter::from(f)?)
+        let w = if writeable && vfs.file_size(path).unwrap_or(0) == 0 {
+            let mut w = FileWithBufWriter::from(vfs.open_append(path)?)?;
+            write_header(&mut w, SegmentKind::Hint)?;
+            Some(w)
         } else {
-            None
+            check_header(&mut vfs.open(path)?, path, SegmentKind::Hint)?;
+            if writeable {
+                Some(FileWithBufWriter::from(vfs.open_append(path)?)?)
+            } else {
+                None
+            }
         };
 
         Ok(Self {
@@ -66,32 +79,30 @@ impl HintFile {
             entries_written: 0,
             writeable,
             writer: w,
-            reader: BufReader::new(File::open(path)?),
+            reader: BufReader::new(vfs.open(path)?),
+            vfs,
         })
     }
 
-    pub(crate) fn write(&mut self, key: &[u8], offset: u64, size: u64) -> Result<()> {
-        let entry = Entry {
-            key: key.into(),
-            offset,
-            size,
-        };
-        trace!("append {} to file {}", &entry, self.path.display());
+    /// Append an entry, return its size.
+    pub(crate) fn write(&mut self, entry: &Entry) -> Result<u64> {
+        trace!("append {} to file {}", entry, self.path.display());
 
         let w = &mut self.writer.as_mut().expect("hint file is not writeable");
-        bincode::serialize_into(w, &entry)?;
+        let size = bincode::serialized_size(entry)?;
+        bincode::serialize_into(w, entry)?;
         self.entries_written += 1;
 
         self.flush()?;
 
-        Ok(())
+        Ok(size)
     }
 
     /// Sync all pending writes to disk.
     pub(crate) fn sync(&mut self) -> Result<()> {
         self.flush()?;
-        if self.writer.is_some() {
-            self.writer.as_mut().unwrap().sync()?;
+        if let Some(w) = self.writer.as_mut() {
+            w.sync()?;
         }
         Ok(())
     }
@@ -104,7 +115,7 @@ impl HintFile {
         Ok(())
     }
 
-    pub(crate) fn entry_iter(&mut self) -> EntryIter {
+    pub(crate) fn entry_iter(&mut self) -> EntryIter<'_> {
         EntryIter::new(self)
     }
 }
@@ -121,40 +132,76 @@ impl Drop for HintFile {
 
         if self.writeable
             && self.entries_written == 0
-            && fs::remove_file(self.path.as_path()).is_ok()
+            && self.vfs.remove_file(self.path.as_path()).is_ok()
         {
             trace!("hint file {} is empty, remove it.", self.path.display());
         }
     }
 }
 
+/// An iterator over a hint file, return hint entries.
+///
+/// It stops at the end of file or at a partially written entry,
+/// other read errors are yielded once.
 pub(crate) struct EntryIter<'a> {
     hint_file: &'a mut HintFile,
     offset: u64,
+    done: bool,
 }
 
 impl<'a> EntryIter<'a> {
     fn new(hint_file: &'a mut HintFile) -> Self {
         EntryIter {
             hint_file,
-            offset: 0,
+            offset: HEADER_SIZE,
+            done: false,
         }
     }
+
+    fn read_entry(&mut self) -> Result<Option<Entry>> {
+        let reader = &mut self.hint_file.reader;
+        reader.seek(SeekFrom::Start(self.offset))?;
+        let entry: Entry = match bincode::deserialize_from(&mut *reader) {
+            Ok(entry) => entry,
+            Err(e) if is_unexpected_eof(&e) => return Ok(None),
+            Err(e) => {
+                return Err(TinkvError::SegmentRead {
+                    path: self.hint_file.path.clone(),
+                    offset: self.offset,
+                    source: e,
+                })
+            }
+        };
+        self.offset = reader.stream_position()?;
+        Ok(Some(entry))
+    }
 }
 
-impl<'a> Iterator for EntryIter<'a> {
-    type Item = Entry;
+impl Iterator for EntryIter<'_> {
+    type Item = Result<Entry>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let reader = &mut self.hint_file.reader;
-        reader.seek(SeekFrom::Start(self.offset)).unwrap();
-        let entry: Entry = bincode::deserialize_from(reader).ok()?;
-        self.offset = self.hint_file.reader.seek(SeekFrom::Current(0)).unwrap();
-        trace!(
-            "iter read {} from hint file {}",
-            &entry,
-            self.hint_file.path.display()
-        );
-        Some(entry)
+        if self.done {
+            return None;
+        }
+
+        match self.read_entry() {
+            Ok(Some(entry)) => {
+                trace!(
+                    "iter read {} from hint file {}",
+                    &entry,
+                    self.hint_file.path.display()
+                );
+                Some(Ok(entry))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
     }
 }
@@ -1,5 +1,11 @@
 mod data;
+mod dump;
+mod header;
 mod hint;
 
-pub(crate) use data::{DataFile, Entry as DataEntry};
-pub(crate) use hint::HintFile;
+pub(crate) use data::{
+    value_size_of, DataFile, Entry as DataEntry, EntryIter as DataEntryIter, RecordKind,
+};
+pub use dump::{dump_segment, DumpOptions};
+pub(crate) use header::{FORMAT_VERSION, HEADER_SIZE};
+pub(crate) use hint::{Entry as HintEntry, HintFile};
@@ -1,8 +1,10 @@
 //! TinKV server is a redis-compatible key value server.
 
+use crate::config;
+use crate::engine::KvEngine;
 use crate::error::{Result, TinkvError};
-
-use crate::store::Store;
+use crate::replication::{serve_replica, ReplicationState};
+use crate::store::{CommitHandle, RateLimiter, ScrubReport, Store};
 
 use crate::resp::{deserialize_from_reader, serialize_to_writer, Value};
 use lazy_static::lazy_static;
@@ -14,22 +16,241 @@ use std::fmt;
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter};
 use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
 
 lazy_static! {
     static ref COMMANDS: Vec<&'static str> = vec![
-        "ping", "get", "mget", "set", "mset", "del", "dbsize", "exists", "keys", "flushdb",
-        "flushall", "compact", "info", "command",
+        "ping",
+        "get",
+        "mget",
+        "set",
+        "setnx",
+        "mset",
+        "del",
+        "dbsize",
+        "exists",
+        "keys",
+        "flushdb",
+        "flushall",
+        "compact",
+        "info",
+        "command",
+        "replicaof",
+        "slaveof",
+        "sync",
+        "debug",
+        "config",
     ];
 }
 
-pub struct Server {
-    store: Store,
+/// Number of the most common key prefixes listed by `INFO keyspace`.
+const MAX_PREFIXES_IN_INFO: usize = 10;
+
+/// Parameters supported by `CONFIG GET` and `CONFIG SET`.
+const CONFIG_PARAMETERS: &[&str] = &["background-io-rate"];
+
+/// Commands rejected by a read-only replica.
+const WRITE_COMMANDS: &[&str] = &["set", "setnx", "mset", "del", "flushall", "flushdb"];
+
+/// Convert error of a write command into a reply, like redis,
+/// writes rejected by a full datastore are replied with `OOM`.
+fn write_error(e: TinkvError) -> TinkvError {
+    match e {
+        TinkvError::StoreFull => TinkvError::new_resp_common("OOM", &format!("{}", e)),
+        e => TinkvError::new_resp_common("INTERNALERR", &format!("{}", e)),
+    }
+}
+
+/// Error of a feature which needs an engine backed by a datastore.
+pub(crate) fn not_supported(feature: &str) -> TinkvError {
+    TinkvError::Custom(format!(
+        "{} is not supported by the storage engine",
+        feature
+    ))
+}
+
+/// Each connection is served in its own thread, they share
+/// the same storage engine.
+///
+/// Replication, group commit, scrubbing, background IO rate and INFO of
+/// persistence and keyspaces rely on data files, they are supported
+/// only if the engine is backed by a `Store`. Other engines (e.g.
+/// `MemoryEngine`) reply errors to `replicaof`, `sync` and `debug scrub`.
+pub struct Server<E: KvEngine = Store> {
+    store: Arc<Mutex<E>>,
+    // durable writes are synced in batches outside of the store lock,
+    // `None` if the engine is not backed by a datastore.
+    commit: Option<CommitHandle>,
+    replication: Arc<Mutex<ReplicationState>>,
+    // limits background IO of the datastore, `None` if the engine
+    // is not backed by a datastore.
+    rate_limiter: Option<RateLimiter>,
+    scrub: Arc<Mutex<ScrubProgress>>,
+}
+
+/// Progress of the running (or the last) scrub, reported by INFO.
+#[derive(Debug, Default)]
+struct ScrubProgress {
+    in_progress: bool,
+    total_files: u64,
+    scrubbed_files: u64,
+    // data file being scrubbed.
+    current_file: Option<u64>,
+    corrupted_records: u64,
+    affected_keys: u64,
+}
+
+impl<E: KvEngine> Clone for Server<E> {
+    fn clone(&self) -> Self {
+        Server {
+            store: self.store.clone(),
+            commit: self.commit.clone(),
+            replication: self.replication.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            scrub: self.scrub.clone(),
+        }
+    }
 }
 
-impl Server {
+impl<E: KvEngine> Server<E> {
     #[allow(dead_code)]
-    pub fn new(store: Store) -> Self {
-        Server { store }
+    pub fn new(mut store: E) -> Self {
+        let commit = store.as_store().map(|store| {
+            store.set_defer_commits(true);
+            store.commit_handle()
+        });
+        let rate_limiter = store.as_store().map(|store| store.rate_limiter());
+        Server {
+            commit,
+            rate_limiter,
+            store: Arc::new(Mutex::new(store)),
+            replication: Arc::new(Mutex::new(ReplicationState::default())),
+            scrub: Arc::new(Mutex::new(ScrubProgress::default())),
+        }
+    }
+
+    /// Scrub sealed data files every `interval` in a background thread,
+    /// reads are limited by the rate limiter of background IO.
+    #[allow(dead_code)]
+    pub fn scrub_in_background(&self, interval: Duration) {
+        if self.rate_limiter.is_none() {
+            error!("{}", not_supported("scrub"));
+            return;
+        }
+
+        let server = self.clone();
+        let scrub = move || loop {
+            thread::sleep(interval);
+            match server.scrub() {
+                Ok(report) if report.is_clean() => {
+                    info!("scrub {} data files, no corruption", report.total_files)
+                }
+                Ok(report) => error!(
+                    "scrub {} data files, found {} corrupted records, {} affected keys: {:?}",
+                    report.total_files,
+                    report.corrupted_records.len(),
+                    report.affected_keys.len(),
+                    report.affected_keys
+                ),
+                Err(e) => error!("failed to scrub data files: {}", e),
+            }
+        };
+        thread::Builder::new()
+            .name("tinkv-scrub".to_owned())
+            .spawn(scrub)
+            .expect("failed to spawn scrub thread");
+    }
+
+    /// Scrub sealed data files one by one. Records are read through
+    /// a separate file handle without holding the store, the store is
+    /// only locked to open each data file and to check the keydir.
+    fn scrub(&self) -> Result<ScrubReport> {
+        let not_supported = || not_supported("scrub");
+        let limiter = self.rate_limiter.as_ref().ok_or_else(not_supported)?;
+        let file_ids = match self.store().as_store() {
+            Some(store) => store.sealed_segments(),
+            None => return Err(not_supported()),
+        };
+        *self.scrub.lock().unwrap() = ScrubProgress {
+            in_progress: true,
+            total_files: file_ids.len() as u64,
+            ..Default::default()
+        };
+
+        let r = self.scrub_segments(&file_ids, limiter);
+        let mut progress = self.scrub.lock().unwrap();
+        progress.in_progress = false;
+        progress.current_file = None;
+        r
+    }
+
+    fn scrub_segments(&self, file_ids: &[u64], limiter: &RateLimiter) -> Result<ScrubReport> {
+        let not_supported = || not_supported("scrub");
+        let mut report = ScrubReport::default();
+        for &file_id in file_ids {
+            let scrub = match self.store().as_store() {
+                Some(store) => store.open_segment_scrub(file_id),
+                None => return Err(not_supported()),
+            };
+            let mut scrub = match scrub {
+                Ok(scrub) => scrub,
+                // removed by compaction in the meantime.
+                Err(TinkvError::SegmentNotFound(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            self.scrub.lock().unwrap().current_file = Some(file_id);
+
+            scrub.verify_records(|size| limiter.acquire(size))?;
+            let r = match self.store().as_store() {
+                Some(store) => scrub.finish(store),
+                None => return Err(not_supported()),
+            };
+
+            let mut progress = self.scrub.lock().unwrap();
+            progress.scrubbed_files += 1;
+            progress.corrupted_records += r.corrupted_records.len() as u64;
+            progress.affected_keys += r.affected_keys.len() as u64;
+            drop(progress);
+            report.merge(r);
+        }
+        Ok(report)
+    }
+
+    /// Compact data files step by step, the store is released
+    /// while waiting for the rate limiter of background IO.
+    fn compact(&self) -> Result<()> {
+        let limiter = match self.rate_limiter.as_ref() {
+            Some(limiter) => limiter,
+            None => return self.store().compact(),
+        };
+        let not_supported = || not_supported("compaction");
+
+        match self.store().as_store() {
+            Some(store) => store.begin_compaction()?,
+            None => return Err(not_supported()),
+        }
+        loop {
+            let r = match self.store().as_store() {
+                Some(store) => store.compaction_step(config::COMPACTION_STEP_SIZE),
+                None => return Err(not_supported()),
+            };
+            match r {
+                Ok(Some(size)) => limiter.acquire(size),
+                Ok(None) => break,
+                Err(e) => {
+                    if let Some(store) = self.store().as_store() {
+                        store.abort_compaction();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        match self.store().as_store() {
+            Some(store) => store.finish_compaction(),
+            None => Err(not_supported()),
+        }
     }
 
     pub fn run<A: ToSocketAddrs>(&mut self, addr: A) -> Result<()> {
@@ -39,9 +260,12 @@ impl Server {
         for stream in listener.incoming() {
             match stream {
                 Ok(stream) => {
-                    if let Err(e) = self.serve(stream) {
-                        error!("{}", e);
-                    }
+                    let mut server = self.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = server.serve(stream) {
+                            error!("{}", e);
+                        }
+                    });
                 }
                 Err(e) => error!("{}", e),
             }
@@ -57,7 +281,29 @@ impl Server {
         let mut conn = Conn::new(writer);
 
         for value in deserialize_from_reader(reader) {
-            self.handle_request(&mut conn, Request::try_from(value?)?)?;
+            let req = Request::try_from(value?)?;
+            if req.name == "sync" {
+                if self.store().as_store().is_none() {
+                    let msg = format!("{}", not_supported("replication"));
+                    conn.write_value(Value::new_error("ERR", &msg))?;
+                    conn.flush()?;
+                    continue;
+                }
+                let offset = req
+                    .argv()
+                    .first()
+                    .map(|offset| to_utf8_string(offset).parse::<u64>())
+                    .transpose()?;
+                // the connection is taken over by replication.
+                return serve_replica(
+                    &self.store,
+                    &self.replication,
+                    &peer_addr.to_string().replace(':', "-"),
+                    offset,
+                    conn.writer,
+                );
+            }
+            self.handle_request(&mut conn, req)?;
         }
 
         debug!("connection disconnected from {}", &peer_addr);
@@ -90,20 +336,37 @@ impl Server {
             };
         }
 
+        if WRITE_COMMANDS.contains(&req.name.as_ref())
+            && self.replication.lock().unwrap().primary.is_some()
+        {
+            conn.write_value(Value::new_error(
+                "READONLY",
+                "You can't write against a read only replica.",
+            ))?;
+            conn.flush()?;
+            return Ok(());
+        }
+
         match req.name.as_ref() {
             "ping" => send!(self.handle_ping(&argv)),
             "get" => send!(self.handle_get(&argv)),
             "mget" => send!(self.handle_mget(&argv)),
-            "set" => send!(self.handle_set(&argv)),
-            "mset" => send!(self.handle_mset(&argv)),
-            "del" => send!(self.handle_del(&argv)),
+            "set" => send!(self.durable(|s| s.handle_set(&argv))),
+            "setnx" => send!(self.durable(|s| s.handle_setnx(&argv))),
+            "mset" => send!(self.durable(|s| s.handle_mset(&argv))),
+            "del" => send!(self.durable(|s| s.handle_del(&argv))),
             "dbsize" => send!(self.handle_dbsize(&argv)),
             "exists" => send!(self.handle_exists(&argv)),
             "keys" => send!(self.handle_keys(&argv)),
-            "flushall" | "flushdb" => send!(self.handle_flush(req.name.as_ref(), &argv)),
+            "flushall" | "flushdb" => {
+                send!(self.durable(|s| s.handle_flush(req.name.as_ref(), &argv)))
+            }
             "compact" => send!(self.handle_compact(&argv)),
+            "debug" => send!(self.handle_debug(&argv)),
+            "config" => send!(self.handle_config(&argv)),
             "info" => send!(self.handle_info(&argv)),
             "command" => send!(self.handle_command(&argv)),
+            "replicaof" | "slaveof" => send!(self.handle_replicaof(req.name.as_ref(), &argv)),
             _ => {
                 conn.write_value(Value::new_error(
                     "ERR",
@@ -117,6 +380,31 @@ impl Server {
         Ok(())
     }
 
+    fn store(&self) -> MutexGuard<'_, E> {
+        self.store.lock().unwrap()
+    }
+
+    /// Return sequence number of the last write, `0` if the
+    /// engine is not backed by a datastore.
+    fn last_seq(&self) -> u64 {
+        self.store()
+            .as_store()
+            .map(|store| store.last_seq())
+            .unwrap_or_default()
+    }
+
+    /// Handle a write command, reply after its writes are persisted.
+    fn durable<F>(&mut self, f: F) -> Result<Value>
+    where
+        F: FnOnce(&mut Self) -> Result<Value>,
+    {
+        let value = f(self)?;
+        if let Some(commit) = self.commit.as_ref() {
+            commit.wait(self.last_seq())?;
+        }
+        Ok(value)
+    }
+
     fn handle_ping(&mut self, argv: &[&[u8]]) -> Result<Value> {
         match argv.len() {
             0 => Ok(Value::new_simple_string("PONG")),
@@ -131,7 +419,7 @@ impl Server {
         }
 
         Ok(self
-            .store
+            .store()
             .get(argv[0])?
             .map(Value::new_bulk_string)
             .unwrap_or_else(Value::new_null_bulk_string))
@@ -145,7 +433,7 @@ impl Server {
         let mut values = vec![];
         for arg in argv {
             let value = self
-                .store
+                .store()
                 .get(arg)?
                 .map(Value::new_bulk_string)
                 .unwrap_or_else(Value::new_null_bulk_string);
@@ -160,17 +448,42 @@ impl Server {
             return Err(TinkvError::resp_wrong_num_of_args("set"));
         }
 
-        match self.store.set(argv[0], argv[1]) {
-            Ok(()) => Ok(Value::new_simple_string("OK")),
-            Err(e) => Err(TinkvError::new_resp_common(
-                "INTERNALERR",
-                &format!("{}", e),
-            )),
+        // `NX`: only set the key if it does not exist.
+        // `XX`: only set the key if it already exists.
+        let mut present = None;
+        for arg in argv[2..].iter() {
+            match to_utf8_string(arg).to_ascii_lowercase().as_ref() {
+                "nx" if present.is_none() => present = Some(false),
+                "xx" if present.is_none() => present = Some(true),
+                _ => return Err(TinkvError::new_resp_common("ERR", "syntax error")),
+            }
+        }
+
+        let r = match present {
+            Some(present) => self.store().set_if(argv[0], argv[1], present),
+            None => self.store().set(argv[0], argv[1]).map(|()| true),
+        };
+
+        match r {
+            Ok(true) => Ok(Value::new_simple_string("OK")),
+            Ok(false) => Ok(Value::new_null_bulk_string()),
+            Err(e) => Err(write_error(e)),
+        }
+    }
+
+    fn handle_setnx(&mut self, argv: &[&[u8]]) -> Result<Value> {
+        if argv.len() != 2 {
+            return Err(TinkvError::resp_wrong_num_of_args("setnx"));
+        }
+
+        match self.store().set_if(argv[0], argv[1], false) {
+            Ok(saved) => Ok(Value::new_integer(saved as i64)),
+            Err(e) => Err(write_error(e)),
         }
     }
 
     fn handle_mset(&mut self, argv: &[&[u8]]) -> Result<Value> {
-        if argv.len() % 2 != 0 {
+        if !argv.len().is_multiple_of(2) {
             return Err(TinkvError::resp_wrong_num_of_args("mset"));
         }
 
@@ -180,11 +493,8 @@ impl Server {
                 break;
             }
 
-            if let Err(e) = self.store.set(argv[i], argv[i + 1]) {
-                return Err(TinkvError::new_resp_common(
-                    "INTERNALERR",
-                    &format!("{}", e),
-                ));
+            if let Err(e) = self.store().set(argv[i], argv[i + 1]) {
+                return Err(write_error(e));
             }
 
             i += 2;
@@ -198,7 +508,7 @@ impl Server {
             return Err(TinkvError::resp_wrong_num_of_args("del"));
         }
 
-        match self.store.remove(argv[0]) {
+        match self.store().remove(argv[0]) {
             Ok(()) => Ok(Value::new_simple_string("OK")),
             Err(e) => Err(TinkvError::new_resp_common(
                 "INTERNALERR",
@@ -212,7 +522,7 @@ impl Server {
             return Err(TinkvError::resp_wrong_num_of_args("dbsize"));
         }
 
-        Ok(Value::new_integer(self.store.len() as i64))
+        Ok(Value::new_integer(self.store().len() as i64))
     }
 
     fn handle_exists(&mut self, argv: &[&[u8]]) -> Result<Value> {
@@ -222,7 +532,7 @@ impl Server {
 
         let mut exists = 0;
         for arg in argv {
-            if self.store.contains_key(arg) {
+            if self.store().contains_key(arg) {
                 exists += 1;
             }
         }
@@ -240,9 +550,9 @@ impl Server {
         let mut keys = vec![];
 
         let pattern = pattern.map_err(|e| TinkvError::new_resp_common("ERR", &format!("{}", e)))?;
-        for key in self.store.keys() {
-            if pattern.matches(to_utf8_string(key).as_ref()) {
-                keys.push(Value::new_bulk_string(key.to_vec()));
+        for key in self.store().scan(b"") {
+            if pattern.matches(to_utf8_string(&key).as_ref()) {
+                keys.push(Value::new_bulk_string(key));
             };
         }
 
@@ -254,18 +564,9 @@ impl Server {
             return Err(TinkvError::resp_wrong_num_of_args(cmd));
         }
 
-        // too stupid, just in order to pass borrow checking.
-        // FIXME: find a better way to implement this feature?
-        let mut keys = vec![];
-        for key in self.store.keys() {
-            keys.push(key.clone());
-        }
-
-        for key in keys.iter() {
-            self.store
-                .remove(key.as_ref())
-                .map_err(|e| TinkvError::new_resp_common("INTERNALERR", &format!("{}", e)))?;
-        }
+        self.store()
+            .clear()
+            .map_err(|e| TinkvError::new_resp_common("INTERNALERR", &format!("{}", e)))?;
 
         Ok(Value::new_simple_string("OK"))
     }
@@ -275,7 +576,7 @@ impl Server {
             return Err(TinkvError::resp_wrong_num_of_args("compact"));
         }
 
-        match self.store.compact() {
+        match self.compact() {
             Ok(_) => Ok(Value::new_simple_string("OK")),
             Err(e) => Err(TinkvError::new_resp_common(
                 "INTERNALERR",
@@ -284,6 +585,131 @@ impl Server {
         }
     }
 
+    fn handle_debug(&mut self, argv: &[&[u8]]) -> Result<Value> {
+        if argv.len() != 1 {
+            return Err(TinkvError::resp_wrong_num_of_args("debug"));
+        }
+
+        match to_utf8_string(argv[0]).to_ascii_lowercase().as_ref() {
+            "scrub" => self.handle_debug_scrub(),
+            subcommand => Err(TinkvError::new_resp_common(
+                "ERR",
+                &format!("unknown subcommand `{}` of debug", subcommand),
+            )),
+        }
+    }
+
+    fn handle_debug_scrub(&mut self) -> Result<Value> {
+        if self.rate_limiter.is_none() {
+            return Err(TinkvError::new_resp_common(
+                "ERR",
+                &format!("{}", not_supported("scrub")),
+            ));
+        }
+        let report = self
+            .scrub()
+            .map_err(|e| TinkvError::new_resp_common("INTERNALERR", &format!("{}", e)))?;
+
+        let mut info = String::new();
+        info.push_str(&format!("total_files: {}\n", report.total_files));
+        info.push_str(&format!("total_records: {}\n", report.total_records));
+        info.push_str(&format!("size_of_files: {}\n", report.size_of_files));
+        info.push_str(&format!(
+            "corrupted_records: {}\n",
+            report.corrupted_records.len()
+        ));
+        info.push_str(&format!("affected_keys: {}\n", report.affected_keys.len()));
+        for r in report.corrupted_records.iter() {
+            info.push_str(&format!(
+                "corrupted_record: file_id={} offset={}\n",
+                r.file_id, r.offset
+            ));
+        }
+        for (keyspace, key) in report.affected_keys.iter() {
+            info.push_str(&format!(
+                "affected_key: {} {}\n",
+                keyspace,
+                to_utf8_string(key)
+            ));
+        }
+        Ok(Value::new_bulk_string(info.into_bytes()))
+    }
+
+    fn handle_config(&mut self, argv: &[&[u8]]) -> Result<Value> {
+        if argv.is_empty() {
+            return Err(TinkvError::resp_wrong_num_of_args("config"));
+        }
+
+        match to_utf8_string(argv[0]).to_ascii_lowercase().as_ref() {
+            "get" if argv.len() == 2 => self.handle_config_get(argv[1]),
+            "set" if argv.len() == 3 => self.handle_config_set(argv[1], argv[2]),
+            "get" | "set" => Err(TinkvError::resp_wrong_num_of_args("config")),
+            subcommand => Err(TinkvError::new_resp_common(
+                "ERR",
+                &format!("unknown subcommand `{}` of config", subcommand),
+            )),
+        }
+    }
+
+    /// Return current value of a config parameter, `None` if it's
+    /// not supported by the storage engine.
+    fn config_value(&self, name: &str) -> Option<String> {
+        match name {
+            "background-io-rate" => self
+                .rate_limiter
+                .as_ref()
+                .map(|limiter| limiter.rate().unwrap_or_default().to_string()),
+            _ => None,
+        }
+    }
+
+    fn handle_config_get(&mut self, pattern: &[u8]) -> Result<Value> {
+        let pattern = glob::Pattern::new(&to_utf8_string(pattern).to_ascii_lowercase())
+            .map_err(|e| TinkvError::new_resp_common("ERR", &format!("{}", e)))?;
+
+        let mut values = vec![];
+        for name in CONFIG_PARAMETERS
+            .iter()
+            .filter(|name| pattern.matches(name))
+        {
+            if let Some(value) = self.config_value(name) {
+                values.push(Value::new_bulk_string(name.as_bytes().to_vec()));
+                values.push(Value::new_bulk_string(value.into_bytes()));
+            }
+        }
+
+        Ok(Value::new_array(values))
+    }
+
+    fn handle_config_set(&mut self, name: &[u8], value: &[u8]) -> Result<Value> {
+        let name = to_utf8_string(name).to_ascii_lowercase();
+        let value = to_utf8_string(value);
+        let invalid = || {
+            TinkvError::new_resp_common(
+                "ERR",
+                &format!("invalid argument '{}' for CONFIG SET '{}'", &value, &name),
+            )
+        };
+
+        match name.as_ref() {
+            "background-io-rate" => {
+                // bytes per second, `0` means unlimited.
+                let rate = value.parse::<u64>().map_err(|_| invalid())?;
+                let limiter = self.rate_limiter.as_ref().ok_or_else(invalid)?;
+                info!("set background io rate to {} bytes/s", rate);
+                limiter.set_rate(Some(rate));
+            }
+            _ => {
+                return Err(TinkvError::new_resp_common(
+                    "ERR",
+                    &format!("unsupported CONFIG parameter: {}", &name),
+                ))
+            }
+        }
+
+        Ok(Value::new_simple_string("OK"))
+    }
+
     fn handle_info(&mut self, argv: &[&[u8]]) -> Result<Value> {
         let server_section = || {
             let mut info = String::new();
@@ -303,7 +729,7 @@ impl Server {
         let stats_section = || {
             let mut info = String::new();
             info.push_str("# Stats\n");
-            let stats = self.store.stats();
+            let stats = self.store().stats();
             info.push_str(&format!(
                 "size_of_stale_entries: {}\n",
                 stats.size_of_stale_entries
@@ -329,6 +755,127 @@ impl Server {
                 "size_of_all_data_files_human: {}\n",
                 bytefmt::format(stats.size_of_all_data_files)
             ));
+            info.push_str(&format!(
+                "total_evicted_keys: {}\n",
+                stats.total_evicted_keys
+            ));
+            info
+        };
+
+        let persistence_section = || {
+            let mut info = String::new();
+            info.push_str("# Persistence\n");
+            match self.commit.as_ref() {
+                Some(commit) => {
+                    info.push_str(&format!("sync_policy: {}\n", commit.policy()));
+                    info.push_str(&format!("total_syncs: {}\n", commit.total_syncs()));
+                    info.push_str(&format!("synced_seq: {}\n", commit.synced_seq()));
+                    info.push_str(&format!("last_seq: {}\n", self.last_seq()));
+                }
+                None => info.push_str("sync_policy: none\n"),
+            }
+            info
+        };
+
+        let replication_section = || {
+            let mut info = String::new();
+            info.push_str("# Replication\n");
+            let state = self.replication.lock().unwrap();
+            match state.primary.as_ref() {
+                Some((host, port)) => {
+                    info.push_str("role: slave\n");
+                    info.push_str(&format!("master_host: {}\n", host));
+                    info.push_str(&format!("master_port: {}\n", port));
+                    info.push_str(&format!(
+                        "master_link_status: {}\n",
+                        if state.link_up { "up" } else { "down" }
+                    ));
+                    info.push_str(&format!("slave_repl_offset: {}\n", state.offset));
+                    if let Some(error) = state.error.as_ref() {
+                        info.push_str(&format!("master_sync_error: {}\n", error));
+                    }
+                }
+                None => {
+                    info.push_str("role: master\n");
+                    info.push_str(&format!("connected_slaves: {}\n", state.connected_replicas));
+                    drop(state);
+                    info.push_str(&format!("master_repl_offset: {}\n", self.last_seq()));
+                }
+            }
+            info
+        };
+
+        let scrub_section = || {
+            let mut info = String::new();
+            info.push_str("# Scrub\n");
+            let progress = self.scrub.lock().unwrap();
+            info.push_str(&format!(
+                "scrub_in_progress: {}\n",
+                progress.in_progress as u8
+            ));
+            if let Some(file_id) = progress.current_file {
+                info.push_str(&format!("scrub_current_file: {}\n", file_id));
+            }
+            info.push_str(&format!("scrub_total_files: {}\n", progress.total_files));
+            info.push_str(&format!(
+                "scrub_scrubbed_files: {}\n",
+                progress.scrubbed_files
+            ));
+            info.push_str(&format!(
+                "scrub_corrupted_records: {}\n",
+                progress.corrupted_records
+            ));
+            info.push_str(&format!(
+                "scrub_affected_keys: {}\n",
+                progress.affected_keys
+            ));
+            info
+        };
+
+        let keyspace_section = || {
+            let mut info = String::new();
+            info.push_str("# Keyspace\n");
+            let mut engine = self.store();
+            let store = match engine.as_store() {
+                Some(store) => store,
+                None => {
+                    info.push_str(&format!("default:keys={}\n", engine.len()));
+                    return info;
+                }
+            };
+
+            let mut names = store.keyspace_names().cloned().collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                if let Ok(ks) = store.keyspace(&name) {
+                    let stats = ks.stats();
+                    info.push_str(&format!(
+                        "{}:keys={},size={}\n",
+                        name, stats.total_active_entries, stats.size_of_active_entries
+                    ));
+                }
+            }
+
+            let key_stats = store.key_stats();
+            for (name, histogram) in &[
+                ("key_size", &key_stats.key_sizes),
+                ("value_size", &key_stats.value_sizes),
+            ] {
+                for &p in &[50, 90, 99] {
+                    info.push_str(&format!(
+                        "{}_p{}: {}\n",
+                        name,
+                        p,
+                        histogram.percentile(p as f64)
+                    ));
+                }
+            }
+            info.push_str(&format!("total_prefixes: {}\n", key_stats.prefixes.len()));
+            let mut prefixes = key_stats.prefixes.iter().collect::<Vec<_>>();
+            prefixes.sort_by(|a, b| b.1.cmp(a.1));
+            for (prefix, count) in prefixes.iter().take(MAX_PREFIXES_IN_INFO) {
+                info.push_str(&format!("prefix:{}: {}\n", to_utf8_string(prefix), count));
+            }
             info
         };
 
@@ -338,6 +885,10 @@ impl Server {
             0 => {
                 info.push(server_section());
                 info.push(stats_section());
+                info.push(persistence_section());
+                info.push(replication_section());
+                info.push(scrub_section());
+                info.push(keyspace_section());
             }
             1 => match to_utf8_string(argv[0]).to_ascii_lowercase().as_ref() {
                 "server" => {
@@ -346,6 +897,18 @@ impl Server {
                 "stats" => {
                     info.push(stats_section());
                 }
+                "persistence" => {
+                    info.push(persistence_section());
+                }
+                "replication" => {
+                    info.push(replication_section());
+                }
+                "scrub" => {
+                    info.push(scrub_section());
+                }
+                "keyspace" => {
+                    info.push(keyspace_section());
+                }
                 _ => {}
             },
             _ => return Err(TinkvError::resp_wrong_num_of_args("info")),
@@ -354,6 +917,35 @@ impl Server {
         Ok(Value::new_bulk_string(info.join("\n").as_bytes().to_vec()))
     }
 
+    fn handle_replicaof(&mut self, cmd: &str, argv: &[&[u8]]) -> Result<Value> {
+        if argv.len() != 2 {
+            return Err(TinkvError::resp_wrong_num_of_args(cmd));
+        }
+
+        if self.store().as_store().is_none() {
+            return Err(TinkvError::new_resp_common(
+                "ERR",
+                &format!("{}", not_supported("replication")),
+            ));
+        }
+
+        let host = to_utf8_string(argv[0]);
+        let port = to_utf8_string(argv[1]);
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            info!("stop replication, turn into a primary");
+            self.replication.lock().unwrap().stop();
+            return Ok(Value::new_simple_string("OK"));
+        }
+
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| TinkvError::new_resp_common("ERR", "invalid master port"))?;
+        info!("turn into a replica of {}:{}", &host, port);
+        ReplicationState::replicate_from(&self.replication, &self.store, &host, port);
+
+        Ok(Value::new_simple_string("OK"))
+    }
+
     fn handle_command(&mut self, argv: &[&[u8]]) -> Result<Value> {
         if !argv.is_empty() {
             return Err(TinkvError::resp_wrong_num_of_args("command"));
@@ -445,3 +1037,411 @@ where
         self.writer.flush()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::MemoryEngine;
+    use crate::store::{AddOperator, OpenOptions};
+    use std::net::TcpListener;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    fn spawn_server(path: &std::path::Path) -> (Server, u16) {
+        spawn_server_with(Store::open(path).unwrap())
+    }
+
+    fn spawn_server_with<E: KvEngine>(engine: E) -> (Server<E>, u16) {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let server = Server::new(engine);
+        let mut s = server.clone();
+        thread::spawn(move || s.run(("127.0.0.1", port)));
+        (server, port)
+    }
+
+    fn call(port: u16, args: &[&str]) -> Value {
+        let begin_at = Instant::now();
+        let stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => break stream,
+                Err(e) if begin_at.elapsed() > Duration::from_secs(5) => panic!("{}", e),
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+
+        let req = Value::new_array(
+            args.iter()
+                .map(|arg| Value::new_bulk_string(arg.as_bytes().to_vec()))
+                .collect(),
+        );
+        serialize_to_writer(&mut BufWriter::new(&stream), &req).unwrap();
+        let mut values = deserialize_from_reader(BufReader::new(&stream));
+        values.next().unwrap().unwrap()
+    }
+
+    fn wait_until<F: FnMut() -> bool>(mut f: F) {
+        let begin_at = Instant::now();
+        while !f() {
+            assert!(begin_at.elapsed() < Duration::from_secs(10), "timeout");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_conditional_set() {
+        let tmpdir = TempDir::new().unwrap();
+        let (_, port) = spawn_server(tmpdir.path());
+
+        let value = call(port, &["set", "a", "1", "xx"]);
+        assert!(value.is_null_bulk_string());
+        let value = call(port, &["set", "a", "1", "nx"]);
+        assert_eq!(value.as_simple_string(), Some("OK"));
+        let value = call(port, &["set", "a", "2", "NX"]);
+        assert!(value.is_null_bulk_string());
+        let value = call(port, &["set", "a", "2", "XX"]);
+        assert_eq!(value.as_simple_string(), Some("OK"));
+        let value = call(port, &["set", "a", "2", "nx", "xx"]);
+        assert!(value.is_error());
+
+        assert_eq!(call(port, &["setnx", "a", "3"]).as_integer(), Some(0));
+        assert_eq!(call(port, &["setnx", "b", "3"]).as_integer(), Some(1));
+        let value = call(port, &["get", "a"]);
+        assert_eq!(value.as_bulk_string(), Some(&b"2"[..]));
+    }
+
+    #[test]
+    fn test_store_full() {
+        let tmpdir = TempDir::new().unwrap();
+        let store = crate::OpenOptions::new()
+            .max_keys(1)
+            .open(tmpdir.path())
+            .unwrap();
+        let (_, port) = spawn_server_with(store);
+
+        assert_eq!(
+            call(port, &["set", "a", "1"]).as_simple_string(),
+            Some("OK")
+        );
+        assert_eq!(
+            call(port, &["set", "a", "2"]).as_simple_string(),
+            Some("OK")
+        );
+        match call(port, &["set", "b", "1"]) {
+            Value::Error { name, .. } => assert_eq!(name, "OOM"),
+            v => panic!("unexpected reply {}", v),
+        }
+        assert_eq!(call(port, &["dbsize"]).as_integer(), Some(1));
+    }
+
+    #[test]
+    fn test_debug_scrub() {
+        let tmpdir = TempDir::new().unwrap();
+        let (server, port) = spawn_server(tmpdir.path());
+        server.scrub_in_background(Duration::from_millis(10));
+
+        call(port, &["mset", "a", "1", "b", "2"]);
+        call(port, &["compact"]);
+        let value = call(port, &["debug", "scrub"]);
+        let info = to_utf8_string(value.as_bulk_string().unwrap());
+        // a sequence number mark is written by compaction.
+        assert!(info.contains("total_records: 3\n"));
+        assert!(info.contains("corrupted_records: 0\n"));
+        assert!(call(port, &["debug", "foo"]).is_error());
+
+        let (_, port) = spawn_server_with(MemoryEngine::new());
+        assert!(call(port, &["debug", "scrub"]).is_error());
+    }
+
+    #[test]
+    fn test_config() {
+        let tmpdir = TempDir::new().unwrap();
+        let store = crate::OpenOptions::new()
+            .background_io_rate(1 << 20)
+            .open(tmpdir.path())
+            .unwrap();
+        let (server, port) = spawn_server_with(store);
+
+        let value = call(port, &["config", "get", "background-io-rate"]);
+        let values = value.as_array().unwrap();
+        assert_eq!(values[0].as_bulk_string(), Some(&b"background-io-rate"[..]));
+        assert_eq!(values[1].as_bulk_string(), Some(&b"1048576"[..]));
+
+        let value = call(port, &["config", "set", "background-io-rate", "4096"]);
+        assert_eq!(value.as_simple_string(), Some("OK"));
+        assert_eq!(server.rate_limiter.as_ref().unwrap().rate(), Some(4096));
+        let value = call(port, &["config", "get", "background-*"]);
+        assert_eq!(
+            value.as_array().unwrap()[1].as_bulk_string(),
+            Some(&b"4096"[..])
+        );
+        call(port, &["config", "set", "background-io-rate", "0"]);
+        assert_eq!(server.rate_limiter.as_ref().unwrap().rate(), None);
+
+        assert!(call(port, &["config", "set", "background-io-rate", "x"]).is_error());
+        assert!(call(port, &["config", "set", "foo", "1"]).is_error());
+        assert!(call(port, &["config", "get"]).is_error());
+        let value = call(port, &["config", "get", "foo"]);
+        assert_eq!(value.as_array().map(|values| values.len()), Some(0));
+    }
+
+    #[test]
+    fn test_compact_without_blocking_clients() {
+        let tmpdir = TempDir::new().unwrap();
+        let store = crate::OpenOptions::new()
+            .background_io_rate(1)
+            .open(tmpdir.path())
+            .unwrap();
+        let (server, port) = spawn_server_with(store);
+        let value = "x".repeat(1024);
+        for i in 0..100 {
+            call(port, &["set", &i.to_string(), &value]);
+        }
+
+        // it takes days to compact at 1 byte per second.
+        let compaction = thread::spawn(move || call(port, &["compact"]));
+        thread::sleep(Duration::from_millis(100));
+        let begin_at = Instant::now();
+        call(port, &["set", "0", "y"]);
+        call(port, &["del", "1"]);
+        assert_eq!(call(port, &["get", "0"]).as_bulk_string(), Some(&b"y"[..]));
+        assert!(begin_at.elapsed() < Duration::from_secs(1));
+
+        call(port, &["config", "set", "background-io-rate", "0"]);
+        let value = compaction.join().unwrap();
+        assert_eq!(value.as_simple_string(), Some("OK"));
+        let mut store = server.store();
+        assert_eq!(store.get(b"0").unwrap(), Some(b"y".to_vec()));
+        assert!(!store.contains_key(b"1"));
+        assert_eq!(store.stats().total_active_entries, 99);
+    }
+
+    #[test]
+    fn test_scrub_without_blocking_clients() {
+        let tmpdir = TempDir::new().unwrap();
+        let store = crate::OpenOptions::new()
+            .background_io_rate(1)
+            .max_data_file_size(4096)
+            .open(tmpdir.path())
+            .unwrap();
+        let (_, port) = spawn_server_with(store);
+        let value = "x".repeat(1024);
+        for i in 0..10 {
+            call(port, &["set", &i.to_string(), &value]);
+        }
+
+        // records are read at 1 byte per second.
+        let scrub = thread::spawn(move || call(port, &["debug", "scrub"]));
+        wait_until(|| {
+            let info = call(port, &["info", "scrub"]);
+            to_utf8_string(info.as_bulk_string().unwrap()).contains("scrub_in_progress: 1\n")
+        });
+        let begin_at = Instant::now();
+        call(port, &["set", "0", "y"]);
+        assert_eq!(call(port, &["get", "0"]).as_bulk_string(), Some(&b"y"[..]));
+        assert!(begin_at.elapsed() < Duration::from_secs(1));
+        let info = call(port, &["info", "scrub"]);
+        let info = to_utf8_string(info.as_bulk_string().unwrap());
+        assert!(info.starts_with("# Scrub\n"));
+        assert!(info.contains("scrub_current_file: "));
+        assert!(info.contains("scrub_scrubbed_files: 0\n"));
+
+        call(port, &["config", "set", "background-io-rate", "0"]);
+        let value = scrub.join().unwrap();
+        let report = to_utf8_string(value.as_bulk_string().unwrap());
+        assert!(report.contains("corrupted_records: 0\n"));
+        let info = call(port, &["info", "scrub"]);
+        let info = to_utf8_string(info.as_bulk_string().unwrap());
+        assert!(info.contains("scrub_in_progress: 0\n"));
+        assert!(!info.contains("scrub_current_file: "));
+        assert!(info.contains("scrub_total_files: 2\n"));
+        assert!(info.contains("scrub_scrubbed_files: 2\n"));
+        assert!(info.contains("scrub_corrupted_records: 0\n"));
+    }
+
+    #[test]
+    fn test_memory_engine() {
+        let (server, port) = spawn_server_with(MemoryEngine::new());
+
+        call(port, &["mset", "a", "1", "b", "2"]);
+        assert_eq!(call(port, &["dbsize"]).as_integer(), Some(2));
+        let value = call(port, &["get", "a"]);
+        assert_eq!(value.as_bulk_string(), Some(&b"1"[..]));
+        let value = call(port, &["keys", "*"]);
+        assert_eq!(value.as_array().map(|keys| keys.len()), Some(2));
+        assert_eq!(call(port, &["setnx", "a", "3"]).as_integer(), Some(0));
+
+        assert_eq!(call(port, &["del", "a"]).as_simple_string(), Some("OK"));
+        assert!(!server.store().contains_key(b"a"));
+        assert_eq!(call(port, &["compact"]).as_simple_string(), Some("OK"));
+
+        let info = call(port, &["info", "persistence"]);
+        let info = to_utf8_string(info.as_bulk_string().unwrap());
+        assert!(info.contains("sync_policy: none"));
+        assert!(call(port, &["replicaof", "127.0.0.1", "7379"]).is_error());
+        assert!(call(port, &["sync"]).is_error());
+        assert!(call(port, &["debug", "scrub"]).is_error());
+        let value = call(port, &["config", "get", "*"]);
+        assert_eq!(value.as_array().map(|values| values.len()), Some(0));
+        let info = call(port, &["info", "keyspace"]);
+        let info = to_utf8_string(info.as_bulk_string().unwrap());
+        assert!(info.contains("default:keys=1\n"));
+
+        assert_eq!(call(port, &["flushdb"]).as_simple_string(), Some("OK"));
+        assert!(server.store().is_empty());
+    }
+
+    #[test]
+    fn test_info_keyspace() {
+        let tmpdir = TempDir::new().unwrap();
+        let (server, port) = spawn_server(tmpdir.path());
+        call(
+            port,
+            &["mset", "user:1", "abc", "user:2", "abcdef", "post:1", "a"],
+        );
+        server
+            .store()
+            .as_store()
+            .unwrap()
+            .keyspace("sessions")
+            .unwrap()
+            .set(b"token", b"abc")
+            .unwrap();
+
+        let info = call(port, &["info", "keyspace"]);
+        let info = to_utf8_string(info.as_bulk_string().unwrap());
+        assert!(info.starts_with("# Keyspace\n"));
+        assert!(info.contains("default:keys=3,"));
+        assert!(info.contains("sessions:keys=1,"));
+        assert!(info.contains("key_size_p50: 7\n"));
+        assert!(info.contains("total_prefixes: 3\n"));
+        assert!(info.contains("prefix:user: 2\n"));
+        let info = call(port, &["info"]);
+        assert!(to_utf8_string(info.as_bulk_string().unwrap()).contains("# Keyspace\n"));
+
+        let (_, port) = spawn_server_with(MemoryEngine::new());
+        call(port, &["set", "a", "1"]);
+        let info = call(port, &["info", "keyspace"]);
+        let info = to_utf8_string(info.as_bulk_string().unwrap());
+        assert!(info.contains("default:keys=1\n"));
+    }
+
+    #[test]
+    fn test_replication() {
+        let primary_dir = TempDir::new().unwrap();
+        let replica_dir = TempDir::new().unwrap();
+        let (primary, primary_port) = spawn_server(primary_dir.path());
+        let (replica, replica_port) = spawn_server(replica_dir.path());
+
+        call(primary_port, &["set", "a", "1"]);
+        call(primary_port, &["set", "b", "2"]);
+        call(replica_port, &["set", "x", "0"]);
+
+        let port = primary_port.to_string();
+        let value = call(replica_port, &["replicaof", "127.0.0.1", &port]);
+        assert_eq!(value.as_simple_string(), Some("OK"));
+
+        // full snapshot replaces data of replica.
+        wait_until(|| replica.store().get(b"a").unwrap() == Some(b"1".to_vec()));
+        assert!(!replica.store().contains_key(b"x"));
+
+        // new writes are tailed.
+        call(primary_port, &["set", "c", "3"]);
+        call(primary_port, &["del", "a"]);
+        wait_until(|| !replica.store().contains_key(b"a"));
+        assert_eq!(replica.store().get(b"c").unwrap(), Some(b"3".to_vec()));
+
+        // so are keyspaces dropped.
+        primary
+            .store()
+            .keyspace("users")
+            .unwrap()
+            .set(b"k", b"v")
+            .unwrap();
+        wait_until(|| replica.store().keyspace_names().any(|name| name == "users"));
+        primary.store().drop_keyspace("users").unwrap();
+        wait_until(|| replica.store().keyspace_names().all(|name| name != "users"));
+
+        let info = call(replica_port, &["info", "replication"]);
+        let info = to_utf8_string(info.as_bulk_string().unwrap());
+        assert!(info.contains("role: slave"));
+        assert!(info.contains("master_link_status: up"));
+
+        let value = call(replica_port, &["set", "d", "4"]);
+        assert!(value.is_error());
+
+        call(replica_port, &["replicaof", "no", "one"]);
+        let value = call(replica_port, &["set", "d", "4"]);
+        assert_eq!(value.as_simple_string(), Some("OK"));
+    }
+
+    #[test]
+    fn test_partial_sync() {
+        let tmpdir = TempDir::new().unwrap();
+        let (primary, port) = spawn_server(tmpdir.path());
+        call(port, &["set", "a", "1"]);
+        call(port, &["set", "b", "2"]);
+
+        let sync = |args: &[&str]| {
+            let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let req = Value::new_array(
+                args.iter()
+                    .map(|arg| Value::new_bulk_string(arg.as_bytes().to_vec()))
+                    .collect(),
+            );
+            serialize_to_writer(&mut BufWriter::new(&stream), &req).unwrap();
+            let mut values = deserialize_from_reader(BufReader::new(stream));
+            // the first one tells merge operator of primary.
+            values.next().unwrap().unwrap();
+            values.map(|value| {
+                let value = value.unwrap();
+                let values = value.as_array().unwrap();
+                (
+                    to_utf8_string(values[0].as_bulk_string().unwrap()),
+                    values.get(1).cloned(),
+                )
+            })
+        };
+
+        // changes after offset are replayed.
+        let mut messages = sync(&["sync", "1"]);
+        let (kind, seq) = messages.next().unwrap();
+        assert_eq!(kind, "continue");
+        assert_eq!(seq.and_then(|v| v.as_integer()), Some(1));
+        let (kind, seq) = messages.next().unwrap();
+        assert_eq!(kind, "put");
+        assert_eq!(seq.and_then(|v| v.as_integer()), Some(2));
+
+        // a snapshot is sent if they are dropped by compaction.
+        primary.store().compact().unwrap();
+        assert_eq!(sync(&["sync", "1"]).next().unwrap().0, "file");
+        assert_eq!(sync(&["sync"]).next().unwrap().0, "file");
+        let last_seq = primary.last_seq().to_string();
+        assert_eq!(sync(&["sync", &last_seq]).next().unwrap().0, "continue");
+    }
+
+    #[test]
+    fn test_replication_without_merge_operator() {
+        let primary_dir = TempDir::new().unwrap();
+        let replica_dir = TempDir::new().unwrap();
+        let primary = OpenOptions::new()
+            .merge_operator(AddOperator)
+            .open(primary_dir.path())
+            .unwrap();
+        let (_, primary_port) = spawn_server_with(primary);
+        let (_, replica_port) = spawn_server(replica_dir.path());
+
+        let port = primary_port.to_string();
+        call(replica_port, &["replicaof", "127.0.0.1", &port]);
+        let info = || {
+            let info = call(replica_port, &["info", "replication"]);
+            to_utf8_string(info.as_bulk_string().unwrap())
+        };
+        wait_until(|| info().contains("master_sync_error: "));
+        assert!(info().contains("merge operator 'add', but replica uses none"));
+        assert!(info().contains("master_link_status: down"));
+    }
+}
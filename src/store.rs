@@ -1,16 +1,52 @@
 //! A simple key-value store.
+use crate::backup::{Manifest, ManifestFile};
 use crate::config;
 use crate::error::{Result, TinkvError};
-use crate::segment::{DataEntry, DataFile, HintFile};
-use glob::glob;
-use log::{debug, info, trace};
+use crate::segment::{DataEntry, DataFile, HintFile, RecordKind, HEADER_SIZE};
+use crate::util::current_millis;
+use crate::vfs::{DiskFs, Vfs};
+use log::{debug, info, trace, warn};
 use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::fs::create_dir_all;
+use std::io;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{mpsc, Arc};
 use std::time;
 
 use std::path::{Path, PathBuf};
 
+mod cas;
+mod cdc;
+mod commit;
+mod compaction;
+mod evict;
+mod export;
+mod history;
+mod key_stats;
+mod keyspace;
+mod merge;
+mod rate_limit;
+mod replica;
+mod scrub;
+mod segments;
+
+pub use cas::CompareAndSwapError;
+pub use cdc::{ChangeEvent, ChangeKind, Subscription};
+pub use commit::{CommitHandle, SyncPolicy};
+use compaction::Compaction;
+pub use evict::EvictionPolicy;
+use evict::EvictionState;
+pub use export::{Encoding, ExportOptions, Format, RdbImportStats};
+pub use history::Version;
+pub use key_stats::{KeyStats, SizeHistogram};
+use keyspace::KeyspaceState;
+pub use keyspace::{Keyspace, KeyspaceOptions, KeyspaceStats};
+pub use merge::{AddOperator, AppendOperator, MergeOperator};
+pub use rate_limit::RateLimiter;
+pub use scrub::{CorruptedRecord, ScrubReport, SegmentScrub};
+pub use segments::SegmentStats;
+
 /// The `Store` stores key/value pairs.
 ///
 /// Key/value pairs are persisted in data files.
@@ -22,47 +58,191 @@ pub struct Store {
     data_files: HashMap<u64, DataFile>,
     // only active data file is writeable.
     active_data_file: Option<DataFile>,
-    // keydir maintains key value index for fast query.
-    keydir: BTreeMap<Vec<u8>, KeyDirEntry>,
+    // each keyspace maintains its own keydir (the in-memory index),
+    // all of them share the same data files.
+    keyspaces: HashMap<String, KeyspaceState>,
+    // sequence number of the last write.
+    seq: u64,
+    // sequence number of the last compaction, changes up to
+    // it may be dropped from data files.
+    compacted_seq: u64,
+    // senders of change data capture subscriptions.
+    subscribers: Vec<mpsc::SyncSender<ChangeEvent>>,
     /// monitor tinkv store status, record statistics data.
     stats: Stats,
     /// store config.
     config: Config,
+    // folds merge operands onto base values.
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    // syncs durable writes in batches.
+    commit: CommitHandle,
+    // writers wait for their records to be persisted by themselves.
+    defer_commits: bool,
+    // filesystem of segment files.
+    vfs: Arc<dyn Vfs>,
+    // eviction order of keys, only if datastore has
+    // limits and an eviction policy.
+    eviction: Option<EvictionState>,
+    // limits IO of compaction, hint generation and scrubbing.
+    rate_limiter: RateLimiter,
+    // distribution of live keys.
+    key_stats: KeyStats,
+    // ongoing compaction, it's copying entries step by step.
+    compaction: Option<Compaction>,
+}
+
+/// State of a datastore built from its data files, see `Store::refresh`.
+#[derive(Default)]
+struct IndexState {
+    data_files: HashMap<u64, DataFile>,
+    keyspaces: HashMap<String, KeyspaceState>,
+    seq: u64,
+    compacted_seq: u64,
+    stats: Stats,
+    eviction: Option<EvictionState>,
+    key_stats: KeyStats,
 }
 
 impl Store {
     /// Initialize key value store with the given path.
     /// If the given path not found, a new one will be created.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        Self::open_with_options(path, Config::default())
+        Self::open_with_options(path, &OpenOptions::default())
     }
 
     /// Open datasotre directory with custom options.
-    fn open_with_options<P: AsRef<Path>>(path: P, config: Config) -> Result<Self> {
+    fn open_with_options<P: AsRef<Path>>(path: P, options: &OpenOptions) -> Result<Self> {
         info!("open store path: {}", path.as_ref().display());
-        create_dir_all(&path)?;
+        let config = options.config;
+        let vfs = options.vfs.clone().unwrap_or_else(|| Arc::new(DiskFs));
+        if config.read_only {
+            // directory must exist, nothing is created.
+            vfs.read_dir(path.as_ref())?;
+        } else {
+            vfs.create_dir_all(path.as_ref())?;
+        }
         let mut store = Store {
             path: path.as_ref().to_path_buf(),
             data_files: HashMap::new(),
             active_data_file: None,
-            keydir: BTreeMap::new(),
+            keyspaces: HashMap::new(),
+            seq: 0,
+            compacted_seq: 0,
+            subscribers: vec![],
             stats: Stats::default(),
             config,
+            merge_operator: options.merge_operator.clone(),
+            commit: CommitHandle::new(config.sync_policy),
+            defer_commits: false,
+            vfs,
+            eviction: config.eviction_state(),
+            rate_limiter: RateLimiter::new(config.background_io_rate),
+            key_stats: KeyStats::default(),
+            compaction: None,
         };
 
         store.open_data_files()?;
         store.build_keydir()?;
-        store.new_active_data_file(None)?;
+        store
+            .keyspaces
+            .entry(config::DEFAULT_KEYSPACE.to_owned())
+            .or_default();
+        if !config.read_only {
+            store.new_active_data_file(None)?;
+        }
 
         Ok(store)
     }
 
+    /// Reload data files of a read-only datastore, so that writes made
+    /// by another process (e.g. a running server) since opening, and
+    /// its compactions, become visible.
+    pub fn refresh(&mut self) -> Result<()> {
+        if !self.config.read_only {
+            return Err(TinkvError::Custom(
+                "only read-only datastore can be refreshed".to_owned(),
+            ));
+        }
+
+        // the datastore is rebuilt from scratch, the current state is
+        // put back if it fails, so readers never see an empty datastore.
+        let mut retries = 0;
+        loop {
+            let fresh = IndexState {
+                eviction: self.config.eviction_state(),
+                ..Default::default()
+            };
+            let old = self.swap_index_state(fresh);
+            match self.open_data_files().and_then(|_| self.build_keydir()) {
+                Ok(()) => {
+                    // keyspace options are kept.
+                    self.keyspaces
+                        .entry(config::DEFAULT_KEYSPACE.to_owned())
+                        .or_default();
+                    for (name, ks) in old.keyspaces {
+                        if let Some(state) = self.keyspaces.get_mut(&name) {
+                            state.options = ks.options;
+                        }
+                    }
+                    return Ok(());
+                }
+                Err(e) => {
+                    self.swap_index_state(old);
+                    // a data file is removed by a compaction of the writer
+                    // in the meantime, its entries are found in new files.
+                    let vanished =
+                        matches!(&e, TinkvError::Io(e) if e.kind() == io::ErrorKind::NotFound);
+                    if !vanished || retries == config::REFRESH_RETRIES {
+                        return Err(e);
+                    }
+                    retries += 1;
+                    warn!("data files changed while refreshing, retry: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Replace data files and keydirs of the datastore, return the
+    /// replaced ones.
+    fn swap_index_state(&mut self, mut state: IndexState) -> IndexState {
+        std::mem::swap(&mut self.data_files, &mut state.data_files);
+        std::mem::swap(&mut self.keyspaces, &mut state.keyspaces);
+        std::mem::swap(&mut self.seq, &mut state.seq);
+        std::mem::swap(&mut self.compacted_seq, &mut state.compacted_seq);
+        std::mem::swap(&mut self.stats, &mut state.stats);
+        std::mem::swap(&mut self.eviction, &mut state.eviction);
+        std::mem::swap(&mut self.key_stats, &mut state.key_stats);
+        state
+    }
+
+    /// Return error if datastore is opened in read-only mode.
+    fn check_writeable(&self) -> Result<()> {
+        if self.config.read_only {
+            return Err(TinkvError::ReadOnly);
+        }
+        Ok(())
+    }
+
     /// Open data files (they are immutable).
     fn open_data_files(&mut self) -> Result<()> {
-        let pattern = format!("{}/*{}", self.path.display(), config::DATA_FILE_SUFFIX);
-        trace!("read data files with pattern: {}", &pattern);
-        for path in glob(&pattern)? {
-            let df = DataFile::new(path?.as_path(), false)?;
+        trace!("read data files in {}", self.path.display());
+        for path in self.vfs.read_dir(&self.path)? {
+            let is_data_file = path
+                .file_name()
+                .map(|name| name.to_string_lossy().ends_with(config::DATA_FILE_SUFFIX))
+                .unwrap_or(false);
+            if !is_data_file {
+                continue;
+            }
+
+            let df = match DataFile::new(self.vfs.clone(), &path, false) {
+                Ok(df) => df,
+                Err(TinkvError::UnknownFile(path)) => {
+                    warn!("skip unknown file {} in datastore", path.display());
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
 
             self.stats.total_data_files += 1;
             self.stats.size_of_all_data_files += df.size;
@@ -71,6 +251,61 @@ impl Store {
         }
         trace!("got {} immutable data files", self.data_files.len());
 
+        self.discard_unfinished_compaction()
+    }
+
+    /// Remove (or skip in read-only mode) data files of a compaction which
+    /// didn't finish. They hold copies of entries still present in the old
+    /// data files, and old versions which must not override live entries.
+    fn discard_unfinished_compaction(&mut self) -> Result<()> {
+        // compaction data files come with hint files, the ones of a compaction
+        // have consecutive ids (compactions are separated by the id of their
+        // active data file), and the last one ends with a sequence number mark.
+        let mut file_ids = self
+            .data_files
+            .keys()
+            .cloned()
+            .filter(|&id| self.vfs.exists(&segment_hint_file_path(&self.path, id)))
+            .collect::<Vec<_>>();
+        file_ids.sort_unstable();
+        let mut runs: Vec<Vec<u64>> = vec![];
+        for file_id in file_ids {
+            match runs.last_mut() {
+                Some(run) if run.last() == Some(&(file_id - 1)) => run.push(file_id),
+                _ => runs.push(vec![file_id]),
+            }
+        }
+
+        for run in runs {
+            let last = run[run.len() - 1];
+            let path = segment_hint_file_path(&self.path, last);
+            let mut hint_file = HintFile::new(self.vfs.clone(), &path, false)?;
+            let finished = matches!(
+                hint_file.entry_iter().last(),
+                Some(Ok(entry)) if entry.kind == RecordKind::SeqMark
+            );
+            if finished {
+                continue;
+            }
+
+            for file_id in run {
+                let df = match self.data_files.remove(&file_id) {
+                    Some(df) => df,
+                    None => continue,
+                };
+                warn!(
+                    "skip data file {} of an unfinished compaction",
+                    df.path.display()
+                );
+                self.stats.total_data_files -= 1;
+                self.stats.size_of_all_data_files -= df.size;
+                if !self.config.read_only {
+                    self.vfs.remove_file(&df.path)?;
+                    self.vfs
+                        .remove_file(&segment_hint_file_path(&self.path, file_id))?;
+                }
+            }
+        }
         Ok(())
     }
 
@@ -84,22 +319,20 @@ impl Store {
 
         for file_id in file_ids {
             let hint_file_path = segment_hint_file_path(&self.path, file_id);
-            if hint_file_path.exists() {
+            if self.vfs.exists(&hint_file_path) {
                 self.build_keydir_from_hint_file(&hint_file_path)?;
             } else {
                 self.build_keydir_from_data_file(file_id)?;
             }
         }
 
-        // update stats.
-        self.stats.total_active_entries = self.keydir.len() as u64;
-
         let duration = time::Instant::now().duration_since(begin_at);
 
         info!(
-            "build keydir in {:?}, got {} keys. current stats: {:?}",
+            "build keydir in {:?}, got {} keys in {} keyspaces. current stats: {:?}",
             duration,
-            self.keydir.len(),
+            self.stats.total_active_entries,
+            self.keyspaces.len(),
             self.stats
         );
         Ok(())
@@ -107,47 +340,76 @@ impl Store {
 
     fn build_keydir_from_hint_file(&mut self, path: &Path) -> Result<()> {
         trace!("build keydir from hint file {}", path.display());
-        let mut hint_file = HintFile::new(path, false)?;
+        let mut hint_file = HintFile::new(self.vfs.clone(), path, false)?;
         let hint_file_id = hint_file.id;
 
         for entry in hint_file.entry_iter() {
-            let keydir_ent = KeyDirEntry::new(hint_file_id, entry.offset, entry.size);
-            let old = self.keydir.insert(entry.key, keydir_ent);
-            if let Some(old_ent) = old {
-                self.stats.size_of_stale_entries += old_ent.size;
-                self.stats.total_stale_entries += 1;
+            let entry = entry?;
+            self.seq = self.seq.max(entry.seq);
+            match entry.kind {
+                RecordKind::SeqMark => {
+                    self.compacted_seq = self.compacted_seq.max(entry.seq);
+                }
+                // tomestones of old versions retained by compaction.
+                RecordKind::Remove => {
+                    self.mark_stale(&entry.keyspace, hint_file_id, entry.size);
+                    self.unindex(&entry.keyspace, &entry.key);
+                }
+                _ => {
+                    let keydir_ent = KeyDirEntry::new(
+                        hint_file_id,
+                        entry.offset,
+                        entry.size,
+                        entry.seq,
+                        entry.expires_at,
+                    );
+                    self.index(&entry.keyspace, entry.key, keydir_ent);
+                }
             }
         }
         Ok(())
     }
 
     fn build_keydir_from_data_file(&mut self, file_id: u64) -> Result<()> {
-        let df = self.data_files.get(&file_id).unwrap();
+        let df = self
+            .data_files
+            .get(&file_id)
+            .ok_or(TinkvError::SegmentNotFound(file_id))?;
         info!("build keydir from data file {}", df.path.display());
-        for entry in df.entry_iter() {
+        for entry in df.entry_iter()? {
+            let entry = entry?;
             if !entry.is_valid() {
                 return Err(TinkvError::DataEntryCorrupted {
-                    file_id: df.id,
+                    file_id,
                     key: entry.key().into(),
                     offset: entry.offset,
                 });
             }
 
-            if entry.value() == config::REMOVE_TOMESTONE {
-                trace!("{} is a remove tomestone", &entry);
-                self.stats.total_stale_entries += 1;
-                self.stats.size_of_stale_entries += entry.size;
-
-                if let Some(old_ent) = self.keydir.remove(entry.key()) {
-                    self.stats.size_of_stale_entries += old_ent.size;
-                    self.stats.total_stale_entries += 1;
+            self.seq = self.seq.max(entry.seq());
+            let keydir_ent = KeyDirEntry::new(
+                file_id,
+                entry.offset,
+                entry.size,
+                entry.seq(),
+                entry.expires_at(),
+            );
+            match entry.kind() {
+                RecordKind::Put => self.index(entry.keyspace(), entry.key().into(), keydir_ent),
+                RecordKind::Merge => {
+                    self.index_operand(entry.keyspace(), entry.key().into(), keydir_ent)
                 }
-            } else {
-                let keydir_ent = KeyDirEntry::new(file_id, entry.offset, entry.size);
-                let old = self.keydir.insert(entry.key().into(), keydir_ent);
-                if let Some(old_ent) = old {
-                    self.stats.size_of_stale_entries += old_ent.size;
-                    self.stats.total_stale_entries += 1;
+                RecordKind::Remove => {
+                    self.mark_stale(entry.keyspace(), file_id, entry.size);
+                    self.unindex(entry.keyspace(), entry.key());
+                }
+                RecordKind::DropKeyspace => {
+                    self.unindex_keyspace(entry.keyspace());
+                    self.mark_stale(entry.keyspace(), file_id, entry.size);
+                }
+                RecordKind::SeqMark => {
+                    trace!("{} is a sequence number mark", &entry);
+                    self.compacted_seq = self.compacted_seq.max(entry.seq());
                 }
             }
         }
@@ -159,82 +421,241 @@ impl Store {
         let next_file_id: u64 =
             file_id.unwrap_or_else(|| self.data_files.keys().max().unwrap_or(&0) + 1);
 
+        // sync data to disk, pending commits are acknowledged by it.
+        if let Some(df) = self.active_data_file.as_mut() {
+            df.sync()?;
+        }
+
         // build data file path.
         let p = segment_data_file_path(&self.path, next_file_id);
         debug!("new data file at: {}", &p.display());
-        self.active_data_file = Some(DataFile::new(p.as_path(), true)?);
+        let df = DataFile::new(self.vfs.clone(), p.as_path(), true)?;
+        self.switch_commit_file(df.try_clone_file()?);
+        self.active_data_file = Some(df);
 
         // preapre a read-only data file with the same path.
-        let df = DataFile::new(p.as_path(), false)?;
-        self.data_files.insert(df.id, df);
-
+        let df = DataFile::new(self.vfs.clone(), p.as_path(), false)?;
         self.stats.total_data_files += 1;
+        self.stats.size_of_all_data_files += df.size;
+        self.data_files.insert(df.id, df);
 
         Ok(())
     }
 
+    /// Insert an entry into keydir of the given keyspace, update stats.
+    fn index(&mut self, keyspace: &str, key: Vec<u8>, mut keydir_ent: KeyDirEntry) {
+        if !self.keyspaces.contains_key(keyspace) {
+            self.keyspaces
+                .insert(keyspace.to_owned(), KeyspaceState::default());
+        }
+        let ks = self.keyspaces.get_mut(keyspace).unwrap();
+
+        ks.stats.total_active_entries += 1;
+        ks.stats.size_of_active_entries += keydir_ent.size;
+        self.stats.total_active_entries += 1;
+
+        let pending = ks.merges.remove(&key);
+        if let Some(eviction) = self.eviction.as_mut() {
+            let old = ks.keydir.get(&key);
+            eviction.insert(keyspace, &key, &mut keydir_ent, old);
+        }
+        let delimiter = self.config.key_prefix_delimiter;
+        if let Some(old) = ks.keydir.get(&key) {
+            self.key_stats.remove(keyspace, &key, old, delimiter);
+        }
+        self.key_stats.add(keyspace, &key, &keydir_ent, delimiter);
+        if let Some(old) = ks.keydir.insert(key, keydir_ent) {
+            ks.stats.total_active_entries -= 1;
+            ks.stats.size_of_active_entries -= old.size;
+            self.stats.total_active_entries -= 1;
+            self.mark_stale(keyspace, old.segment_id, old.size);
+        }
+        if let Some(state) = pending {
+            self.retire_merge_state(keyspace, state);
+        }
+    }
+
+    /// Remove an entry from keydir of the given keyspace, update stats.
+    fn unindex(&mut self, keyspace: &str, key: &[u8]) -> Option<KeyDirEntry> {
+        let ks = self.keyspaces.get_mut(keyspace)?;
+        let pending = ks.merges.remove(key);
+        let old = ks.keydir.remove(key)?;
+        if let Some(eviction) = self.eviction.as_mut() {
+            eviction.remove(keyspace, key, &old);
+        }
+
+        ks.stats.total_active_entries -= 1;
+        ks.stats.size_of_active_entries -= old.size;
+        self.stats.total_active_entries -= 1;
+        self.mark_stale(keyspace, old.segment_id, old.size);
+        let delimiter = self.config.key_prefix_delimiter;
+        self.key_stats.remove(keyspace, key, &old, delimiter);
+        if let Some(state) = pending {
+            self.retire_merge_state(keyspace, state);
+        }
+
+        Some(old)
+    }
+
+    /// Forget the whole keyspace, all of its entries become stale.
+    fn unindex_keyspace(&mut self, keyspace: &str) -> Option<KeyspaceState> {
+        let ks = self.keyspaces.remove(keyspace)?;
+        if let Some(eviction) = self.eviction.as_mut() {
+            for (key, ent) in ks.keydir.iter() {
+                eviction.remove(keyspace, key, ent);
+            }
+        }
+        let delimiter = self.config.key_prefix_delimiter;
+        for (key, ent) in ks.keydir.iter() {
+            self.key_stats.remove(keyspace, key, ent, delimiter);
+        }
+
+        // base values and earlier operands are active entries too.
+        let pending: usize = ks
+            .merges
+            .values()
+            .map(|state| state.earlier_entries().count())
+            .sum();
+        self.stats.total_active_entries -= ks.stats.total_active_entries;
+        self.stats.total_stale_entries += ks.stats.total_active_entries + pending as u64;
+        self.stats.size_of_stale_entries += ks.stats.size_of_active_entries;
+        if let Some(compaction) = self.compaction.as_mut() {
+            compaction.unindex_keyspace(keyspace, &ks);
+        }
+
+        Some(ks)
+    }
+
+    /// Record a stale entry in data file `segment_id`, which can be
+    /// deleted after a compaction.
+    fn mark_stale(&mut self, keyspace: &str, segment_id: u64, size: u64) {
+        self.stats.total_stale_entries += 1;
+        self.stats.size_of_stale_entries += size;
+        if let Some(compaction) = self.compaction.as_mut() {
+            compaction.mark_stale(keyspace, segment_id, size);
+        }
+
+        if let Some(ks) = self.keyspaces.get_mut(keyspace) {
+            ks.stats.total_stale_entries += 1;
+            ks.stats.size_of_stale_entries += size;
+        }
+    }
+
     /// Save key & value pair to database.
     pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
-        if key.len() as u64 > self.config.max_key_size {
+        self.set_in(config::DEFAULT_KEYSPACE, key, value, None)
+    }
+
+    /// Save key & value pair to database, the key will be expired
+    /// after the given `ttl`.
+    pub fn set_with_ttl(&mut self, key: &[u8], value: &[u8], ttl: time::Duration) -> Result<()> {
+        self.set_in(config::DEFAULT_KEYSPACE, key, value, Some(ttl))
+    }
+
+    fn set_in(
+        &mut self,
+        keyspace: &str,
+        key: &[u8],
+        value: &[u8],
+        ttl: Option<time::Duration>,
+    ) -> Result<()> {
+        let options = self.keyspace_options(keyspace);
+        if key.len() as u64 > options.max_key_size.unwrap_or(self.config.max_key_size) {
             return Err(TinkvError::KeyIsTooLarge);
         }
 
-        if value.len() as u64 > self.config.max_value_size {
+        if value.len() as u64 > options.max_value_size.unwrap_or(self.config.max_value_size) {
             return Err(TinkvError::ValueIsTooLarge);
         }
 
+        let expires_at = ttl
+            .or(options.default_ttl)
+            .map(|ttl| current_millis() + ttl.as_millis() as u64);
+
+        self.make_room(keyspace, key)?;
+        self.put_in(keyspace, key, value, expires_at)
+    }
+
+    /// Save key & value pair without checking limits of keyspace.
+    fn put_in(
+        &mut self,
+        keyspace: &str,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<()> {
         // save data to data file.
-        let ent = self.write(key, value)?;
+        let ent = self.write(RecordKind::Put, keyspace, key, value, expires_at)?;
 
         // update keydir, the in-memory index.
-        let old = self.keydir.insert(
+        self.index(
+            keyspace,
             key.to_vec(),
-            KeyDirEntry::new(ent.file_id, ent.offset, ent.size),
+            KeyDirEntry::new(ent.file_id, ent.offset, ent.size, ent.seq(), expires_at),
         );
 
-        match old {
-            None => {
-                self.stats.total_active_entries += 1;
-            }
-            Some(entry) => {
-                self.stats.size_of_stale_entries += entry.size;
-                self.stats.total_stale_entries += 1;
-            }
-        }
-
         self.stats.size_of_all_data_files += ent.size;
 
+        self.publish(|| ChangeEvent {
+            seq: ent.seq(),
+            keyspace: keyspace.to_owned(),
+            key: key.to_vec(),
+            kind: ChangeKind::Put {
+                value: value.to_vec(),
+                expires_at,
+            },
+        });
+
         Ok(())
     }
 
     /// Remove key value from database.
     pub fn remove(&mut self, key: &[u8]) -> Result<()> {
-        if self.keydir.contains_key(key) {
+        self.remove_from(config::DEFAULT_KEYSPACE, key)
+    }
+
+    fn remove_from(&mut self, keyspace: &str, key: &[u8]) -> Result<()> {
+        if self.contains_key_in(keyspace, key) {
             trace!(
-                "remove key '{}' from datastore",
-                String::from_utf8_lossy(key)
+                "remove key '{}' from keyspace '{}'",
+                String::from_utf8_lossy(key),
+                keyspace
             );
             // write tomestone, will be removed on compaction.
-            let entry = self.write(key, config::REMOVE_TOMESTONE)?;
+            let entry = self.write(RecordKind::Remove, keyspace, key, b"", None)?;
             // remove key from in-memory index.
-            let old = self.keydir.remove(key).expect("key not found");
+            self.unindex(keyspace, key).expect("key not found");
+            self.mark_stale(keyspace, entry.file_id, entry.size);
 
-            self.stats.total_active_entries -= 1;
-            self.stats.total_stale_entries += 1;
             self.stats.size_of_all_data_files += entry.size;
-            self.stats.size_of_stale_entries += old.size + entry.size;
+
+            self.publish(|| ChangeEvent {
+                seq: entry.seq(),
+                keyspace: keyspace.to_owned(),
+                key: key.to_vec(),
+                kind: ChangeKind::Delete,
+            });
 
             Ok(())
         } else {
             trace!(
-                "remove key '{}' failed, not found in datastore",
-                String::from_utf8_lossy(key)
+                "remove key '{}' failed, not found in keyspace '{}'",
+                String::from_utf8_lossy(key),
+                keyspace
             );
             Err(TinkvError::KeyNotFound(key.into()))
         }
     }
 
-    fn write(&mut self, key: &[u8], value: &[u8]) -> Result<DataEntry> {
+    fn write(
+        &mut self,
+        kind: RecordKind,
+        keyspace: &str,
+        key: &[u8],
+        value: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<DataEntry> {
+        self.check_writeable()?;
         let mut df = self
             .active_data_file
             .as_mut()
@@ -244,9 +665,6 @@ impl Store {
         if df.size > self.config.max_data_file_size {
             info!("size of active data file '{}' exceeds maximum size of {} bytes, switch to another one.", df.path.display(), self.config.max_data_file_size);
 
-            // sync data to disk.
-            let _ = df.sync();
-
             // create a new active data file.
             self.new_active_data_file(None)?;
 
@@ -257,167 +675,215 @@ impl Store {
                 .expect("active data file not found");
         }
 
-        let entry = df.write(key, value)?;
-        if self.config.sync {
-            // make sure data entry is persisted in storage.
-            df.sync()?;
-        }
+        self.seq += 1;
+        let entry = df.write(self.seq, kind, keyspace, key, value, expires_at)?;
+        // make sure data entry is persisted in storage by sync policy.
+        self.commit(entry.seq(), entry.size)?;
 
         Ok(entry)
     }
 
     /// Get key value from database.
     pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        if let Some(keydir_ent) = self.keydir.get(key) {
-            trace!(
-                "found key '{}' in keydir, got value {:?}",
-                String::from_utf8_lossy(key),
-                &keydir_ent
-            );
-            let df = self
-                .data_files
-                .get_mut(&keydir_ent.segment_id)
-                .unwrap_or_else(|| panic!("data file {} not found", &keydir_ent.segment_id));
-            let entry = df.read(keydir_ent.offset)?;
-            if !entry.is_valid() {
-                Err(TinkvError::DataEntryCorrupted {
-                    file_id: df.id,
-                    key: entry.key().into(),
-                    offset: entry.offset,
-                })
-            } else {
-                Ok(Some(entry.value().into()))
-            }
-        } else {
-            Ok(None)
-        }
+        self.get_from(config::DEFAULT_KEYSPACE, key)
     }
 
-    /// Clear stale entries from data files and reclaim disk space.
-    pub fn compact(&mut self) -> Result<()> {
-        let begin_at = time::Instant::now();
-
-        info!(
-            "there are {} data files need to be compacted",
-            self.data_files.len()
-        );
-
-        let next_file_id = self.next_file_id();
-
-        // switch to another active data file.
-        self.new_active_data_file(Some(next_file_id + 1))?;
-        let mut compaction_data_file_id = next_file_id + 2;
-
-        // create a new data file for compaction.
-        let data_file_path = segment_data_file_path(&self.path, compaction_data_file_id);
-
-        debug!("create compaction data file: {}", data_file_path.display());
-        let mut compaction_df = DataFile::new(&data_file_path, true)?;
-
-        // register read-only compaction data file.
-        self.data_files
-            .insert(compaction_df.id, DataFile::new(&compaction_df.path, false)?);
-
-        // create a new hint file to store compaction file index.
-        let hint_file_path = segment_hint_file_path(&self.path, compaction_data_file_id);
+    fn get_from(&mut self, keyspace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .get_with_meta_from(keyspace, key)?
+            .map(|(value, _)| value))
+    }
 
-        debug!("create compaction hint file: {}", hint_file_path.display());
-        let mut hint_file = HintFile::new(&hint_file_path, true)?;
+    /// Get key value and its metadata (version and write time) from database.
+    pub fn get_with_meta(&mut self, key: &[u8]) -> Result<Option<(Vec<u8>, EntryMeta)>> {
+        self.get_with_meta_from(config::DEFAULT_KEYSPACE, key)
+    }
 
-        let mut total_size_of_compaction_files = 0;
+    fn get_with_meta_from(
+        &mut self,
+        keyspace: &str,
+        key: &[u8],
+    ) -> Result<Option<(Vec<u8>, EntryMeta)>> {
+        let keydir_ent = match self.keydir(keyspace).and_then(|keydir| keydir.get(key)) {
+            Some(keydir_ent) => *keydir_ent,
+            None => return Ok(None),
+        };
 
-        // copy all the data entries into compaction data file.
-        // TODO: check if data file size exceeds threshold, switch
-        // to another one if nessesary.
-        for (key, keydir_ent) in self.keydir.iter_mut() {
-            if compaction_df.size > self.config.max_data_file_size {
-                total_size_of_compaction_files += compaction_df.size;
+        trace!(
+            "found key '{}' in keydir, got value {:?}",
+            String::from_utf8_lossy(key),
+            &keydir_ent
+        );
 
-                compaction_df.sync()?;
-                hint_file.sync()?;
+        if keydir_ent.is_expired(current_millis()) {
+            // expired keys are reclaimed lazily.
+            trace!("key '{}' is expired", String::from_utf8_lossy(key));
+            self.unindex(keyspace, key);
+            return Ok(None);
+        }
 
-                compaction_data_file_id += 1;
-                // switch to a new data file for compaction.
-                let data_file_path = segment_data_file_path(&self.path, compaction_data_file_id);
-                debug!(
-                    "file size exceeds limit, switch to another compaction data file: {}",
-                    data_file_path.display()
-                );
-                compaction_df = DataFile::new(&data_file_path, true)?;
+        let entry = self.read_entry(&keydir_ent)?;
+        if let Some(eviction) = self.eviction.as_mut() {
+            let keydir_ent = self
+                .keyspaces
+                .get_mut(keyspace)
+                .and_then(|ks| ks.keydir.get_mut(key));
+            if let Some(keydir_ent) = keydir_ent {
+                eviction.touch(keyspace, key, keydir_ent);
+            }
+        }
+        let meta = EntryMeta {
+            version: entry.seq(),
+            timestamp_ns: entry.timestamp(),
+            expires_at_ms: entry.expires_at(),
+        };
 
-                self.data_files
-                    .insert(compaction_df.id, DataFile::new(&compaction_df.path, false)?);
+        if entry.kind() == RecordKind::Merge {
+            // fold merge operands onto the base value lazily.
+            let value = self.fold_operands(keyspace, key)?;
+            Ok(Some((value, meta)))
+        } else {
+            Ok(Some((entry.value().into(), meta)))
+        }
+    }
 
-                let hint_file_path = segment_hint_file_path(&self.path, compaction_data_file_id);
-                debug!(
-                    "switch to another compaction hint file: {}",
-                    hint_file_path.display()
-                );
-                hint_file = HintFile::new(&hint_file_path, true)?;
-            }
+    /// Read data entry pointed by keydir entry, and check its checksum.
+    fn read_entry(&mut self, keydir_ent: &KeyDirEntry) -> Result<DataEntry> {
+        let df = self
+            .data_files
+            .get_mut(&keydir_ent.segment_id)
+            .ok_or(TinkvError::SegmentNotFound(keydir_ent.segment_id))?;
+        let entry = df.read(keydir_ent.offset)?;
+        if !entry.is_valid() {
+            Err(TinkvError::DataEntryCorrupted {
+                file_id: df.id,
+                key: entry.key().into(),
+                offset: entry.offset,
+            })
+        } else {
+            Ok(entry)
+        }
+    }
 
-            let df = self
-                .data_files
-                .get_mut(&keydir_ent.segment_id)
-                .expect("cannot find data file");
-            trace!(
-                "copy key '{}': original data file({}) -> compaction data file({})",
-                String::from_utf8_lossy(key),
-                df.path.display(),
-                compaction_df.path.display()
-            );
+    /// Create a checkpoint of datastore in directory `dir`, which can be
+    /// opened by `Store::open` directly.
+    ///
+    /// Active data file will be sealed first, then all the data files and
+    /// hint files are hard linked (or copied across filesystems) into `dir`.
+    /// A manifest file is written at last. `dir` is always on the real disk,
+    /// segment files of a datastore on another `Vfs` are copied out of it.
+    pub fn checkpoint<P: AsRef<Path>>(&mut self, dir: P) -> Result<Manifest> {
+        self.backup_into(dir.as_ref(), None)
+    }
 
-            let offset = compaction_df.copy_bytes_from(df, keydir_ent.offset, keydir_ent.size)?;
+    /// Create an incremental backup in directory `dir` based on manifest of
+    /// the previous backup. Only segment files not found in the `base`
+    /// backup are copied, removed segment files are recorded in manifest.
+    ///
+    /// Use `restore_backup` to reassemble a datastore from the full backup
+    /// and the following incremental backups.
+    pub fn incremental_backup<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+        base: &Manifest,
+    ) -> Result<Manifest> {
+        self.backup_into(dir.as_ref(), Some(base))
+    }
 
-            keydir_ent.segment_id = compaction_df.id;
-            keydir_ent.offset = offset;
+    fn backup_into(&mut self, dir: &Path, base: Option<&Manifest>) -> Result<Manifest> {
+        // active data file is sealed before linking data files.
+        self.check_writeable()?;
+        info!("create backup at: {}", dir.display());
 
-            hint_file.write(key, keydir_ent.offset, keydir_ent.size)?;
+        if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+            return Err(TinkvError::Custom(format!(
+                "backup directory '{}' is not empty",
+                dir.display()
+            )));
         }
+        create_dir_all(dir)?;
 
-        compaction_df.sync()?;
-        hint_file.sync()?;
+        // seal active data file, so that all the files
+        // to be linked are immutable.
+        let active_file_id = self.next_file_id() - 1;
+        let sealed = self
+            .active_data_file
+            .as_ref()
+            .map(|df| df.size > HEADER_SIZE)
+            == Some(true);
+        if sealed {
+            self.sync()?;
+            self.new_active_data_file(None)?;
+        }
 
-        total_size_of_compaction_files += compaction_df.size;
+        let mut file_ids = self
+            .data_files
+            .keys()
+            .cloned()
+            .filter(|&id| id < active_file_id || (sealed && id == active_file_id))
+            // files of an ongoing compaction are still being written.
+            .filter(|&id| !self.compaction.as_ref().is_some_and(|c| c.owns(id)))
+            .collect::<Vec<_>>();
+        file_ids.sort();
 
-        // remove stale segments.
-        let mut stale_segment_count = 0;
-        for df in self.data_files.values() {
-            if df.id <= next_file_id {
-                if df.path.exists() {
-                    debug!("try to remove stale data file: {}", df.path.display());
-                    fs::remove_file(&df.path)?;
+        let mut manifest = Manifest {
+            created_at: current_millis(),
+            ..Default::default()
+        };
+        for file_id in file_ids {
+            for src in [
+                segment_data_file_path(&self.path, file_id),
+                segment_hint_file_path(&self.path, file_id),
+            ]
+            .iter()
+            {
+                if !self.vfs.exists(src) {
+                    continue;
                 }
 
-                let hint_file_path = segment_hint_file_path(&self.path, df.id);
-                if hint_file_path.exists() {
-                    debug!(
-                        "try to remove stale hint file: {}",
-                        &hint_file_path.display()
-                    );
-                    fs::remove_file(&hint_file_path)?;
+                let name = src
+                    .file_name()
+                    .expect("segment file name not found")
+                    .to_string_lossy()
+                    .to_string();
+                let size = self.vfs.file_size(src)?;
+
+                // sealed segment files never change, skip the
+                // ones already in the base backup.
+                let inherited = base
+                    .and_then(|m| m.get(&name))
+                    .map(|f| f.size == size)
+                    .unwrap_or(false);
+                if !inherited {
+                    trace!("copy segment file {} into backup", src.display());
+                    self.vfs.copy_to_disk(src, &dir.join(&name))?;
                 }
 
-                stale_segment_count += 1;
+                manifest.files.push(ManifestFile {
+                    name,
+                    size,
+                    inherited,
+                });
             }
         }
 
-        self.data_files.retain(|&k, _| k > next_file_id);
-        debug!("cleaned {} stale segments", stale_segment_count);
+        if let Some(base) = base {
+            for f in base.files.iter() {
+                if manifest.get(&f.name).is_none() {
+                    manifest.removed.push(f.name.clone());
+                }
+            }
+        }
 
+        manifest.save(dir)?;
         info!(
-            "compaction progress done in {:?}",
-            time::Instant::now().duration_since(begin_at)
+            "backup created with {} files ({} bytes copied), {} files removed since base",
+            manifest.files.len(),
+            manifest.total_size(),
+            manifest.removed.len()
         );
 
-        // update stats.
-        self.stats.total_data_files = self.data_files.len() as u64;
-        self.stats.total_active_entries = self.keydir.len() as u64;
-        self.stats.total_stale_entries = 0;
-        self.stats.size_of_stale_entries = 0;
-        self.stats.size_of_all_data_files = total_size_of_compaction_files;
-
-        Ok(())
+        Ok(manifest)
     }
 
     fn next_file_id(&self) -> u64 {
@@ -428,6 +894,11 @@ impl Store {
             + 1
     }
 
+    /// Return path of datastore directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
     /// Return current stats of datastore.
     pub fn stats(&self) -> &Stats {
         &self.stats
@@ -435,12 +906,44 @@ impl Store {
 
     /// Return all keys in datastore.
     pub fn keys(&self) -> impl Iterator<Item = &Vec<u8>> {
-        self.keydir.keys()
+        self.keys_in(config::DEFAULT_KEYSPACE)
+    }
+
+    fn keys_in(&self, keyspace: &str) -> impl Iterator<Item = &Vec<u8>> {
+        let now = current_millis();
+        self.keydir(keyspace)
+            .into_iter()
+            .flat_map(|keydir| keydir.iter())
+            .filter(move |(_, keydir_ent)| !keydir_ent.is_expired(now))
+            .map(|(key, _)| key)
+    }
+
+    /// Return keys within the given range in datastore, in order.
+    pub fn keys_range<R: RangeBounds<Vec<u8>>>(&self, range: R) -> impl Iterator<Item = &Vec<u8>> {
+        let now = current_millis();
+        let keydir = self
+            .keydir(config::DEFAULT_KEYSPACE)
+            .filter(|_| !is_empty_range(&range));
+        keydir
+            .into_iter()
+            .flat_map(move |keydir| {
+                keydir.range::<Vec<u8>, _>((range.start_bound(), range.end_bound()))
+            })
+            .filter(move |(_, keydir_ent)| !keydir_ent.is_expired(now))
+            .map(|(key, _)| key)
     }
 
     /// Return total number of keys in datastore.
+    ///
+    /// Expired keys are counted until they are reclaimed.
     pub fn len(&self) -> u64 {
-        self.keydir.len() as u64
+        self.len_of(config::DEFAULT_KEYSPACE)
+    }
+
+    fn len_of(&self, keyspace: &str) -> u64 {
+        self.keydir(keyspace)
+            .map(|keydir| keydir.len() as u64)
+            .unwrap_or_default()
     }
 
     /// Check datastore is empty or not.
@@ -450,7 +953,96 @@ impl Store {
 
     /// Return `true` if datastore contains the given key.
     pub fn contains_key(&self, key: &[u8]) -> bool {
-        self.keydir.contains_key(key)
+        self.contains_key_in(config::DEFAULT_KEYSPACE, key)
+    }
+
+    fn contains_key_in(&self, keyspace: &str, key: &[u8]) -> bool {
+        self.keydir(keyspace)
+            .and_then(|keydir| keydir.get(key))
+            .map(|keydir_ent| !keydir_ent.is_expired(current_millis()))
+            .unwrap_or(false)
+    }
+
+    fn keydir(&self, keyspace: &str) -> Option<&BTreeMap<Vec<u8>, KeyDirEntry>> {
+        self.keyspaces.get(keyspace).map(|ks| &ks.keydir)
+    }
+
+    fn keyspace_options(&self, keyspace: &str) -> KeyspaceOptions {
+        self.keyspaces
+            .get(keyspace)
+            .map(|ks| ks.options)
+            .unwrap_or_default()
+    }
+
+    /// Return handle of the keyspace with the given name, the keyspace
+    /// will be created if not found.
+    ///
+    /// Keyspaces share data files and compaction of the datastore, but
+    /// have separated keydirs, stats and options.
+    pub fn keyspace(&mut self, name: &str) -> Result<Keyspace<'_>> {
+        if name.is_empty() {
+            return Err(TinkvError::Custom("keyspace name is empty".to_owned()));
+        }
+
+        if !self.keyspaces.contains_key(name) {
+            debug!("create keyspace '{}'", name);
+            self.keyspaces
+                .insert(name.to_owned(), KeyspaceState::default());
+        }
+
+        Ok(Keyspace::new(self, name))
+    }
+
+    /// Return handle of the keyspace with the given name, and apply
+    /// custom options to it.
+    ///
+    /// Options are kept in memory, they should be applied again
+    /// after reopening the datastore.
+    pub fn keyspace_with_options(
+        &mut self,
+        name: &str,
+        options: &KeyspaceOptions,
+    ) -> Result<Keyspace<'_>> {
+        let mut ks = self.keyspace(name)?;
+        ks.set_options(options);
+        Ok(ks)
+    }
+
+    /// Drop a keyspace and all of its keys.
+    ///
+    /// Only a tomestone is written, disk space will be reclaimed
+    /// on compaction.
+    pub fn drop_keyspace(&mut self, name: &str) -> Result<()> {
+        if name == config::DEFAULT_KEYSPACE {
+            return Err(TinkvError::Custom(format!(
+                "keyspace '{}' cannot be dropped",
+                name
+            )));
+        }
+
+        if !self.keyspaces.contains_key(name) {
+            return Err(TinkvError::KeyspaceNotFound(name.to_owned()));
+        }
+
+        debug!("drop keyspace '{}'", name);
+        let entry = self.write(RecordKind::DropKeyspace, name, b"", b"", None)?;
+        self.unindex_keyspace(name);
+        self.mark_stale(name, entry.file_id, entry.size);
+        self.stats.size_of_all_data_files += entry.size;
+
+        self.publish(|| ChangeEvent {
+            seq: entry.seq(),
+            keyspace: name.to_owned(),
+            key: vec![],
+            kind: ChangeKind::DropKeyspace,
+        });
+
+        Ok(())
+    }
+
+    /// Return names of all keyspaces in datastore.
+    pub fn keyspace_names(&self) -> impl Iterator<Item = &String> {
+        self.keyspaces.keys()
     }
 
     /// Iterate all keys in datastore and call function `f`
@@ -486,9 +1078,10 @@ impl Store {
 
     /// Force flushing any pending writes to disk.
     pub fn sync(&mut self) -> Result<()> {
-        if self.active_data_file.is_some() {
-            self.active_data_file.as_mut().unwrap().sync()?;
+        if let Some(df) = self.active_data_file.as_mut() {
+            df.sync()?;
         }
+        self.mark_synced();
         Ok(())
     }
 
@@ -516,16 +1109,44 @@ struct KeyDirEntry {
     offset: u64,
     /// data entry size.
     size: u64,
+    /// sequence number of the write.
+    seq: u64,
+    /// expiration time in milliseconds since unix epoch.
+    expires_at: Option<u64>,
+    /// logical time of the last access, used by eviction.
+    accessed: u64,
+    /// total accesses, used by eviction.
+    hits: u64,
 }
 
 impl KeyDirEntry {
-    fn new(segment_id: u64, offset: u64, size: u64) -> Self {
+    fn new(segment_id: u64, offset: u64, size: u64, seq: u64, expires_at: Option<u64>) -> Self {
         KeyDirEntry {
             segment_id,
             offset,
             size,
+            seq,
+            expires_at,
+            accessed: 0,
+            hits: 0,
         }
     }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.map(|t| t <= now).unwrap_or(false)
+    }
+}
+
+/// Metadata of a key value pair.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EntryMeta {
+    /// version of the value, it's the sequence number of the write
+    /// which increases monotonically in datastore.
+    pub version: u64,
+    /// write time in nanoseconds since unix epoch.
+    pub timestamp_ns: u64,
+    /// expiration time in milliseconds since unix epoch.
+    pub expires_at_ms: Option<u64>,
 }
 
 #[derive(Debug, Copy, Clone, Default)]
@@ -541,6 +1162,8 @@ pub struct Stats {
     pub total_data_files: u64,
     /// total size (bytes) of all data files.
     pub size_of_all_data_files: u64,
+    /// total keys evicted since opening.
+    pub total_evicted_keys: u64,
 }
 
 fn segment_data_file_path(dir: &Path, segment_id: u64) -> PathBuf {
@@ -557,14 +1180,50 @@ fn segment_file_path(dir: &Path, segment_id: u64, suffix: &str) -> PathBuf {
     p
 }
 
+/// `BTreeMap::range` panics on such ranges.
+fn is_empty_range<R: RangeBounds<Vec<u8>>>(range: &R) -> bool {
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        _ => false,
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub(crate) struct Config {
     max_data_file_size: u64,
     max_key_size: u64,
     max_value_size: u64,
-    // sync data to storage after each writting operation.
-    // we should balance data reliability and writting performance.
-    sync: bool,
+    // when to sync data to storage, we should balance
+    // data reliability and writting performance.
+    sync_policy: SyncPolicy,
+    // versions (including the current one) of each key kept on compaction.
+    retain_versions: usize,
+    // versions written within this window are kept on compaction.
+    retain_window: Option<time::Duration>,
+    // never create or write any files.
+    read_only: bool,
+    // limits of datastore, keys are evicted by policy
+    // once they are reached.
+    max_keys: Option<u64>,
+    max_total_size: Option<u64>,
+    eviction_policy: EvictionPolicy,
+    // bytes per second of compaction, hint generation and scrubbing.
+    background_io_rate: Option<u64>,
+    // keys are counted by the part before it.
+    key_prefix_delimiter: u8,
+}
+
+impl Config {
+    fn eviction_state(&self) -> Option<EvictionState> {
+        let limited = self.max_keys.is_some() || self.max_total_size.is_some();
+        if !limited || self.read_only || self.eviction_policy == EvictionPolicy::NoEviction {
+            return None;
+        }
+        Some(EvictionState::new(self.eviction_policy))
+    }
 }
 
 impl Default for Config {
@@ -573,23 +1232,25 @@ impl Default for Config {
             max_data_file_size: config::DEFAULT_MAX_DATA_FILE_SIZE,
             max_key_size: config::DEFAULT_MAX_KEY_SIZE,
             max_value_size: config::DEFAULT_MAX_VALUE_SIZE,
-            sync: false,
+            sync_policy: SyncPolicy::Never,
+            retain_versions: 1,
+            retain_window: None,
+            read_only: false,
+            max_keys: None,
+            max_total_size: None,
+            eviction_policy: EvictionPolicy::NoEviction,
+            background_io_rate: None,
+            key_prefix_delimiter: config::DEFAULT_KEY_PREFIX_DELIMITER,
         }
     }
 }
 
 /// Build custom open options.
-#[derive(Debug)]
+#[derive(Debug, Default, Clone)]
 pub struct OpenOptions {
     config: Config,
-}
-
-impl Default for OpenOptions {
-    fn default() -> Self {
-        Self {
-            config: Config::default(),
-        }
-    }
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    vfs: Option<Arc<dyn Vfs>>,
 }
 
 impl OpenOptions {
@@ -616,14 +1277,113 @@ impl OpenOptions {
         self
     }
 
+    /// Sync data to storage after each writting operation if `value`
+    /// is `true`, same as `sync_policy(SyncPolicy::Always)`.
     #[allow(dead_code)]
     pub fn sync(&mut self, value: bool) -> &mut Self {
-        self.config.sync = value;
+        self.config.sync_policy = if value {
+            SyncPolicy::Always
+        } else {
+            SyncPolicy::Never
+        };
+        self
+    }
+
+    /// Set when to sync data to storage. A zero interval is raised to 1ms.
+    #[allow(dead_code)]
+    pub fn sync_policy(&mut self, value: SyncPolicy) -> &mut Self {
+        self.config.sync_policy = match value {
+            SyncPolicy::Interval(interval) => {
+                SyncPolicy::Interval(interval.max(time::Duration::from_millis(1)))
+            }
+            policy => policy,
+        };
+        self
+    }
+
+    /// Keep the last `value` versions of each key on compaction,
+    /// so that they can be read by `get_at` and `history`.
+    #[allow(dead_code)]
+    pub fn retain_versions(&mut self, value: usize) -> &mut Self {
+        self.config.retain_versions = value.max(1);
+        self
+    }
+
+    /// Keep versions written within the last `value` on compaction.
+    #[allow(dead_code)]
+    pub fn retain_window(&mut self, value: time::Duration) -> &mut Self {
+        self.config.retain_window = Some(value);
+        self
+    }
+
+    /// Open datastore in read-only mode, no files are created or written,
+    /// so it can be used along with another process writing to it.
+    /// Mutations fail with `TinkvError::ReadOnly`.
+    #[allow(dead_code)]
+    pub fn read_only(&mut self, value: bool) -> &mut Self {
+        self.config.read_only = value;
+        self
+    }
+
+    /// Limit total number of keys in all keyspaces, keys are evicted
+    /// by eviction policy once it's reached.
+    #[allow(dead_code)]
+    pub fn max_keys(&mut self, value: u64) -> &mut Self {
+        self.config.max_keys = Some(value);
+        self
+    }
+
+    /// Limit total size (bytes) of live entries in all keyspaces, keys
+    /// are evicted by eviction policy once it's reached. Disk space
+    /// of evicted keys is reclaimed on compaction.
+    #[allow(dead_code)]
+    pub fn max_total_size(&mut self, value: u64) -> &mut Self {
+        self.config.max_total_size = Some(value);
+        self
+    }
+
+    /// Set which keys to evict once datastore reaches its limits,
+    /// writes fail with `TinkvError::StoreFull` by default.
+    #[allow(dead_code)]
+    pub fn eviction_policy(&mut self, value: EvictionPolicy) -> &mut Self {
+        self.config.eviction_policy = value;
+        self
+    }
+
+    /// Limit bytes per second read and written by compaction, hint
+    /// generation and scrubbing, `0` means unlimited. It can be
+    /// changed later by `Store::rate_limiter`.
+    #[allow(dead_code)]
+    pub fn background_io_rate(&mut self, value: u64) -> &mut Self {
+        self.config.background_io_rate = Some(value);
+        self
+    }
+
+    /// Keys are counted by prefix before the first `value` in key
+    /// stats, `:` by default.
+    #[allow(dead_code)]
+    pub fn key_prefix_delimiter(&mut self, value: u8) -> &mut Self {
+        self.config.key_prefix_delimiter = value;
+        self
+    }
+
+    /// Register a merge operator, which is required by `Store::merge`.
+    #[allow(dead_code)]
+    pub fn merge_operator<M: MergeOperator + 'static>(&mut self, value: M) -> &mut Self {
+        self.merge_operator = Some(Arc::new(value));
+        self
+    }
+
+    /// Store segment files in the given filesystem instead of the disk,
+    /// e.g. `MemFs` for testing. Backups still go to the disk.
+    #[allow(dead_code)]
+    pub fn vfs<V: Vfs + 'static>(&mut self, value: V) -> &mut Self {
+        self.vfs = Some(Arc::new(value));
         self
     }
 
     #[allow(dead_code)]
     pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<Store> {
-        Store::open_with_options(path, self.config)
+        Store::open_with_options(path, self)
     }
 }
@@ -0,0 +1,84 @@
+//! Conditional writes, the current state of keydir is checked
+//! before appending to data file.
+use super::Store;
+use crate::config;
+use crate::error::Result;
+use std::fmt;
+
+/// Returned by `compare_and_swap` if the current value of key
+/// is not the expected one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompareAndSwapError {
+    /// current value of the key, `None` if key not found.
+    pub current: Option<Vec<u8>>,
+}
+
+impl fmt::Display for CompareAndSwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "compare and swap conflict")
+    }
+}
+
+impl std::error::Error for CompareAndSwapError {}
+
+impl Store {
+    /// Set key to `new` if its current value is `expected`. `None` means
+    /// the key is absent for `expected`, and removing the key for `new`.
+    ///
+    /// The actual current value is returned on mismatch.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<std::result::Result<(), CompareAndSwapError>> {
+        self.compare_and_swap_in(config::DEFAULT_KEYSPACE, key, expected, new)
+    }
+
+    pub(super) fn compare_and_swap_in(
+        &mut self,
+        keyspace: &str,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<std::result::Result<(), CompareAndSwapError>> {
+        let current = self.get_from(keyspace, key)?;
+        if current.as_deref() != expected {
+            return Ok(Err(CompareAndSwapError { current }));
+        }
+
+        match new {
+            Some(value) => self.set_in(keyspace, key, value, None)?,
+            None if current.is_some() => self.remove_from(keyspace, key)?,
+            None => {}
+        }
+        Ok(Ok(()))
+    }
+
+    /// Save key & value pair only if the key is absent.
+    /// Return `true` if the value is saved.
+    pub fn set_if_absent(&mut self, key: &[u8], value: &[u8]) -> Result<bool> {
+        self.set_if_in(config::DEFAULT_KEYSPACE, key, value, false)
+    }
+
+    /// Save key & value pair only if the key is present.
+    /// Return `true` if the value is saved.
+    pub fn set_if_present(&mut self, key: &[u8], value: &[u8]) -> Result<bool> {
+        self.set_if_in(config::DEFAULT_KEYSPACE, key, value, true)
+    }
+
+    pub(super) fn set_if_in(
+        &mut self,
+        keyspace: &str,
+        key: &[u8],
+        value: &[u8],
+        present: bool,
+    ) -> Result<bool> {
+        if self.contains_key_in(keyspace, key) != present {
+            return Ok(false);
+        }
+
+        self.set_in(keyspace, key, value, None)?;
+        Ok(true)
+    }
+}
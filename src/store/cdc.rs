@@ -0,0 +1,342 @@
+//! Change data capture, subscribers receive an ordered stream of
+//! writes and deletes applied to the datastore.
+use super::{segment_hint_file_path, Store};
+use crate::config;
+use crate::error::{Result, TinkvError};
+use crate::segment::{DataEntry, DataFile, RecordKind};
+use log::{debug, error, warn};
+use std::cell::{Cell, RefCell};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A change applied to the datastore.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    /// sequence number of the change, increases monotonically.
+    pub seq: u64,
+    /// name of the keyspace which the key belongs to.
+    pub keyspace: String,
+    pub key: Vec<u8>,
+    pub kind: ChangeKind,
+}
+
+/// Kind of a change event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeKind {
+    /// A key value pair was saved, `expires_at` is the expiration
+    /// time in milliseconds since unix epoch.
+    Put {
+        value: Vec<u8>,
+        expires_at: Option<u64>,
+    },
+    /// A merge operand was appended to a key.
+    Merge { operand: Vec<u8> },
+    /// A key was removed.
+    Delete,
+    /// The keyspace was dropped with all of its keys, key is empty.
+    DropKeyspace,
+}
+
+/// A subscription to the change events of datastore, events are
+/// delivered in the order of sequence numbers.
+///
+/// At most `config::SUBSCRIPTION_BUFFER_SIZE` events are buffered until
+/// they are received, a subscription falling further behind is cancelled.
+/// The subscription is cancelled once it's dropped.
+#[derive(Debug)]
+pub struct Subscription {
+    // changes replayed from data files, they are followed by live ones.
+    replay: RefCell<Option<Receiver<Result<ChangeEvent>>>>,
+    receiver: Receiver<ChangeEvent>,
+    cancelled: Cell<bool>,
+}
+
+impl Subscription {
+    /// Return the next event if there is one, without blocking.
+    pub fn try_recv(&self) -> Option<ChangeEvent> {
+        if let Some(r) = self.recv_replayed(|replay| match replay.try_recv() {
+            Ok(r) => Ok(Some(r)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(()),
+        }) {
+            return r;
+        }
+
+        match self.receiver.try_recv() {
+            Ok(event) => Some(event),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => self.cancel(),
+        }
+    }
+
+    /// Wait for the next event until `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ChangeEvent> {
+        let deadline = Instant::now() + timeout;
+        if let Some(r) = self.recv_replayed(|replay| match replay.recv_timeout(timeout) {
+            Ok(r) => Ok(Some(r)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(()),
+        }) {
+            return r;
+        }
+
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.receiver.recv_timeout(timeout) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => self.cancel(),
+        }
+    }
+
+    /// Return `true` if no more events will be delivered, because the
+    /// subscription fell behind, replay failed or datastore is closed.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.get()
+    }
+
+    /// Receive from replayed events with `f` until replay is finished,
+    /// return `None` once it's finished.
+    fn recv_replayed<F>(&self, f: F) -> Option<Option<ChangeEvent>>
+    where
+        F: FnOnce(
+            &Receiver<Result<ChangeEvent>>,
+        ) -> std::result::Result<Option<Result<ChangeEvent>>, ()>,
+    {
+        if self.cancelled.get() {
+            return Some(None);
+        }
+
+        let mut replay = self.replay.borrow_mut();
+        let r = f(replay.as_ref()?);
+        match r {
+            Ok(Some(Ok(event))) => Some(Some(event)),
+            Ok(Some(Err(e))) => {
+                error!("failed to replay change events: {}", e);
+                Some(self.cancel())
+            }
+            Ok(None) => Some(None),
+            Err(()) => {
+                *replay = None;
+                None
+            }
+        }
+    }
+
+    fn cancel(&self) -> Option<ChangeEvent> {
+        self.cancelled.set(true);
+        None
+    }
+}
+
+/// Blocks until the next event, ends after datastore is closed.
+impl Iterator for Subscription {
+    type Item = ChangeEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(r) = self.recv_replayed(|replay| replay.recv().map(Some).map_err(|_| ())) {
+            return r;
+        }
+        match self.receiver.recv() {
+            Ok(event) => Some(event),
+            Err(_) => self.cancel(),
+        }
+    }
+}
+
+impl Store {
+    /// Subscribe change events of all the following writes and deletes.
+    pub fn subscribe(&mut self) -> Subscription {
+        let (sender, receiver) = mpsc::sync_channel(config::SUBSCRIPTION_BUFFER_SIZE);
+        self.subscribers.push(sender);
+        Subscription {
+            replay: RefCell::new(None),
+            receiver,
+            cancelled: Cell::new(false),
+        }
+    }
+
+    /// Subscribe change events whose sequence number is greater than `seq`.
+    ///
+    /// Changes already persisted are replayed from data files first, in
+    /// a background thread. Note that overwritten values and tomestones
+    /// are dropped by compaction, only changes still retained in data
+    /// files are replayed.
+    pub fn subscribe_from(&mut self, seq: u64) -> Result<Subscription> {
+        self.sync()?;
+
+        // data files are opened right now, they may be
+        // removed by compaction while replaying.
+        let mut file_ids = self.data_files.keys().cloned().collect::<Vec<_>>();
+        file_ids.sort();
+        let mut files = vec![];
+        for file_id in file_ids {
+            let path = &self.data_files[&file_id].path;
+            let df = DataFile::new(self.vfs.clone(), path, false)?;
+            let is_compacted = self
+                .vfs
+                .exists(&segment_hint_file_path(&self.path, file_id));
+            files.push((df, is_compacted));
+        }
+
+        let until = self.seq;
+        let (sender, receiver) = mpsc::sync_channel(config::SUBSCRIPTION_BUFFER_SIZE);
+        thread::Builder::new()
+            .name("tinkv-replay".to_owned())
+            .spawn(move || {
+                if let Err(e) = replay(files, seq, until, &sender) {
+                    let _ = sender.send(Err(e));
+                }
+            })?;
+
+        let sub = self.subscribe();
+        *sub.replay.borrow_mut() = Some(receiver);
+        Ok(sub)
+    }
+
+    /// Return `true` if all the changes after `seq` are still retained
+    /// in data files, so that `subscribe_from(seq)` replays them without
+    /// any gaps. Compaction drops overwritten values and tomestones.
+    pub fn can_replay_from(&self, seq: u64) -> bool {
+        seq >= self.compacted_seq && seq <= self.seq
+    }
+
+    /// Return sequence number of the last write.
+    pub fn last_seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Deliver a change event to all the subscribers, cancelled
+    /// subscriptions and the ones falling behind are removed.
+    pub(super) fn publish<F>(&mut self, event: F)
+    where
+        F: FnOnce() -> ChangeEvent,
+    {
+        if self.subscribers.is_empty() {
+            return;
+        }
+
+        let event = event();
+        self.subscribers
+            .retain(|sender| match sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!(
+                        "cancel a subscription falling behind by {} change events",
+                        config::SUBSCRIPTION_BUFFER_SIZE
+                    );
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+}
+
+/// Send change events in data files whose sequence numbers are in range
+/// `(after, until]`, stop once the subscription is dropped.
+fn replay(
+    mut files: Vec<(DataFile, bool)>,
+    after: u64,
+    until: u64,
+    sender: &SyncSender<Result<ChangeEvent>>,
+) -> Result<()> {
+    let mut replayer = Replayer {
+        last: after,
+        until,
+        total: 0,
+        sender,
+    };
+
+    let mut i = 0;
+    while i < files.len() {
+        if !files[i].1 {
+            for entry in files[i].0.entry_iter()? {
+                if !replayer.send(entry?)? {
+                    return Ok(());
+                }
+            }
+            i += 1;
+            continue;
+        }
+
+        // entries in compaction data files are not ordered by sequence
+        // numbers, sort positions of the consecutive ones before sending.
+        let mut positions = vec![];
+        let mut j = i;
+        while j < files.len() && files[j].1 {
+            for entry in files[j].0.entry_iter()? {
+                let entry = entry?;
+                if replayer.is_pending(entry.seq()) {
+                    positions.push((entry.seq(), j, entry.offset));
+                }
+            }
+            j += 1;
+        }
+        positions.sort();
+        for (_, k, offset) in positions {
+            if !replayer.send(files[k].0.read(offset)?)? {
+                return Ok(());
+            }
+        }
+        i = j;
+    }
+
+    debug!(
+        "replayed {} change events after seq {}",
+        replayer.total, after
+    );
+    Ok(())
+}
+
+struct Replayer<'a> {
+    // sequence number of the last event sent.
+    last: u64,
+    until: u64,
+    total: u64,
+    sender: &'a SyncSender<Result<ChangeEvent>>,
+}
+
+impl Replayer<'_> {
+    /// Return `true` if the entry is not sent yet, copies made by an
+    /// aborted compaction are sent only once.
+    fn is_pending(&self, seq: u64) -> bool {
+        seq > self.last && seq <= self.until
+    }
+
+    /// Send change event of the entry, return `false` if the
+    /// subscription is dropped.
+    fn send(&mut self, entry: DataEntry) -> Result<bool> {
+        if !entry.is_valid() {
+            return Err(TinkvError::DataEntryCorrupted {
+                file_id: entry.file_id,
+                key: entry.key().into(),
+                offset: entry.offset,
+            });
+        }
+        if !self.is_pending(entry.seq()) {
+            return Ok(true);
+        }
+
+        let kind = match entry.kind() {
+            RecordKind::Put => ChangeKind::Put {
+                value: entry.value().to_vec(),
+                expires_at: entry.expires_at(),
+            },
+            RecordKind::Merge => ChangeKind::Merge {
+                operand: entry.value().to_vec(),
+            },
+            RecordKind::Remove => ChangeKind::Delete,
+            RecordKind::DropKeyspace => ChangeKind::DropKeyspace,
+            RecordKind::SeqMark => return Ok(true),
+        };
+        self.last = entry.seq();
+        self.total += 1;
+        let event = ChangeEvent {
+            seq: entry.seq(),
+            keyspace: entry.keyspace().to_owned(),
+            key: entry.key().to_vec(),
+            kind,
+        };
+        Ok(self.sender.send(Ok(event)).is_ok())
+    }
+}
@@ -0,0 +1,279 @@
+//! Sync policies and group commit of durable writes.
+//!
+//! Records are flushed to the active data file by writers holding the
+//! store, waiting for them to be persisted happens outside of it. The
+//! first waiter becomes the leader and syncs all the records written so
+//! far in one go, the others wait for it and are acknowledged together.
+use super::Store;
+use crate::error::{Result, TinkvError};
+use crate::vfs::VfsFile;
+use log::{error, trace};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// When to sync writes to storage, like `appendfsync` of redis.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum SyncPolicy {
+    /// Sync after each write, a write returns after it is persisted.
+    Always,
+    /// Sync pending writes in a background thread every interval,
+    /// which must not be zero.
+    Interval(Duration),
+    /// Sync once pending writes exceed the given bytes.
+    Bytes(u64),
+    /// Let the operating system decide when to flush writes.
+    #[default]
+    Never,
+}
+
+impl fmt::Display for SyncPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncPolicy::Always => write!(f, "always"),
+            SyncPolicy::Interval(interval) => write!(f, "{}ms", interval.as_millis()),
+            SyncPolicy::Bytes(bytes) => write!(f, "{}bytes", bytes),
+            SyncPolicy::Never => write!(f, "never"),
+        }
+    }
+}
+
+impl FromStr for SyncPolicy {
+    type Err = TinkvError;
+
+    /// Parse `always`, `never`, `<N>ms` or `<N>bytes`.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || TinkvError::Custom(format!("invalid sync policy '{}'", s));
+        let number = |n: &str| n.parse::<u64>().map_err(|_| invalid());
+        match s.to_ascii_lowercase().as_ref() {
+            "always" => Ok(SyncPolicy::Always),
+            "never" => Ok(SyncPolicy::Never),
+            s if s.ends_with("ms") => match number(&s[..s.len() - 2])? {
+                // the sync thread would spin with a zero interval.
+                0 => Err(invalid()),
+                n => Ok(SyncPolicy::Interval(Duration::from_millis(n))),
+            },
+            s if s.ends_with("bytes") => Ok(SyncPolicy::Bytes(number(&s[..s.len() - 5])?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A handle to wait for writes of a store to be persisted.
+///
+/// It can be cloned and used by multiple threads after releasing the
+/// store, see `Store::set_defer_commits`.
+#[derive(Debug, Clone)]
+pub struct CommitHandle {
+    inner: Arc<GroupCommit>,
+}
+
+#[derive(Debug)]
+struct GroupCommit {
+    policy: SyncPolicy,
+    state: Mutex<CommitState>,
+    synced: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct CommitState {
+    // handle of the active data file, used by the leader.
+    file: Option<Arc<dyn VfsFile>>,
+    // sequence number of the last record flushed to data file.
+    written: u64,
+    // sequence number of the last record persisted in storage.
+    synced: u64,
+    // size of records written but not synced yet.
+    unsynced_bytes: u64,
+    // a leader is syncing the active data file.
+    syncing: bool,
+    total_syncs: u64,
+}
+
+impl CommitHandle {
+    pub(super) fn new(policy: SyncPolicy) -> Self {
+        let handle = CommitHandle {
+            inner: Arc::new(GroupCommit {
+                policy,
+                state: Mutex::new(CommitState::default()),
+                synced: Condvar::new(),
+            }),
+        };
+
+        if let SyncPolicy::Interval(interval) = policy {
+            let inner = Arc::downgrade(&handle.inner);
+            thread::Builder::new()
+                .name("tinkv-flusher".to_owned())
+                .spawn(move || flush_periodically(inner, interval))
+                .expect("failed to spawn flusher thread");
+        }
+
+        handle
+    }
+
+    /// Block until the write with sequence number `seq` (and all the
+    /// previous ones) is persisted in storage. It returns immediately
+    /// unless sync policy is `SyncPolicy::Always`.
+    pub fn wait(&self, seq: u64) -> Result<()> {
+        if self.inner.policy != SyncPolicy::Always {
+            return Ok(());
+        }
+        self.sync_until(seq)
+    }
+
+    /// Sync active data file until the write with sequence number
+    /// `seq` is persisted, syncs of concurrent callers are shared.
+    fn sync_until(&self, seq: u64) -> Result<()> {
+        let mut state = self.inner.state.lock().unwrap();
+        loop {
+            if state.synced >= seq {
+                return Ok(());
+            }
+
+            if state.syncing {
+                state = self.inner.synced.wait(state).unwrap();
+                continue;
+            }
+
+            // become the leader, records written by other writers
+            // in the meantime are synced in the same batch.
+            state.syncing = true;
+            let target = state.written.max(seq);
+            let file = state.file.clone();
+            drop(state);
+
+            let r = match file {
+                Some(f) => f.sync_data(),
+                None => Ok(()),
+            };
+
+            state = self.inner.state.lock().unwrap();
+            state.syncing = false;
+            if r.is_ok() && target > state.synced {
+                state.synced = target;
+                state.unsynced_bytes = 0;
+                state.total_syncs += 1;
+            }
+            self.inner.synced.notify_all();
+            r?;
+        }
+    }
+
+    /// Return sync policy of the store.
+    pub fn policy(&self) -> SyncPolicy {
+        self.inner.policy
+    }
+
+    /// Return total syncs made by group commit leaders, the background
+    /// flusher and byte threshold.
+    pub fn total_syncs(&self) -> u64 {
+        self.inner.state.lock().unwrap().total_syncs
+    }
+
+    /// Return sequence number of the last write persisted in storage.
+    pub fn synced_seq(&self) -> u64 {
+        self.inner.state.lock().unwrap().synced
+    }
+
+    /// Record with sequence number `seq` is flushed to data file,
+    /// return size of all the records not synced yet.
+    fn written(&self, seq: u64, size: u64) -> u64 {
+        let mut state = self.inner.state.lock().unwrap();
+        state.written = state.written.max(seq);
+        state.unsynced_bytes += size;
+        state.unsynced_bytes
+    }
+
+    /// All the records until `seq` are persisted.
+    fn synced(&self, seq: u64) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.written = state.written.max(seq);
+        state.synced = state.synced.max(seq);
+        state.unsynced_bytes = 0;
+        self.inner.synced.notify_all();
+    }
+
+    /// The active data file is switched, the previous one is synced.
+    fn switch(&self, file: Box<dyn VfsFile>, seq: u64) {
+        self.synced(seq);
+        self.inner.state.lock().unwrap().file = Some(Arc::from(file));
+    }
+}
+
+/// Sync pending writes every interval, until the store and
+/// all of its commit handles are dropped.
+fn flush_periodically(inner: Weak<GroupCommit>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let handle = match inner.upgrade() {
+            Some(inner) => CommitHandle { inner },
+            None => break,
+        };
+
+        let written = handle.inner.state.lock().unwrap().written;
+        trace!("flush pending writes until {}", written);
+        if let Err(e) = handle.sync_until(written) {
+            error!("failed to sync pending writes: {}", e);
+        }
+    }
+}
+
+impl Store {
+    /// Return a handle to wait for writes to be persisted.
+    pub fn commit_handle(&self) -> CommitHandle {
+        self.commit.clone()
+    }
+
+    /// With `SyncPolicy::Always`, write operations no longer wait for their
+    /// records to be persisted if `value` is `true`. Callers sharing the
+    /// store should wait with `CommitHandle::wait(store.last_seq())` after
+    /// releasing it instead, so that concurrent writes are synced in batches.
+    pub fn set_defer_commits(&mut self, value: bool) {
+        self.defer_commits = value;
+    }
+
+    /// Record written to the active data file, sync it by policy.
+    pub(super) fn commit(&self, seq: u64, size: u64) -> Result<()> {
+        let unsynced_bytes = self.commit.written(seq, size);
+        match self.config.sync_policy {
+            SyncPolicy::Always if !self.defer_commits => self.commit.wait(seq),
+            SyncPolicy::Bytes(threshold) if unsynced_bytes >= threshold => {
+                self.commit.sync_until(seq)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// All the writes so far are persisted.
+    pub(super) fn mark_synced(&self) {
+        self.commit.synced(self.seq);
+    }
+
+    /// A new active data file is created, previous one is synced.
+    pub(super) fn switch_commit_file(&self, file: Box<dyn VfsFile>) {
+        self.commit.switch(file, self.seq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sync_policy() {
+        for &(s, policy) in &[
+            ("always", SyncPolicy::Always),
+            ("never", SyncPolicy::Never),
+            ("1000ms", SyncPolicy::Interval(Duration::from_secs(1))),
+            ("4096bytes", SyncPolicy::Bytes(4096)),
+        ] {
+            assert_eq!(s.parse::<SyncPolicy>().unwrap(), policy);
+            assert_eq!(policy.to_string(), s);
+        }
+        assert!("everysec".parse::<SyncPolicy>().is_err());
+        assert!("xms".parse::<SyncPolicy>().is_err());
+        assert!("0ms".parse::<SyncPolicy>().is_err());
+    }
+}
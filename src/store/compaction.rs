@@ -0,0 +1,559 @@
+//! Compaction copies live entries (and old versions kept by retention
+//! policy) into new data files, then removes the old ones.
+//!
+//! It runs in steps, so that the caller may release the datastore and
+//! wait for the rate limiter of background IO in between, see
+//! `Server::compact`. Writes made in the meantime go to a new active
+//! data file, whose id is greater than the ones of compaction data
+//! files, so that they win on rebuilding keydir.
+use super::{
+    segment_data_file_path, segment_hint_file_path, KeyDirEntry, KeyspaceState, KeyspaceStats,
+    Store,
+};
+use crate::config;
+use crate::error::{Result, TinkvError};
+use crate::segment::{DataFile, HintEntry, HintFile, RecordKind};
+use crate::util::current_millis;
+use log::{debug, info, trace, warn};
+use std::collections::HashMap;
+use std::time;
+
+/// An entry to be copied by compaction: keyspace, key, position
+/// and kind of the record.
+pub(super) type CompactionEntry = (String, Vec<u8>, KeyDirEntry, RecordKind);
+
+/// State of an ongoing compaction.
+#[derive(Debug)]
+pub(super) struct Compaction {
+    begin_at: time::Instant,
+    // data files whose id is not greater than it will be removed.
+    last_stale_file_id: u64,
+    // id of the active data file created on beginning, compaction
+    // data files must use ids less than it.
+    active_file_id: u64,
+    // old versions first, then the live ones, with kinds of records.
+    entries: Vec<CompactionEntry>,
+    total_retained: usize,
+    next: usize,
+    data_file: DataFile,
+    hint_file: HintFile,
+    total_size_of_compaction_files: u64,
+    size_of_stale_files: u64,
+    stale: StaleEntries,
+}
+
+/// Stale entries in data files which are kept by compaction.
+#[derive(Debug, Default)]
+struct StaleEntries {
+    total: u64,
+    size: u64,
+    keyspaces: HashMap<String, (u64, u64)>,
+}
+
+impl Compaction {
+    /// Return `true` if the data file is written by the compaction.
+    pub(super) fn owns(&self, file_id: u64) -> bool {
+        file_id > self.last_stale_file_id && file_id < self.active_file_id
+    }
+
+    /// Record a stale entry found during compaction.
+    pub(super) fn mark_stale(&mut self, keyspace: &str, segment_id: u64, size: u64) {
+        // entries of the removed data files are gone with them.
+        if segment_id <= self.last_stale_file_id {
+            return;
+        }
+        self.stale.total += 1;
+        self.stale.size += size;
+        let (total, size_of) = self.stale.keyspaces.entry(keyspace.to_owned()).or_default();
+        *total += 1;
+        *size_of += size;
+    }
+
+    /// A keyspace is dropped during compaction, its entries become stale.
+    pub(super) fn unindex_keyspace(&mut self, keyspace: &str, ks: &KeyspaceState) {
+        self.stale.keyspaces.remove(keyspace);
+        let entries = ks
+            .keydir
+            .values()
+            .chain(ks.merges.values().flat_map(|state| state.earlier_entries()));
+        let last_stale_file_id = self.last_stale_file_id;
+        for ent in entries.filter(|ent| ent.segment_id > last_stale_file_id) {
+            self.stale.total += 1;
+            self.stale.size += ent.size;
+        }
+    }
+}
+
+impl Store {
+    /// Clear stale entries from data files and reclaim disk space.
+    pub fn compact(&mut self) -> Result<()> {
+        self.begin_compaction()?;
+        loop {
+            match self.compaction_step(config::COMPACTION_STEP_SIZE) {
+                Ok(Some(size)) => self.rate_limiter.acquire(size),
+                Ok(None) => break,
+                Err(e) => {
+                    self.abort_compaction();
+                    return Err(e);
+                }
+            }
+        }
+        self.finish_compaction()
+    }
+
+    /// Begin a compaction, entries are copied by `compaction_step`.
+    pub(crate) fn begin_compaction(&mut self) -> Result<()> {
+        self.check_writeable()?;
+        if self.compaction.is_some() {
+            return Err(TinkvError::Custom("compaction is in progress".to_owned()));
+        }
+        let begin_at = time::Instant::now();
+
+        info!(
+            "there are {} data files need to be compacted",
+            self.data_files.len()
+        );
+
+        // merge operands are folded into base values.
+        self.collapse_merges()?;
+
+        // expired entries will not be copied.
+        let now = current_millis();
+        let mut expired = vec![];
+        for (keyspace, ks) in self.keyspaces.iter() {
+            for (key, keydir_ent) in ks.keydir.iter() {
+                if keydir_ent.is_expired(now) {
+                    expired.push((keyspace.clone(), key.clone()));
+                }
+            }
+        }
+        for (keyspace, key) in expired {
+            self.unindex(&keyspace, &key);
+        }
+
+        // old versions retained by retention policy are copied before the
+        // live ones, so that live ones win on rebuilding keydir from data files.
+        let mut entries = self.retained_versions()?;
+        let total_retained = entries.len();
+        for (keyspace, ks) in self.keyspaces.iter() {
+            for (key, keydir_ent) in ks.keydir.iter() {
+                entries.push((keyspace.clone(), key.clone(), *keydir_ent, RecordKind::Put));
+            }
+        }
+
+        // data files whose id is not greater than the active
+        // one will be removed after compaction.
+        let last_stale_file_id = self.next_file_id() - 1;
+        let size_of_stale_files = self.stats.size_of_all_data_files;
+
+        // reserve ids for compaction data files, a data file is switched
+        // once it exceeds the size limit, so there are at most `n` of them.
+        let size_of_entries: u64 = entries.iter().map(|(_, _, ent, _)| ent.size).sum();
+        let n = size_of_entries / self.config.max_data_file_size.max(1) + 2;
+        let active_file_id = last_stale_file_id + n + 1;
+        self.new_active_data_file(Some(active_file_id))?;
+
+        let (data_file, hint_file) = self.new_compaction_files(last_stale_file_id + 1)?;
+        self.compaction = Some(Compaction {
+            begin_at,
+            last_stale_file_id,
+            active_file_id,
+            entries,
+            total_retained,
+            next: 0,
+            data_file,
+            hint_file,
+            total_size_of_compaction_files: 0,
+            size_of_stale_files,
+            stale: StaleEntries::default(),
+        });
+        Ok(())
+    }
+
+    /// Copy entries of the ongoing compaction until `max_size` bytes are
+    /// read and written. Return the number of bytes, or `None` if all the
+    /// entries are copied.
+    pub(crate) fn compaction_step(&mut self, max_size: u64) -> Result<Option<u64>> {
+        let mut c = self
+            .compaction
+            .take()
+            .ok_or_else(|| TinkvError::Custom("compaction is not in progress".to_owned()))?;
+        let r = self.copy_entries(&mut c, max_size);
+        self.compaction = Some(c);
+        r
+    }
+
+    fn copy_entries(&mut self, c: &mut Compaction, max_size: u64) -> Result<Option<u64>> {
+        if c.next >= c.entries.len() {
+            return Ok(None);
+        }
+
+        let mut size = 0;
+        while size < max_size && c.next < c.entries.len() {
+            let i = c.next;
+            c.next += 1;
+            let (keyspace, key, keydir_ent, kind) = &c.entries[i];
+            let is_retained = i < c.total_retained;
+
+            // live entries may be overwritten or removed since compaction
+            // began, or become the base value of new merge operands.
+            let is_same = |ent: &KeyDirEntry| {
+                ent.segment_id == keydir_ent.segment_id && ent.offset == keydir_ent.offset
+            };
+            let ks = self.keyspaces.get(keyspace);
+            let is_live = ks.and_then(|ks| ks.keydir.get(key)).is_some_and(is_same);
+            let is_base = ks
+                .and_then(|ks| ks.merges.get(key))
+                .and_then(|state| state.base())
+                .is_some_and(is_same);
+            if !is_retained && !is_live && !is_base {
+                continue;
+            }
+
+            if c.data_file.size > self.config.max_data_file_size
+                && c.data_file.id + 1 < c.active_file_id
+            {
+                c.total_size_of_compaction_files += c.data_file.size;
+                c.data_file.sync()?;
+                c.hint_file.sync()?;
+
+                // switch to another compaction data file.
+                let (data_file, hint_file) = self.new_compaction_files(c.data_file.id + 1)?;
+                c.data_file = data_file;
+                c.hint_file = hint_file;
+            }
+
+            let df = self
+                .data_files
+                .get_mut(&keydir_ent.segment_id)
+                .ok_or(TinkvError::SegmentNotFound(keydir_ent.segment_id))?;
+            trace!(
+                "copy key '{}': original data file({}) -> compaction data file({})",
+                String::from_utf8_lossy(key),
+                df.path.display(),
+                c.data_file.path.display()
+            );
+
+            let offset = c
+                .data_file
+                .copy_bytes_from(df, keydir_ent.offset, keydir_ent.size)?;
+            size += keydir_ent.size;
+            let hint = HintEntry {
+                seq: keydir_ent.seq,
+                kind: *kind,
+                keyspace: keyspace.clone(),
+                key: key.clone(),
+                offset,
+                size: keydir_ent.size,
+                expires_at: keydir_ent.expires_at,
+            };
+
+            if is_retained {
+                // old versions are listed in hint file but not indexed,
+                // they become stale on rebuilding keydir, as the newer
+                // versions are copied after them.
+                size += c.hint_file.write(&hint)?;
+                continue;
+            }
+
+            let ks = self
+                .keyspaces
+                .get_mut(keyspace)
+                .expect("keyspace not found");
+            let relocated = ks
+                .keydir
+                .get_mut(key)
+                .filter(|_| is_live)
+                .into_iter()
+                .chain(
+                    ks.merges
+                        .get_mut(key)
+                        .and_then(|state| state.base_mut())
+                        .filter(|_| is_base),
+                );
+            for ent in relocated {
+                ent.segment_id = c.data_file.id;
+                ent.offset = offset;
+            }
+
+            size += c.hint_file.write(&hint)?;
+        }
+
+        // copied entries are readable once the lock is released.
+        c.data_file.flush()?;
+        Ok(Some(size))
+    }
+
+    /// Finish the ongoing compaction, remove the old data files.
+    pub(crate) fn finish_compaction(&mut self) -> Result<()> {
+        let mut c = self
+            .compaction
+            .take()
+            .ok_or_else(|| TinkvError::Custom("compaction is not in progress".to_owned()))?;
+
+        // tomestones are not copied, keep the sequence number of the last
+        // write as a mark, so that it never goes backwards on reopening.
+        let mark = c
+            .data_file
+            .write(self.seq, RecordKind::SeqMark, "", b"", b"", None)?;
+        c.hint_file.write(&HintEntry {
+            seq: self.seq,
+            kind: RecordKind::SeqMark,
+            keyspace: String::new(),
+            key: vec![],
+            offset: mark.offset,
+            size: mark.size,
+            expires_at: None,
+        })?;
+
+        c.data_file.sync()?;
+        c.hint_file.sync()?;
+        self.compacted_seq = self.seq;
+
+        c.total_size_of_compaction_files += c.data_file.size;
+
+        // remove stale segments.
+        let mut stale_segment_count = 0;
+        for df in self.data_files.values() {
+            if df.id <= c.last_stale_file_id {
+                if self.vfs.exists(&df.path) {
+                    debug!("try to remove stale data file: {}", df.path.display());
+                    self.vfs.remove_file(&df.path)?;
+                }
+
+                let hint_file_path = segment_hint_file_path(&self.path, df.id);
+                if self.vfs.exists(&hint_file_path) {
+                    debug!(
+                        "try to remove stale hint file: {}",
+                        &hint_file_path.display()
+                    );
+                    self.vfs.remove_file(&hint_file_path)?;
+                }
+
+                stale_segment_count += 1;
+            }
+        }
+
+        self.data_files.retain(|&k, _| k > c.last_stale_file_id);
+        debug!("cleaned {} stale segments", stale_segment_count);
+
+        info!(
+            "compaction progress done in {:?}",
+            time::Instant::now().duration_since(c.begin_at)
+        );
+
+        // update stats, old versions are stale entries of compaction data files.
+        let mut retained: HashMap<&str, (u64, u64)> = HashMap::new();
+        for (keyspace, _, ent, _) in c.entries[..c.total_retained].iter() {
+            let (total, size) = retained.entry(keyspace.as_str()).or_default();
+            *total += 1;
+            *size += ent.size;
+        }
+        self.stats.total_data_files = self.data_files.len() as u64;
+        self.stats.total_active_entries = 0;
+        for (name, ks) in self.keyspaces.iter_mut() {
+            let stale = c.stale.keyspaces.get(name).cloned().unwrap_or_default();
+            let retained = retained.get(name.as_str()).cloned().unwrap_or_default();
+            ks.stats = KeyspaceStats {
+                total_active_entries: ks.keydir.len() as u64,
+                size_of_active_entries: ks.keydir.values().map(|ent| ent.size).sum::<u64>()
+                    + ks.merges
+                        .values()
+                        .flat_map(|state| state.earlier_entries())
+                        .map(|ent| ent.size)
+                        .sum::<u64>(),
+                total_stale_entries: stale.0 + retained.0,
+                size_of_stale_entries: stale.1 + retained.1,
+            };
+            self.stats.total_active_entries += ks.stats.total_active_entries;
+        }
+        self.stats.total_stale_entries =
+            c.stale.total + retained.values().map(|(total, _)| total).sum::<u64>();
+        self.stats.size_of_stale_entries =
+            c.stale.size + retained.values().map(|(_, size)| size).sum::<u64>();
+        self.stats.size_of_all_data_files = self.stats.size_of_all_data_files
+            + c.total_size_of_compaction_files
+            - c.size_of_stale_files;
+
+        Ok(())
+    }
+
+    /// Give up the ongoing compaction, old data files are kept. Entries
+    /// moved to compaction data files are pointed back at the old ones,
+    /// then compaction data files and their hint files are removed.
+    pub(crate) fn abort_compaction(&mut self) {
+        let c = match self.compaction.take() {
+            Some(c) => c,
+            None => return,
+        };
+        info!("compaction aborted after copying {} entries", c.next);
+
+        for (keyspace, key, keydir_ent, _) in c.entries[..c.next].iter().skip(c.total_retained) {
+            let ks = match self.keyspaces.get_mut(keyspace) {
+                Some(ks) => ks,
+                None => continue,
+            };
+            // entries changed since they were copied are left alone.
+            let moved = ks
+                .keydir
+                .get_mut(key)
+                .into_iter()
+                .chain(ks.merges.get_mut(key).and_then(|state| state.base_mut()));
+            for ent in moved {
+                if c.owns(ent.segment_id) && ent.seq == keydir_ent.seq {
+                    ent.segment_id = keydir_ent.segment_id;
+                    ent.offset = keydir_ent.offset;
+                }
+            }
+        }
+
+        let file_ids = self
+            .data_files
+            .keys()
+            .cloned()
+            .filter(|&id| c.owns(id))
+            .collect::<Vec<_>>();
+        drop(c);
+        for file_id in file_ids {
+            if let Some(df) = self.data_files.remove(&file_id) {
+                let hint_file_path = segment_hint_file_path(&self.path, file_id);
+                for path in [&df.path, &hint_file_path] {
+                    if let Err(e) = self.vfs.remove_file(path) {
+                        // ignored on opening, as compaction is unfinished.
+                        warn!("failed to remove {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Create a compaction data file and its hint file.
+    fn new_compaction_files(&mut self, file_id: u64) -> Result<(DataFile, HintFile)> {
+        let data_file_path = segment_data_file_path(&self.path, file_id);
+        debug!("create compaction data file: {}", data_file_path.display());
+        let data_file = DataFile::new(self.vfs.clone(), &data_file_path, true)?;
+
+        // register read-only compaction data file.
+        self.data_files.insert(
+            data_file.id,
+            DataFile::new(self.vfs.clone(), &data_file.path, false)?,
+        );
+
+        let hint_file_path = segment_hint_file_path(&self.path, file_id);
+        debug!("create compaction hint file: {}", hint_file_path.display());
+        let hint_file = HintFile::new(self.vfs.clone(), &hint_file_path, true)?;
+        Ok((data_file, hint_file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::OpenOptions;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_write_between_steps() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut store = Store::open(tmpdir.path()).unwrap();
+        for i in 0..10u8 {
+            store.set(&[i], &[i; 100]).unwrap();
+        }
+        store.remove(&[9]).unwrap();
+
+        store.begin_compaction().unwrap();
+        assert!(store.begin_compaction().is_err());
+        assert!(store.compaction_step(1).unwrap().is_some());
+        store.set(&[0], b"new").unwrap();
+        store.set(&[5], b"new").unwrap();
+        store.remove(&[6]).unwrap();
+        store.set(&[10], b"new").unwrap();
+        while store.compaction_step(100).unwrap().is_some() {}
+        store.finish_compaction().unwrap();
+
+        let check = |store: &mut Store| {
+            for i in 0..11u8 {
+                let expected = match i {
+                    0 | 5 | 10 => Some(b"new".to_vec()),
+                    6 | 9 => None,
+                    _ => Some(vec![i; 100]),
+                };
+                assert_eq!(store.get(&[i]).unwrap(), expected);
+            }
+        };
+        check(&mut store);
+        let stats = *store.stats();
+        assert_eq!(stats.total_active_entries, 9);
+        // key 0 is overwritten after copying, the others are changed
+        // before copying, only their tomestones are left.
+        assert_eq!(stats.total_stale_entries, 2);
+        drop(store);
+
+        let mut store = Store::open(tmpdir.path()).unwrap();
+        check(&mut store);
+        assert_eq!(store.stats().total_active_entries, 9);
+        assert_eq!(store.stats().total_stale_entries, 2);
+        assert_eq!(
+            store.stats().size_of_stale_entries,
+            stats.size_of_stale_entries
+        );
+    }
+
+    #[test]
+    fn test_abort_compaction() {
+        let tmpdir = TempDir::new().unwrap();
+        let files = || std::fs::read_dir(tmpdir.path()).unwrap().count();
+        let mut store = OpenOptions::new()
+            .retain_versions(2)
+            .open(tmpdir.path())
+            .unwrap();
+        store.set(b"k", b"v1").unwrap();
+        store.set(b"k", b"v2").unwrap();
+        for i in 0..10u8 {
+            store.set(&[i], &[i; 100]).unwrap();
+        }
+        let total_files = files();
+
+        // the old version and some live entries are copied.
+        store.begin_compaction().unwrap();
+        store.compaction_step(300).unwrap();
+        store.set(&[0], b"new").unwrap();
+        store.abort_compaction();
+        assert_eq!(store.get(b"k").unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(store.get(&[0]).unwrap(), Some(b"new".to_vec()));
+        assert_eq!(store.get(&[1]).unwrap(), Some(vec![1; 100]));
+        drop(store);
+        // a new active data file is left.
+        assert_eq!(files(), total_files + 1);
+
+        let mut store = Store::open(tmpdir.path()).unwrap();
+        assert_eq!(store.get(b"k").unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(store.get(&[0]).unwrap(), Some(b"new".to_vec()));
+        assert_eq!(store.get(&[1]).unwrap(), Some(vec![1; 100]));
+        store.compact().unwrap();
+        assert_eq!(store.get(b"k").unwrap(), Some(b"v2".to_vec()));
+    }
+
+    #[test]
+    fn test_checkpoint_during_compaction() {
+        let tmpdir = TempDir::new().unwrap();
+        let mut store = OpenOptions::new()
+            .retain_versions(2)
+            .open(tmpdir.path())
+            .unwrap();
+        store.set(b"k", b"v1").unwrap();
+        store.set(b"k", b"v2").unwrap();
+
+        store.begin_compaction().unwrap();
+        store.compaction_step(1).unwrap();
+        // files still written by compaction are not linked.
+        let dir = tmpdir.path().join("checkpoint");
+        let manifest = store.checkpoint(&dir).unwrap();
+        assert!(manifest.files.iter().all(|f| f.name.ends_with(".data")));
+        while store.compaction_step(100).unwrap().is_some() {}
+        store.finish_compaction().unwrap();
+
+        let mut checkpoint = Store::open(&dir).unwrap();
+        assert_eq!(checkpoint.get(b"k").unwrap(), Some(b"v2".to_vec()));
+    }
+}
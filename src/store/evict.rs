@@ -0,0 +1,217 @@
+//! Limits of datastore size and eviction of keys, like `maxmemory`
+//! and `maxmemory-policy` of redis.
+use super::{KeyDirEntry, Store};
+use crate::error::{Result, TinkvError};
+use log::trace;
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::str::FromStr;
+
+/// Which keys to evict once datastore reaches its limits.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum EvictionPolicy {
+    /// Never evict keys, writes fail with `TinkvError::StoreFull`.
+    #[default]
+    NoEviction,
+    /// Evict the least recently used keys.
+    Lru,
+    /// Evict the least frequently used keys.
+    Lfu,
+    /// Evict random keys.
+    Random,
+    /// Evict keys nearest to expire, keys without ttl are never evicted.
+    VolatileTtl,
+}
+
+impl fmt::Display for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::Lru => "allkeys-lru",
+            EvictionPolicy::Lfu => "allkeys-lfu",
+            EvictionPolicy::Random => "allkeys-random",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = TinkvError;
+
+    /// Parse policy names of redis `maxmemory-policy`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_ref() {
+            "noeviction" => Ok(EvictionPolicy::NoEviction),
+            "allkeys-lru" => Ok(EvictionPolicy::Lru),
+            "allkeys-lfu" => Ok(EvictionPolicy::Lfu),
+            "allkeys-random" => Ok(EvictionPolicy::Random),
+            "volatile-ttl" => Ok(EvictionPolicy::VolatileTtl),
+            _ => Err(TinkvError::Custom(format!(
+                "invalid eviction policy '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+// eviction priority of a key, the lowest one is evicted first.
+type Rank = (u64, u64, String, Vec<u8>);
+
+/// Keys ordered by eviction priority, maintained along with keydirs.
+#[derive(Debug)]
+pub(super) struct EvictionState {
+    policy: EvictionPolicy,
+    // logical clock, ticks on each access.
+    clock: u64,
+    order: BTreeSet<Rank>,
+    random: RandomState,
+}
+
+impl EvictionState {
+    pub(super) fn new(policy: EvictionPolicy) -> Self {
+        EvictionState {
+            policy,
+            clock: 0,
+            order: BTreeSet::new(),
+            random: RandomState::new(),
+        }
+    }
+
+    fn rank(&self, keyspace: &str, key: &[u8], ent: &KeyDirEntry) -> Option<Rank> {
+        let (major, minor) = match self.policy {
+            EvictionPolicy::NoEviction => return None,
+            EvictionPolicy::Lru => (ent.accessed, 0),
+            EvictionPolicy::Lfu => (ent.hits, ent.accessed),
+            EvictionPolicy::Random => (self.random.hash_one((key, ent.seq)), 0),
+            EvictionPolicy::VolatileTtl => (ent.expires_at?, ent.seq),
+        };
+        Some((major, minor, keyspace.to_owned(), key.to_vec()))
+    }
+
+    /// A key is written, `old` is its previous entry.
+    pub(super) fn insert(
+        &mut self,
+        keyspace: &str,
+        key: &[u8],
+        ent: &mut KeyDirEntry,
+        old: Option<&KeyDirEntry>,
+    ) {
+        if let Some(old) = old {
+            self.remove(keyspace, key, old);
+        }
+        self.clock += 1;
+        ent.accessed = self.clock;
+        ent.hits = old.map(|old| old.hits.saturating_add(1)).unwrap_or(1);
+        if let Some(rank) = self.rank(keyspace, key, ent) {
+            self.order.insert(rank);
+        }
+    }
+
+    /// A key is removed from keydir.
+    pub(super) fn remove(&mut self, keyspace: &str, key: &[u8], ent: &KeyDirEntry) {
+        if let Some(rank) = self.rank(keyspace, key, ent) {
+            self.order.remove(&rank);
+        }
+    }
+
+    /// A key is read.
+    pub(super) fn touch(&mut self, keyspace: &str, key: &[u8], ent: &mut KeyDirEntry) {
+        let old = *ent;
+        self.insert(keyspace, key, ent, Some(&old));
+    }
+
+    /// Return the key to be evicted next, except the given one.
+    fn victim(&self, keyspace: &str, key: &[u8]) -> Option<(String, Vec<u8>)> {
+        self.order
+            .iter()
+            .find(|(_, _, ks, k)| !(ks == keyspace && k == key))
+            .map(|(_, _, ks, k)| (ks.clone(), k.clone()))
+    }
+}
+
+impl Store {
+    /// Evict keys before writing key of the keyspace, until the datastore
+    /// is within its limits. The size of datastore may exceed the limit by
+    /// the size of the entry to be written.
+    pub(super) fn make_room(&mut self, keyspace: &str, key: &[u8]) -> Result<()> {
+        let (max_keys, max_total_size) = (self.config.max_keys, self.config.max_total_size);
+        if max_keys.is_none() && max_total_size.is_none() {
+            return Ok(());
+        }
+
+        let new_key = !self.keydir(keyspace).is_some_and(|k| k.contains_key(key));
+        loop {
+            let over_keys = max_keys
+                .map(|max| self.stats.total_active_entries + new_key as u64 > max)
+                .unwrap_or(false);
+            let over_size = max_total_size
+                .map(|max| self.size_of_active_entries() >= max)
+                .unwrap_or(false);
+            if !over_keys && !over_size {
+                return Ok(());
+            }
+
+            let victim = self
+                .eviction
+                .as_ref()
+                .and_then(|eviction| eviction.victim(keyspace, key));
+            match victim {
+                Some((keyspace, key)) => self.evict(&keyspace, &key)?,
+                None => return Err(TinkvError::StoreFull),
+            }
+        }
+    }
+
+    fn evict(&mut self, keyspace: &str, key: &[u8]) -> Result<()> {
+        trace!(
+            "evict key '{}' from keyspace '{}'",
+            String::from_utf8_lossy(key),
+            keyspace
+        );
+        if self.contains_key_in(keyspace, key) {
+            self.remove_from(keyspace, key)?;
+        } else {
+            // expired keys are never read again, no tomestone is needed.
+            self.unindex(keyspace, key);
+        }
+        self.stats.total_evicted_keys += 1;
+        Ok(())
+    }
+
+    /// Return total size of live entries in all keyspaces.
+    pub(super) fn size_of_active_entries(&self) -> u64 {
+        self.keyspaces
+            .values()
+            .map(|ks| ks.stats.size_of_active_entries)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_eviction_policy() {
+        for &policy in &[
+            EvictionPolicy::NoEviction,
+            EvictionPolicy::Lru,
+            EvictionPolicy::Lfu,
+            EvictionPolicy::Random,
+            EvictionPolicy::VolatileTtl,
+        ] {
+            assert_eq!(
+                policy.to_string().parse::<EvictionPolicy>().unwrap(),
+                policy
+            );
+        }
+        assert_eq!(
+            "ALLKEYS-LRU".parse::<EvictionPolicy>().unwrap(),
+            EvictionPolicy::Lru
+        );
+        assert!("lru".parse::<EvictionPolicy>().is_err());
+    }
+}
@@ -0,0 +1,349 @@
+//! Logical export and import of key value pairs in JSON Lines or CSV,
+//! and import from Redis RDB dump files.
+use super::Store;
+use crate::config;
+use crate::error::{Result, TinkvError};
+use crate::rdb;
+use crate::util::current_millis;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::ops::Bound;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Text format of exported key value pairs.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    /// One JSON object per line: `{"key":"...","value":"..."}`.
+    JsonLines,
+    /// CSV with a `key,value` header.
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = TinkvError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_ref() {
+            "jsonl" | "json" => Ok(Format::JsonLines),
+            "csv" => Ok(Format::Csv),
+            _ => Err(TinkvError::Custom(format!("unknown format '{}'", s))),
+        }
+    }
+}
+
+/// Text encoding of binary keys and values.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Encoding {
+    Base64,
+    Hex,
+}
+
+impl FromStr for Encoding {
+    type Err = TinkvError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_ref() {
+            "base64" => Ok(Encoding::Base64),
+            "hex" => Ok(Encoding::Hex),
+            _ => Err(TinkvError::Custom(format!("unknown encoding '{}'", s))),
+        }
+    }
+}
+
+impl Encoding {
+    fn encode(self, value: &[u8]) -> String {
+        match self {
+            Encoding::Base64 => base64::encode(value),
+            Encoding::Hex => hex::encode(value),
+        }
+    }
+
+    fn decode(self, value: &str) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Base64 => base64::decode(value)
+                .map_err(|e| TinkvError::Custom(format!("invalid base64 '{}': {}", value, e))),
+            Encoding::Hex => hex::decode(value)
+                .map_err(|e| TinkvError::Custom(format!("invalid hex '{}': {}", value, e))),
+        }
+    }
+}
+
+/// Options of export and import.
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    format: Format,
+    encoding: Encoding,
+    prefix: Vec<u8>,
+    keyspace: String,
+    batch_size: usize,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: Format::JsonLines,
+            encoding: Encoding::Base64,
+            prefix: vec![],
+            keyspace: config::DEFAULT_KEYSPACE.to_owned(),
+            batch_size: 1000,
+        }
+    }
+}
+
+impl ExportOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn format(&mut self, value: Format) -> &mut Self {
+        self.format = value;
+        self
+    }
+
+    pub fn encoding(&mut self, value: Encoding) -> &mut Self {
+        self.encoding = value;
+        self
+    }
+
+    /// Only keys starting with `value` are exported (or imported).
+    pub fn prefix(&mut self, value: &[u8]) -> &mut Self {
+        self.prefix = value.to_vec();
+        self
+    }
+
+    pub fn keyspace(&mut self, value: &str) -> &mut Self {
+        self.keyspace = value.to_owned();
+        self
+    }
+
+    /// Number of pairs written before syncing to disk on import, and
+    /// number of keys read from keydir at a time on export.
+    pub fn batch_size(&mut self, value: usize) -> &mut Self {
+        self.batch_size = value.max(1);
+        self
+    }
+}
+
+/// Summary of importing a Redis RDB file.
+#[derive(Debug, Clone, Default)]
+pub struct RdbImportStats {
+    /// total imported string keys.
+    pub imported: u64,
+    /// total keys already expired, they are not imported.
+    pub expired: u64,
+    /// total keys failed to import, e.g. key or value is too large.
+    pub failed: u64,
+    /// skipped keys of unsupported value types, grouped by type name.
+    pub skipped: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    key: String,
+    value: String,
+}
+
+impl Store {
+    /// Export all live key value pairs in keydir order to writer `w`.
+    /// Return the number of exported pairs.
+    pub fn export<W: Write>(&mut self, w: W, options: &ExportOptions) -> Result<u64> {
+        // keys are collected in chunks, reading values requires a
+        // mutable borrow, and the whole keydir must not be copied.
+        let mut count = 0;
+        let mut writer = RecordWriter::new(w, options.format);
+        let mut start = Bound::Included(options.prefix.clone());
+        loop {
+            let now = current_millis();
+            let keys = self
+                .keydir(&options.keyspace)
+                .into_iter()
+                .flat_map(|keydir| keydir.range((start.clone(), Bound::Unbounded)))
+                .take_while(|(key, _)| key.starts_with(&options.prefix))
+                .filter(|(_, keydir_ent)| !keydir_ent.is_expired(now))
+                .map(|(key, _)| key.clone())
+                .take(options.batch_size)
+                .collect::<Vec<_>>();
+            let last = match keys.last() {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            debug!("export {} keys from '{}'", keys.len(), &options.keyspace);
+
+            for key in keys {
+                if let Some(value) = self.get_from(&options.keyspace, &key)? {
+                    writer.write(&Record {
+                        key: options.encoding.encode(&key),
+                        value: options.encoding.encode(&value),
+                    })?;
+                    count += 1;
+                }
+            }
+            start = Bound::Excluded(last);
+        }
+        writer.flush()?;
+
+        info!("exported {} pairs from '{}'", count, &options.keyspace);
+        Ok(count)
+    }
+
+    /// Import key value pairs from reader `r`, pairs are written in batches.
+    /// Return the number of imported pairs.
+    pub fn import<R: Read>(&mut self, r: R, options: &ExportOptions) -> Result<u64> {
+        let records: Box<dyn Iterator<Item = Result<Record>>> = match options.format {
+            Format::JsonLines => {
+                Box::new(BufReader::new(r).lines().filter_map(|line| match line {
+                    Ok(line) if line.trim().is_empty() => None,
+                    Ok(line) => Some(serde_json::from_str(&line).map_err(TinkvError::from)),
+                    Err(e) => Some(Err(e.into())),
+                }))
+            }
+            Format::Csv => Box::new(
+                csv::Reader::from_reader(r)
+                    .into_deserialize()
+                    .map(|r| r.map_err(TinkvError::from)),
+            ),
+        };
+
+        let mut count = 0;
+        let mut batch = Vec::with_capacity(options.batch_size);
+        for record in records {
+            let record = record?;
+            let key = options.encoding.decode(&record.key)?;
+            if !key.starts_with(&options.prefix) {
+                continue;
+            }
+            batch.push((key, options.encoding.decode(&record.value)?));
+
+            if batch.len() >= options.batch_size {
+                count += self.import_batch(&options.keyspace, &mut batch)?;
+            }
+        }
+        count += self.import_batch(&options.keyspace, &mut batch)?;
+
+        info!("imported {} pairs into '{}'", count, &options.keyspace);
+        Ok(count)
+    }
+
+    /// Import string keys (with ttl) from a Redis RDB dump file. Keys in
+    /// database 0 are imported into the default keyspace, keys in database
+    /// `N` are imported into keyspace `dbN`.
+    ///
+    /// Keys of other value types are skipped and reported.
+    pub fn import_rdb<R: Read>(&mut self, r: R) -> Result<RdbImportStats> {
+        let mut stats = RdbImportStats::default();
+        for item in rdb::Parser::new(BufReader::new(r))? {
+            match item? {
+                rdb::Item::String {
+                    db,
+                    key,
+                    value,
+                    expires_at,
+                } => {
+                    let keyspace = match db {
+                        0 => config::DEFAULT_KEYSPACE.to_owned(),
+                        _ => format!("db{}", db),
+                    };
+
+                    let now = current_millis();
+                    let ttl = match expires_at {
+                        Some(t) if t <= now => {
+                            stats.expired += 1;
+                            continue;
+                        }
+                        Some(t) => Some(Duration::from_millis(t - now)),
+                        None => None,
+                    };
+
+                    match self.set_in(&keyspace, &key, &value, ttl) {
+                        Ok(()) => stats.imported += 1,
+                        Err(e @ TinkvError::KeyIsTooLarge)
+                        | Err(e @ TinkvError::ValueIsTooLarge) => {
+                            warn!(
+                                "failed to import key '{}': {}",
+                                String::from_utf8_lossy(&key),
+                                e
+                            );
+                            stats.failed += 1;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                rdb::Item::Skipped { type_name, .. } => {
+                    *stats.skipped.entry(type_name.to_owned()).or_default() += 1;
+                }
+            }
+        }
+        self.sync()?;
+
+        info!("import rdb done: {:?}", &stats);
+        Ok(stats)
+    }
+
+    fn import_batch(&mut self, keyspace: &str, batch: &mut Vec<(Vec<u8>, Vec<u8>)>) -> Result<u64> {
+        let count = batch.len() as u64;
+        for (key, value) in batch.drain(..) {
+            self.set_in(keyspace, &key, &value, None)?;
+        }
+        self.sync()?;
+        Ok(count)
+    }
+}
+
+enum RecordWriter<W: Write> {
+    JsonLines(W),
+    Csv(Box<csv::Writer<W>>),
+}
+
+impl<W: Write> RecordWriter<W> {
+    fn new(w: W, format: Format) -> Self {
+        match format {
+            Format::JsonLines => RecordWriter::JsonLines(w),
+            Format::Csv => RecordWriter::Csv(Box::new(csv::Writer::from_writer(w))),
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> Result<()> {
+        match self {
+            RecordWriter::JsonLines(w) => {
+                serde_json::to_writer(&mut *w, record)?;
+                w.write_all(b"\n")?;
+            }
+            RecordWriter::Csv(w) => w.serialize(record)?,
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            RecordWriter::JsonLines(w) => w.flush()?,
+            RecordWriter::Csv(w) => w.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding() {
+        let value = b"\x00tinkv\xff";
+        for encoding in [Encoding::Base64, Encoding::Hex].iter() {
+            let encoded = encoding.encode(value);
+            assert_eq!(encoding.decode(&encoded).unwrap(), value.to_vec());
+        }
+        assert_eq!(Encoding::Hex.encode(b"hi"), "6869");
+        assert!(Encoding::Hex.decode("zz").is_err());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!("jsonl".parse::<Format>().unwrap(), Format::JsonLines);
+        assert_eq!("CSV".parse::<Format>().unwrap(), Format::Csv);
+        assert!("xml".parse::<Format>().is_err());
+    }
+}
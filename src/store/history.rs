@@ -0,0 +1,263 @@
+//! Multi-version reads. Old versions of keys stay in data files until
+//! compaction, and compaction may keep some of them by retention policy.
+use super::compaction::CompactionEntry;
+use super::{segment_hint_file_path, EntryMeta, KeyDirEntry, Store};
+use crate::config;
+use crate::error::{Result, TinkvError};
+use crate::segment::{DataEntry, DataFile, HintFile, RecordKind};
+use crate::util::current_timestamp;
+use log::debug;
+use std::collections::{BTreeMap, HashMap};
+
+/// An old version to be kept or dropped on compaction.
+struct Candidate {
+    // `None` if the version is listed by a hint file.
+    timestamp: Option<u64>,
+    removed: bool,
+    keydir_ent: KeyDirEntry,
+}
+
+/// A version of key value pair found in data files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Version {
+    /// value of the version, `None` if the key was removed.
+    pub value: Option<Vec<u8>>,
+    pub meta: EntryMeta,
+}
+
+impl Store {
+    /// Return all the versions of a key still present in data files,
+    /// ordered from the oldest to the newest one. Merge operands are
+    /// not listed.
+    ///
+    /// All data files are scanned, it's slow for large datastore.
+    pub fn history(&self, key: &[u8]) -> Result<Vec<Version>> {
+        self.history_of(config::DEFAULT_KEYSPACE, key)
+    }
+
+    pub(super) fn history_of(&self, keyspace: &str, key: &[u8]) -> Result<Vec<Version>> {
+        let mut versions = vec![];
+        let mut dropped_at = 0;
+        self.scan_entries(|entry| {
+            if entry.keyspace() != keyspace {
+                return;
+            }
+
+            if entry.kind() == RecordKind::DropKeyspace {
+                dropped_at = dropped_at.max(entry.seq());
+            } else if entry.key() == key && entry.kind() != RecordKind::Merge {
+                let value = if entry.kind() == RecordKind::Remove {
+                    None
+                } else {
+                    Some(entry.value().to_vec())
+                };
+                versions.push(Version {
+                    value,
+                    meta: EntryMeta {
+                        version: entry.seq(),
+                        timestamp_ns: entry.timestamp(),
+                        expires_at_ms: entry.expires_at(),
+                    },
+                });
+            }
+        })?;
+
+        // versions before the keyspace was dropped are gone.
+        versions.retain(|v| v.meta.version > dropped_at);
+        versions.sort_by_key(|v| v.meta.version);
+        Ok(versions)
+    }
+
+    /// Get value of a key as of the given version, which is the
+    /// value of the newest version not greater than `version`.
+    ///
+    /// Expiration time of values is ignored.
+    pub fn get_at(&self, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+        self.get_at_in(config::DEFAULT_KEYSPACE, key, version)
+    }
+
+    pub(super) fn get_at_in(
+        &self,
+        keyspace: &str,
+        key: &[u8],
+        version: u64,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .history_of(keyspace, key)?
+            .into_iter()
+            .rev()
+            .find(|v| v.meta.version <= version)
+            .and_then(|v| v.value))
+    }
+
+    /// Return old versions (and tomestones) to be kept on compaction by
+    /// retention policy, ordered by sequence numbers.
+    ///
+    /// Compaction data files are read through their hint files, which
+    /// list the old versions kept by last compaction as well.
+    pub(super) fn retained_versions(&mut self) -> Result<Vec<CompactionEntry>> {
+        let keep = self.config.retain_versions;
+        if keep <= 1 && self.config.retain_window.is_none() {
+            return Ok(vec![]);
+        }
+
+        let newer_than = self
+            .config
+            .retain_window
+            .map(|window| (current_timestamp() as u64).saturating_sub(window.as_nanos() as u64))
+            .unwrap_or(u64::MAX);
+
+        let mut dropped_at: HashMap<String, u64> = HashMap::new();
+        let mut versions: BTreeMap<(String, Vec<u8>), Vec<Candidate>> = BTreeMap::new();
+        self.scan_versions(|kind, keyspace, key, timestamp, keydir_ent| {
+            match kind {
+                // merge operands are collapsed into values by compaction.
+                RecordKind::SeqMark | RecordKind::Merge => {}
+                RecordKind::DropKeyspace => {
+                    let seq = dropped_at.entry(keyspace.to_owned()).or_default();
+                    *seq = (*seq).max(keydir_ent.seq);
+                }
+                RecordKind::Put | RecordKind::Remove => versions
+                    .entry((keyspace.to_owned(), key.to_vec()))
+                    .or_default()
+                    .push(Candidate {
+                        timestamp,
+                        removed: kind == RecordKind::Remove,
+                        keydir_ent,
+                    }),
+            }
+        })?;
+
+        let mut retained = vec![];
+        for ((keyspace, key), mut entries) in versions {
+            let dropped_at = dropped_at.get(&keyspace).cloned().unwrap_or_default();
+            let live_seq = self
+                .keydir(&keyspace)
+                .and_then(|keydir| keydir.get(&key))
+                .map(|ent| ent.seq);
+
+            entries.retain(|c| c.keydir_ent.seq > dropped_at);
+            entries.sort_by_key(|c| std::cmp::Reverse(c.keydir_ent.seq));
+            let mut kept = vec![];
+            for (i, c) in entries.into_iter().enumerate() {
+                // live version is always copied.
+                if Some(c.keydir_ent.seq) == live_seq {
+                    continue;
+                }
+                if i >= keep && (newer_than == u64::MAX || self.timestamp_of(&c)? < newer_than) {
+                    continue;
+                }
+                kept.push(c);
+            }
+
+            // versions of an expired key are dropped, there is no tomestone
+            // to keep the newest one from being indexed on rebuilding keydir.
+            if live_seq.is_none() && kept.first().is_some_and(|c| !c.removed) {
+                continue;
+            }
+
+            // a tomestone is kept only if older versions are kept,
+            // they must not come back on rebuilding keydir.
+            if kept.len() == 1 && kept[0].removed {
+                continue;
+            }
+
+            for c in kept {
+                let kind = if c.removed {
+                    RecordKind::Remove
+                } else {
+                    RecordKind::Put
+                };
+                retained.push((keyspace.clone(), key.clone(), c.keydir_ent, kind));
+            }
+        }
+        retained.sort_by_key(|(_, _, ent, _)| ent.seq);
+
+        debug!("retain {} old versions on compaction", retained.len());
+        Ok(retained)
+    }
+
+    /// Return timestamp of a version, it's read from data file if the
+    /// version is listed by a hint file.
+    fn timestamp_of(&mut self, c: &Candidate) -> Result<u64> {
+        if let Some(timestamp) = c.timestamp {
+            return Ok(timestamp);
+        }
+        let segment_id = c.keydir_ent.segment_id;
+        let df = self
+            .data_files
+            .get_mut(&segment_id)
+            .ok_or(TinkvError::SegmentNotFound(segment_id))?;
+        Ok(df.read(c.keydir_ent.offset)?.timestamp())
+    }
+
+    /// Call `f` with kind, keyspace, key, timestamp and position of each
+    /// record in data files. Records of compaction data files are read
+    /// from their hint files, without timestamps.
+    fn scan_versions<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(RecordKind, &str, &[u8], Option<u64>, KeyDirEntry),
+    {
+        for df in self.data_files.values() {
+            let hint_file_path = segment_hint_file_path(&self.path, df.id);
+            if !self.vfs.exists(&hint_file_path) {
+                self.scan_data_file(df, |entry| {
+                    let keydir_ent = KeyDirEntry::new(
+                        entry.file_id,
+                        entry.offset,
+                        entry.size,
+                        entry.seq(),
+                        entry.expires_at(),
+                    );
+                    f(
+                        entry.kind(),
+                        entry.keyspace(),
+                        entry.key(),
+                        Some(entry.timestamp()),
+                        keydir_ent,
+                    );
+                })?;
+                continue;
+            }
+
+            let mut hint_file = HintFile::new(self.vfs.clone(), &hint_file_path, false)?;
+            for entry in hint_file.entry_iter() {
+                let entry = entry?;
+                let keydir_ent =
+                    KeyDirEntry::new(df.id, entry.offset, entry.size, entry.seq, entry.expires_at);
+                f(entry.kind, &entry.keyspace, &entry.key, None, keydir_ent);
+            }
+        }
+        Ok(())
+    }
+
+    /// Call `f` for each entry in data files.
+    fn scan_entries<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&DataEntry),
+    {
+        for df in self.data_files.values() {
+            self.scan_data_file(df, &mut f)?;
+        }
+        Ok(())
+    }
+
+    /// Call `f` for each entry in a data file.
+    fn scan_data_file<F>(&self, df: &DataFile, mut f: F) -> Result<()>
+    where
+        F: FnMut(&DataEntry),
+    {
+        for entry in df.entry_iter()? {
+            let entry = entry?;
+            if !entry.is_valid() {
+                return Err(TinkvError::DataEntryCorrupted {
+                    file_id: df.id,
+                    key: entry.key().into(),
+                    offset: entry.offset,
+                });
+            }
+            f(&entry);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//! Distribution of key sizes, value sizes and key prefixes, to help
+//! tuning size limits of datastore.
+use super::{KeyDirEntry, Store};
+use crate::segment::value_size_of;
+use std::collections::BTreeMap;
+
+/// Counts of sizes in power of two buckets.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SizeHistogram {
+    // sizes in `[2^(i-1), 2^i - 1]` are counted in `counts[i]`,
+    // `counts[0]` counts size `0`.
+    counts: Vec<u64>,
+}
+
+impl SizeHistogram {
+    fn bucket(size: u64) -> usize {
+        (64 - size.leading_zeros()) as usize
+    }
+
+    fn bounds(bucket: usize) -> (u64, u64) {
+        match bucket {
+            0 => (0, 0),
+            i => (1 << (i - 1), u64::MAX >> (64 - i)),
+        }
+    }
+
+    fn add(&mut self, size: u64) {
+        let i = Self::bucket(size);
+        if self.counts.len() <= i {
+            self.counts.resize(i + 1, 0);
+        }
+        self.counts[i] += 1;
+    }
+
+    fn remove(&mut self, size: u64) {
+        if let Some(count) = self.counts.get_mut(Self::bucket(size)) {
+            *count = count.saturating_sub(1);
+        }
+        // keep equal histograms equal no matter how they're built.
+        while self.counts.last() == Some(&0) {
+            self.counts.pop();
+        }
+    }
+
+    /// Return total number of sizes counted.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Return non-empty buckets as `(min, max, count)`, bounds are inclusive.
+    pub fn buckets(&self) -> Vec<(u64, u64, u64)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(i, &count)| {
+                let (min, max) = Self::bounds(i);
+                (min, max, count)
+            })
+            .collect()
+    }
+
+    /// Return upper bound of the bucket holding the `p`-th (`0..=100`)
+    /// percentile size, `0` if nothing is counted.
+    pub fn percentile(&self, p: f64) -> u64 {
+        let total = self.count();
+        if total == 0 {
+            return 0;
+        }
+        let rank = ((p / 100.0) * total as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::bounds(i).1;
+            }
+        }
+        Self::bounds(self.counts.len() - 1).1
+    }
+}
+
+/// Distribution of live keys in all keyspaces, it's updated on
+/// writes and rebuilt on opening.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyStats {
+    pub key_sizes: SizeHistogram,
+    /// sizes of the latest values, merge operands not folded yet
+    /// are counted instead of the merged values.
+    pub value_sizes: SizeHistogram,
+    /// number of keys by prefix, which is the part of key before the
+    /// first delimiter. Keys without delimiter have an empty prefix.
+    pub prefixes: BTreeMap<Vec<u8>, u64>,
+}
+
+impl KeyStats {
+    fn prefix(key: &[u8], delimiter: u8) -> &[u8] {
+        match key.iter().position(|&b| b == delimiter) {
+            Some(i) => &key[..i],
+            None => b"",
+        }
+    }
+
+    pub(super) fn add(&mut self, keyspace: &str, key: &[u8], ent: &KeyDirEntry, delimiter: u8) {
+        self.key_sizes.add(key.len() as u64);
+        self.value_sizes
+            .add(value_size_of(ent.size, keyspace, key, ent.expires_at));
+        let prefix = Self::prefix(key, delimiter);
+        match self.prefixes.get_mut(prefix) {
+            Some(count) => *count += 1,
+            None => {
+                self.prefixes.insert(prefix.to_vec(), 1);
+            }
+        }
+    }
+
+    pub(super) fn remove(&mut self, keyspace: &str, key: &[u8], ent: &KeyDirEntry, delimiter: u8) {
+        self.key_sizes.remove(key.len() as u64);
+        self.value_sizes
+            .remove(value_size_of(ent.size, keyspace, key, ent.expires_at));
+        let prefix = Self::prefix(key, delimiter);
+        if let Some(count) = self.prefixes.get_mut(prefix) {
+            *count -= 1;
+            if *count == 0 {
+                self.prefixes.remove(prefix);
+            }
+        }
+    }
+}
+
+impl Store {
+    /// Return distribution of key sizes, value sizes and key prefixes.
+    pub fn key_stats(&self) -> &KeyStats {
+        &self.key_stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_histogram() {
+        let mut histogram = SizeHistogram::default();
+        assert_eq!(histogram.percentile(50.0), 0);
+        for &size in &[0, 1, 2, 3, 4, 100, 100, 1000] {
+            histogram.add(size);
+        }
+        histogram.remove(3);
+        assert_eq!(histogram.count(), 7);
+        assert_eq!(
+            histogram.buckets(),
+            vec![
+                (0, 0, 1),
+                (1, 1, 1),
+                (2, 3, 1),
+                (4, 7, 1),
+                (64, 127, 2),
+                (512, 1023, 1)
+            ]
+        );
+        assert_eq!(histogram.percentile(50.0), 7);
+        assert_eq!(histogram.percentile(90.0), 1023);
+        assert_eq!(histogram.percentile(100.0), 1023);
+
+        histogram.add(u64::MAX);
+        assert_eq!(histogram.percentile(100.0), u64::MAX);
+    }
+
+    #[test]
+    fn test_key_prefix() {
+        assert_eq!(KeyStats::prefix(b"user:1", b':'), b"user");
+        assert_eq!(KeyStats::prefix(b":1", b':'), b"");
+        assert_eq!(KeyStats::prefix(b"user", b':'), b"");
+    }
+}
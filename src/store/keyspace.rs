@@ -0,0 +1,180 @@
+//! Keyspaces (aka column families) split a datastore into
+//! isolated namespaces which share the same data files.
+use super::merge::MergeState;
+use super::{CompareAndSwapError, EntryMeta, KeyDirEntry, Store, Version};
+use crate::error::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+/// In-memory state of a keyspace.
+#[derive(Debug, Default)]
+pub(super) struct KeyspaceState {
+    // keydir maintains key value index for fast query.
+    pub(super) keydir: BTreeMap<Vec<u8>, KeyDirEntry>,
+    // keys with merge operands not folded yet.
+    pub(super) merges: HashMap<Vec<u8>, MergeState>,
+    pub(super) stats: KeyspaceStats,
+    pub(super) options: KeyspaceOptions,
+}
+
+/// Statistics data of a keyspace.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct KeyspaceStats {
+    /// total active key value pairs in keyspace.
+    pub total_active_entries: u64,
+    /// total size (bytes) of active entries in keyspace.
+    pub size_of_active_entries: u64,
+    /// total stale entries of keyspace in data files.
+    pub total_stale_entries: u64,
+    /// size (bytes) of stale entries of keyspace in data files.
+    pub size_of_stale_entries: u64,
+}
+
+/// Build custom keyspace options. Options not set fall back
+/// to the options of datastore.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct KeyspaceOptions {
+    pub(super) max_key_size: Option<u64>,
+    pub(super) max_value_size: Option<u64>,
+    pub(super) default_ttl: Option<Duration>,
+}
+
+impl KeyspaceOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_key_size(&mut self, value: u64) -> &mut Self {
+        self.max_key_size = Some(value);
+        self
+    }
+
+    pub fn max_value_size(&mut self, value: u64) -> &mut Self {
+        self.max_value_size = Some(value);
+        self
+    }
+
+    /// Keys set without explicit ttl will be expired after `value`.
+    pub fn default_ttl(&mut self, value: Duration) -> &mut Self {
+        self.default_ttl = Some(value);
+        self
+    }
+}
+
+/// A handle to a named keyspace of the datastore.
+#[derive(Debug)]
+pub struct Keyspace<'a> {
+    store: &'a mut Store,
+    name: String,
+}
+
+impl<'a> Keyspace<'a> {
+    pub(super) fn new(store: &'a mut Store, name: &str) -> Self {
+        Self {
+            store,
+            name: name.to_owned(),
+        }
+    }
+
+    pub(super) fn set_options(&mut self, options: &KeyspaceOptions) {
+        if let Some(ks) = self.store.keyspaces.get_mut(&self.name) {
+            ks.options = *options;
+        }
+    }
+
+    /// Return name of the keyspace.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Save key & value pair to keyspace.
+    pub fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.store.set_in(&self.name, key, value, None)
+    }
+
+    /// Save key & value pair to keyspace, the key will be expired
+    /// after the given `ttl`.
+    pub fn set_with_ttl(&mut self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.store.set_in(&self.name, key, value, Some(ttl))
+    }
+
+    /// Set key to `new` in keyspace if its current value is `expected`.
+    pub fn compare_and_swap(
+        &mut self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<std::result::Result<(), CompareAndSwapError>> {
+        self.store
+            .compare_and_swap_in(&self.name, key, expected, new)
+    }
+
+    /// Save key & value pair to keyspace only if the key is absent.
+    pub fn set_if_absent(&mut self, key: &[u8], value: &[u8]) -> Result<bool> {
+        self.store.set_if_in(&self.name, key, value, false)
+    }
+
+    /// Save key & value pair to keyspace only if the key is present.
+    pub fn set_if_present(&mut self, key: &[u8], value: &[u8]) -> Result<bool> {
+        self.store.set_if_in(&self.name, key, value, true)
+    }
+
+    /// Get key value from keyspace.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store.get_from(&self.name, key)
+    }
+
+    /// Get key value and its metadata from keyspace.
+    pub fn get_with_meta(&mut self, key: &[u8]) -> Result<Option<(Vec<u8>, EntryMeta)>> {
+        self.store.get_with_meta_from(&self.name, key)
+    }
+
+    /// Get value of a key in keyspace as of the given version.
+    pub fn get_at(&self, key: &[u8], version: u64) -> Result<Option<Vec<u8>>> {
+        self.store.get_at_in(&self.name, key, version)
+    }
+
+    /// Return all the versions of a key in keyspace still present in data files.
+    pub fn history(&self, key: &[u8]) -> Result<Vec<Version>> {
+        self.store.history_of(&self.name, key)
+    }
+
+    /// Append a merge operand to key in keyspace.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.store.merge_in(&self.name, key, operand)
+    }
+
+    /// Remove key value from keyspace.
+    pub fn remove(&mut self, key: &[u8]) -> Result<()> {
+        self.store.remove_from(&self.name, key)
+    }
+
+    /// Return all keys in keyspace.
+    pub fn keys(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.store.keys_in(&self.name)
+    }
+
+    /// Return total number of keys in keyspace.
+    pub fn len(&self) -> u64 {
+        self.store.len_of(&self.name)
+    }
+
+    /// Check keyspace is empty or not.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return `true` if keyspace contains the given key.
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.store.contains_key_in(&self.name, key)
+    }
+
+    /// Return current stats of keyspace.
+    pub fn stats(&self) -> KeyspaceStats {
+        self.store
+            .keyspaces
+            .get(&self.name)
+            .map(|ks| ks.stats)
+            .unwrap_or_default()
+    }
+}
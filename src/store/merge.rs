@@ -0,0 +1,290 @@
+//! Merge operators, read-modify-write without reading.
+//!
+//! `Store::merge` only appends an operand record, operands are folded
+//! onto the base value lazily on reading, and collapsed into a base
+//! value on compaction.
+use super::{ChangeEvent, ChangeKind, KeyDirEntry, Store};
+use crate::config;
+use crate::error::{Result, TinkvError};
+use crate::segment::RecordKind;
+use crate::util::current_millis;
+use std::fmt;
+
+/// A merge operator folds operands onto the existing value of a key.
+pub trait MergeOperator: Send + Sync {
+    /// Name of the merge operator.
+    fn name(&self) -> &str;
+
+    /// Fold `operands` (from the oldest to the newest one) onto the
+    /// `existing` value, `existing` is `None` if key not found.
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Result<Vec<u8>>;
+}
+
+impl fmt::Debug for dyn MergeOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MergeOperator({})", self.name())
+    }
+}
+
+/// Add integer operands to the integer value. Values and operands are
+/// decimal strings, e.g. `"42"`, missing value is treated as `0`.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AddOperator;
+
+impl MergeOperator for AddOperator {
+    fn name(&self) -> &str {
+        "add"
+    }
+
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Result<Vec<u8>> {
+        let parse = |value: &[u8]| {
+            String::from_utf8_lossy(value).parse::<i64>().map_err(|_| {
+                TinkvError::Custom(format!(
+                    "value of key '{}' is not an integer",
+                    String::from_utf8_lossy(key)
+                ))
+            })
+        };
+
+        let mut sum = existing.map(parse).transpose()?.unwrap_or_default();
+        for operand in operands {
+            sum = sum.wrapping_add(parse(operand)?);
+        }
+        Ok(sum.to_string().into_bytes())
+    }
+}
+
+/// Append operands to the value.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AppendOperator;
+
+impl MergeOperator for AppendOperator {
+    fn name(&self) -> &str {
+        "append"
+    }
+
+    fn merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Result<Vec<u8>> {
+        let mut value = existing.map(|v| v.to_vec()).unwrap_or_default();
+        for operand in operands {
+            value.extend_from_slice(operand);
+        }
+        Ok(value)
+    }
+}
+
+/// Merge operands of a key not folded yet.
+#[derive(Debug, Clone, Default)]
+pub(super) struct MergeState {
+    // the value before the first operand.
+    base: Option<KeyDirEntry>,
+    operands: Vec<KeyDirEntry>,
+}
+
+impl MergeState {
+    pub(super) fn base(&self) -> Option<&KeyDirEntry> {
+        self.base.as_ref()
+    }
+
+    pub(super) fn base_mut(&mut self) -> Option<&mut KeyDirEntry> {
+        self.base.as_mut()
+    }
+
+    /// Return the base value and operands of the key, except the
+    /// latest operand which keydir points to.
+    pub(super) fn earlier_entries(&self) -> impl Iterator<Item = &KeyDirEntry> {
+        let n = self.operands.len().saturating_sub(1);
+        self.base.iter().chain(self.operands[..n].iter())
+    }
+}
+
+impl Store {
+    /// Append a merge operand to key, it will be folded onto the
+    /// current value by the registered merge operator.
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.merge_in(config::DEFAULT_KEYSPACE, key, operand)
+    }
+
+    /// Return name of the registered merge operator.
+    pub(crate) fn merge_operator_name(&self) -> Option<&str> {
+        self.merge_operator.as_deref().map(|op| op.name())
+    }
+
+    pub(super) fn merge_in(&mut self, keyspace: &str, key: &[u8], operand: &[u8]) -> Result<()> {
+        if self.merge_operator.is_none() {
+            return Err(TinkvError::Custom(
+                "merge operator is not registered".to_owned(),
+            ));
+        }
+
+        let options = self.keyspace_options(keyspace);
+        if key.len() as u64 > options.max_key_size.unwrap_or(self.config.max_key_size) {
+            return Err(TinkvError::KeyIsTooLarge);
+        }
+
+        if operand.len() as u64 > options.max_value_size.unwrap_or(self.config.max_value_size) {
+            return Err(TinkvError::ValueIsTooLarge);
+        }
+
+        self.make_room(keyspace, key)?;
+
+        // operands inherit expiration time of the current value.
+        let now = current_millis();
+        let expires_at = self
+            .keydir(keyspace)
+            .and_then(|keydir| keydir.get(key))
+            .filter(|ent| !ent.is_expired(now))
+            .and_then(|ent| ent.expires_at);
+
+        let ent = self.write(RecordKind::Merge, keyspace, key, operand, expires_at)?;
+
+        self.index_operand(
+            keyspace,
+            key.to_vec(),
+            KeyDirEntry::new(ent.file_id, ent.offset, ent.size, ent.seq(), expires_at),
+        );
+
+        self.stats.size_of_all_data_files += ent.size;
+
+        self.publish(|| ChangeEvent {
+            seq: ent.seq(),
+            keyspace: keyspace.to_owned(),
+            key: key.to_vec(),
+            kind: ChangeKind::Merge {
+                operand: operand.to_vec(),
+            },
+        });
+
+        Ok(())
+    }
+
+    /// Insert a merge operand into keydir, the previous value
+    /// becomes the base value if there are no pending operands.
+    ///
+    /// Base value and earlier operands are still needed to fold the
+    /// value, so they stay active rather than being replaced.
+    pub(super) fn index_operand(
+        &mut self,
+        keyspace: &str,
+        key: Vec<u8>,
+        mut keydir_ent: KeyDirEntry,
+    ) {
+        let now = current_millis();
+        if self
+            .keydir(keyspace)
+            .and_then(|keydir| keydir.get(&key))
+            .is_some_and(|ent| ent.is_expired(now))
+        {
+            // expired value and its operands are gone, start over.
+            self.unindex(keyspace, &key);
+        }
+
+        let ks = self.keyspaces.entry(keyspace.to_owned()).or_default();
+        let old = ks.keydir.get(&key).copied();
+        if old.is_none() {
+            ks.stats.total_active_entries += 1;
+            self.stats.total_active_entries += 1;
+        }
+        ks.stats.size_of_active_entries += keydir_ent.size;
+
+        if let Some(eviction) = self.eviction.as_mut() {
+            eviction.insert(keyspace, &key, &mut keydir_ent, old.as_ref());
+        }
+        let delimiter = self.config.key_prefix_delimiter;
+        if let Some(old) = old.as_ref() {
+            self.key_stats.remove(keyspace, &key, old, delimiter);
+        }
+        self.key_stats.add(keyspace, &key, &keydir_ent, delimiter);
+
+        let mut state = ks.merges.remove(&key).unwrap_or_else(|| MergeState {
+            base: old,
+            operands: vec![],
+        });
+        state.operands.push(keydir_ent);
+
+        // keydir always points to the latest entry of key.
+        ks.keydir.insert(key.clone(), keydir_ent);
+        ks.merges.insert(key, state);
+    }
+
+    /// Pending merge operands of key are replaced, the base value and
+    /// operands not in keydir become stale.
+    pub(super) fn retire_merge_state(&mut self, keyspace: &str, state: MergeState) {
+        for ent in state.earlier_entries() {
+            if let Some(ks) = self.keyspaces.get_mut(keyspace) {
+                ks.stats.size_of_active_entries -= ent.size;
+            }
+            self.mark_stale(keyspace, ent.segment_id, ent.size);
+        }
+    }
+
+    /// Fold pending merge operands of key onto its base value.
+    pub(super) fn fold_operands(&mut self, keyspace: &str, key: &[u8]) -> Result<Vec<u8>> {
+        let operator = self
+            .merge_operator
+            .clone()
+            .ok_or_else(|| TinkvError::Custom("merge operator is not registered".to_owned()))?;
+        let state = self
+            .keyspaces
+            .get(keyspace)
+            .and_then(|ks| ks.merges.get(key))
+            .cloned()
+            .unwrap_or_default();
+
+        let base = match state.base {
+            Some(ent) => Some(self.read_entry(&ent)?.value().to_vec()),
+            None => None,
+        };
+
+        let mut operands = vec![];
+        for ent in state.operands.iter() {
+            let entry = self.read_entry(ent)?;
+            operands.push(entry.value().to_vec());
+        }
+        let operands = operands.iter().map(|v| v.as_slice()).collect::<Vec<_>>();
+
+        operator.merge(key, base.as_deref(), &operands)
+    }
+
+    /// Save folded values of all the keys with pending merge operands.
+    pub(super) fn collapse_merges(&mut self) -> Result<()> {
+        let mut pending = vec![];
+        for (keyspace, ks) in self.keyspaces.iter() {
+            for key in ks.merges.keys() {
+                pending.push((keyspace.clone(), key.clone()));
+            }
+        }
+
+        for (keyspace, key) in pending {
+            if let Some((value, meta)) = self.get_with_meta_from(&keyspace, &key)? {
+                self.put_in(&keyspace, &key, &value, meta.expires_at_ms)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_operator() {
+        let op = AddOperator;
+        assert_eq!(op.merge(b"k", None, &[b"1", b"2"]).unwrap(), b"3".to_vec());
+        assert_eq!(
+            op.merge(b"k", Some(b"10"), &[b"-3"]).unwrap(),
+            b"7".to_vec()
+        );
+        assert!(op.merge(b"k", Some(b"x"), &[b"1"]).is_err());
+    }
+
+    #[test]
+    fn test_append_operator() {
+        let op = AppendOperator;
+        assert_eq!(
+            op.merge(b"k", Some(b"a"), &[b"b", b"c"]).unwrap(),
+            b"abc".to_vec()
+        );
+        assert_eq!(op.merge(b"k", None, &[]).unwrap(), Vec::<u8>::new());
+    }
+}
@@ -0,0 +1,177 @@
+//! Rate limiting of background IO, so that compaction, hint generation
+//! and scrubbing don't saturate the disk and slow down foreground reads
+//! and writes.
+use super::Store;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A token bucket limiting bytes per second of background IO.
+///
+/// It can be cloned and shared by threads, and its rate can be
+/// adjusted at any time, see `Store::rate_limiter`.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    // waiters are woken up once rate is changed.
+    inner: Arc<(Mutex<Bucket>, Condvar)>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // bytes per second, `None` if unlimited.
+    rate: Option<u64>,
+    // bytes can be consumed without waiting, at most `rate` bytes
+    // are saved up. It goes negative once a request exceeds it,
+    // the following requests wait until it's paid off.
+    tokens: f64,
+    refilled_at: Instant,
+    // total tokens refilled so far, a request in debt waits until
+    // it reaches the debt at the time of the request.
+    refilled: f64,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        if let Some(rate) = self.rate {
+            let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+            self.refilled += elapsed * rate as f64;
+            self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+        }
+        self.refilled_at = now;
+    }
+
+    /// Consume `bytes` tokens, return how long to wait for them.
+    fn take(&mut self, bytes: u64) -> Option<Duration> {
+        let rate = self.rate?;
+        self.refill(Instant::now());
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(-self.tokens / rate as f64))
+    }
+
+    /// Return how long to wait until `target` tokens are refilled at
+    /// the current rate, `None` if there is no need to wait.
+    fn remaining(&mut self, target: f64) -> Option<Duration> {
+        let rate = self.rate?;
+        self.refill(Instant::now());
+        if self.refilled >= target {
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            (target - self.refilled) / rate as f64,
+        ))
+    }
+}
+
+impl RateLimiter {
+    /// Create a rate limiter with the given bytes per second,
+    /// `None` or `Some(0)` means unlimited.
+    pub fn new(rate: Option<u64>) -> Self {
+        let rate = rate.filter(|&rate| rate > 0);
+        RateLimiter {
+            inner: Arc::new((
+                Mutex::new(Bucket {
+                    rate,
+                    tokens: rate.unwrap_or_default() as f64,
+                    refilled_at: Instant::now(),
+                    refilled: 0.0,
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+
+    /// Return bytes per second, `None` if unlimited.
+    pub fn rate(&self) -> Option<u64> {
+        self.inner.0.lock().unwrap().rate
+    }
+
+    /// Change bytes per second, `None` or `Some(0)` means unlimited.
+    pub fn set_rate(&self, rate: Option<u64>) {
+        let rate = rate.filter(|&rate| rate > 0);
+        let mut bucket = self.inner.0.lock().unwrap();
+        bucket.refill(Instant::now());
+        bucket.rate = rate;
+        bucket.tokens = match rate {
+            Some(rate) => bucket.tokens.min(rate as f64),
+            None => 0.0,
+        };
+        self.inner.1.notify_all();
+    }
+
+    /// Block until `bytes` bytes are allowed to be read or written,
+    /// the wait follows changes of rate.
+    pub fn acquire(&self, bytes: u64) {
+        let (lock, cvar) = &*self.inner;
+        let mut bucket = lock.lock().unwrap();
+        let mut wait = bucket.take(bytes);
+        let target = bucket.refilled - bucket.tokens;
+        while let Some(timeout) = wait {
+            bucket = cvar.wait_timeout(bucket, timeout).unwrap().0;
+            wait = bucket.remaining(target);
+        }
+    }
+}
+
+impl Store {
+    /// Return a handle to the rate limiter of background IO, its rate
+    /// can be adjusted without holding the store.
+    pub fn rate_limiter(&self) -> RateLimiter {
+        self.rate_limiter.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(Some(10_000));
+        let mut bucket = limiter.inner.0.lock().unwrap();
+        // a burst of `rate` bytes is allowed.
+        assert_eq!(bucket.take(10_000), None);
+        let wait = bucket.take(2_000).unwrap();
+        assert!(wait > Duration::from_millis(150) && wait <= Duration::from_millis(200));
+        // requests wait for the previous ones.
+        let wait = bucket.take(1_000).unwrap();
+        assert!(wait > Duration::from_millis(250) && wait <= Duration::from_millis(300));
+        drop(bucket);
+
+        limiter.set_rate(Some(0));
+        assert_eq!(limiter.rate(), None);
+        let begin_at = Instant::now();
+        limiter.acquire(1 << 30);
+        assert!(begin_at.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_change_rate_while_waiting() {
+        let limiter = RateLimiter::new(Some(1_000));
+        limiter.acquire(1_000);
+
+        // a request waits for 100 seconds at the initial rate.
+        let begin_at = Instant::now();
+        let waiter = {
+            let limiter = limiter.clone();
+            std::thread::spawn(move || limiter.acquire(100_000))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        limiter.set_rate(Some(10_000_000));
+        waiter.join().unwrap();
+        assert!(begin_at.elapsed() < Duration::from_secs(5));
+
+        limiter.set_rate(Some(1_000));
+        limiter.acquire(1_000);
+        let begin_at = Instant::now();
+        let waiter = {
+            let limiter = limiter.clone();
+            std::thread::spawn(move || limiter.acquire(100_000))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        limiter.set_rate(None);
+        waiter.join().unwrap();
+        assert!(begin_at.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,85 @@
+//! Helpers to apply changes replicated from another datastore.
+use super::{ChangeEvent, ChangeKind, OpenOptions, Store};
+use crate::config;
+use crate::error::{Result, TinkvError};
+use log::info;
+use std::path::Path;
+
+impl Store {
+    /// Replace all the key value pairs with the ones of
+    /// datastore (a snapshot) in directory `dir`.
+    pub(crate) fn replace_with(&mut self, dir: &Path) -> Result<()> {
+        // snapshot files are received on the disk.
+        let options = OpenOptions {
+            config: self.config,
+            merge_operator: self.merge_operator.clone(),
+            vfs: None,
+        };
+        let mut snapshot = Store::open_with_options(dir, &options)?;
+
+        let names = self
+            .keyspace_names()
+            .filter(|name| *name != config::DEFAULT_KEYSPACE)
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in names {
+            self.drop_keyspace(&name)?;
+        }
+
+        let keys = self
+            .keydir(config::DEFAULT_KEYSPACE)
+            .map(|keydir| keydir.keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        for key in keys {
+            if self.contains_key_in(config::DEFAULT_KEYSPACE, &key) {
+                self.remove_from(config::DEFAULT_KEYSPACE, &key)?;
+            } else {
+                // expired keys are just forgotten.
+                self.unindex(config::DEFAULT_KEYSPACE, &key);
+            }
+        }
+
+        let mut total = 0;
+        let names = snapshot.keyspace_names().cloned().collect::<Vec<_>>();
+        for name in names {
+            let entries = snapshot
+                .keydir(&name)
+                .map(|keydir| {
+                    keydir
+                        .iter()
+                        .map(|(key, ent)| (key.clone(), ent.expires_at))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+
+            for (key, expires_at) in entries {
+                if let Some(value) = snapshot.get_from(&name, &key)? {
+                    self.put_in(&name, &key, &value, expires_at)?;
+                    total += 1;
+                }
+            }
+        }
+        self.sync()?;
+
+        info!("loaded {} keys from snapshot {}", total, dir.display());
+        Ok(())
+    }
+
+    /// Apply a change event of another datastore.
+    pub(crate) fn apply(&mut self, event: &ChangeEvent) -> Result<()> {
+        match &event.kind {
+            ChangeKind::Put { value, expires_at } => {
+                self.put_in(&event.keyspace, &event.key, value, *expires_at)
+            }
+            ChangeKind::Merge { operand } => self.merge_in(&event.keyspace, &event.key, operand),
+            ChangeKind::Delete => match self.remove_from(&event.keyspace, &event.key) {
+                Err(TinkvError::KeyNotFound(_)) => Ok(()),
+                r => r,
+            },
+            ChangeKind::DropKeyspace => match self.drop_keyspace(&event.keyspace) {
+                Err(TinkvError::KeyspaceNotFound(_)) => Ok(()),
+                r => r,
+            },
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Scrubbing walks sealed data files to find corrupted records
+//! before a read hits them.
+use super::{KeyDirEntry, Store};
+use crate::error::{Result, TinkvError};
+use crate::segment::DataEntryIter;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::time;
+
+/// A record failed checksum verification or can't be decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorruptedRecord {
+    pub file_id: u64,
+    pub offset: u64,
+    /// keyspace and key of the record, `None` if it can't be decoded,
+    /// records after it in the data file can't be read either.
+    pub key: Option<(String, Vec<u8>)>,
+}
+
+/// Result of scrubbing data files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScrubReport {
+    /// total data files scrubbed.
+    pub total_files: u64,
+    /// total records verified.
+    pub total_records: u64,
+    /// size (bytes) of data files scrubbed.
+    pub size_of_files: u64,
+    pub corrupted_records: Vec<CorruptedRecord>,
+    /// live keys (keyspace and key) whose values are lost, they point
+    /// at corrupted records or at no record at all.
+    pub affected_keys: Vec<(String, Vec<u8>)>,
+}
+
+impl ScrubReport {
+    /// Return `true` if no corruption is found.
+    pub fn is_clean(&self) -> bool {
+        self.corrupted_records.is_empty() && self.affected_keys.is_empty()
+    }
+
+    /// Add up another report.
+    pub fn merge(&mut self, other: ScrubReport) {
+        self.total_files += other.total_files;
+        self.total_records += other.total_records;
+        self.size_of_files += other.size_of_files;
+        self.corrupted_records.extend(other.corrupted_records);
+        self.affected_keys.extend(other.affected_keys);
+    }
+}
+
+/// A record found in data file.
+struct Record {
+    size: u64,
+    valid: bool,
+    keyspace: String,
+    key: Vec<u8>,
+}
+
+/// Scrubbing of a sealed data file, see `Store::open_segment_scrub`.
+///
+/// Records are read through a file handle of its own, the datastore
+/// is only needed at last to check live keys pointing at the file.
+pub struct SegmentScrub {
+    file_id: u64,
+    entries: DataEntryIter,
+    records: HashMap<u64, Record>,
+    report: ScrubReport,
+}
+
+impl SegmentScrub {
+    /// Id of the data file being scrubbed.
+    pub fn file_id(&self) -> u64 {
+        self.file_id
+    }
+
+    /// Verify the next record, return its size in bytes, or `None`
+    /// if there are no more records to read.
+    pub fn verify_next(&mut self) -> Result<Option<u64>> {
+        let entry = match self.entries.next() {
+            Some(Ok(entry)) => entry,
+            Some(Err(TinkvError::SegmentRead { offset, .. })) => {
+                // records after it can't be read, iteration stops here.
+                self.report.corrupted_records.push(CorruptedRecord {
+                    file_id: self.file_id,
+                    offset,
+                    key: None,
+                });
+                return Ok(None);
+            }
+            Some(Err(e)) => return Err(e),
+            None => return Ok(None),
+        };
+
+        self.report.total_records += 1;
+        if !entry.is_valid() {
+            self.report.corrupted_records.push(CorruptedRecord {
+                file_id: self.file_id,
+                offset: entry.offset,
+                key: Some((entry.keyspace().to_owned(), entry.key().to_vec())),
+            });
+        }
+        self.records.insert(
+            entry.offset,
+            Record {
+                size: entry.size,
+                valid: entry.is_valid(),
+                keyspace: entry.keyspace().to_owned(),
+                key: entry.key().to_vec(),
+            },
+        );
+        Ok(Some(entry.size))
+    }
+
+    /// Verify all the remaining records, `on_record` is called with
+    /// size of each record, e.g. to acquire it from a rate limiter.
+    pub fn verify_records<F: FnMut(u64)>(&mut self, mut on_record: F) -> Result<()> {
+        while let Some(size) = self.verify_next()? {
+            on_record(size);
+        }
+        Ok(())
+    }
+
+    /// Check that live keys pointing at the data file point at valid
+    /// records, and return the report of the data file.
+    pub fn finish(self, store: &Store) -> ScrubReport {
+        let SegmentScrub {
+            file_id,
+            records,
+            mut report,
+            ..
+        } = self;
+
+        let is_intact =
+            |keyspace: &str, key: &[u8], ent: &KeyDirEntry| match records.get(&ent.offset) {
+                Some(r) => r.valid && r.size == ent.size && r.keyspace == keyspace && r.key == key,
+                None => false,
+            };
+        for (name, ks) in store.keyspaces.iter() {
+            let live_entries = ks.keydir.iter().chain(
+                ks.merges
+                    .iter()
+                    .flat_map(|(key, state)| state.earlier_entries().map(move |ent| (key, ent))),
+            );
+            for (key, ent) in live_entries {
+                if ent.segment_id == file_id && !is_intact(name, key, ent) {
+                    report.affected_keys.push((name.clone(), key.clone()));
+                }
+            }
+        }
+        report.affected_keys.sort();
+        report.affected_keys.dedup();
+
+        for r in report.corrupted_records.iter() {
+            warn!(
+                "found corrupted record at offset {} of data file {}",
+                r.offset, r.file_id
+            );
+        }
+        report
+    }
+}
+
+impl Store {
+    /// Verify checksums of all the records in sealed data files, and
+    /// that each key in keydir points at a valid record.
+    ///
+    /// Reads are limited record by record by the rate limiter of
+    /// background IO. Use `open_segment_scrub` to scrub data files
+    /// without holding the datastore while reading them.
+    pub fn scrub(&self) -> Result<ScrubReport> {
+        let begin_at = time::Instant::now();
+        let mut report = ScrubReport::default();
+        for file_id in self.sealed_segments() {
+            let mut scrub = self.open_segment_scrub(file_id)?;
+            scrub.verify_records(|size| self.rate_limiter.acquire(size))?;
+            report.merge(scrub.finish(self));
+        }
+
+        info!(
+            "scrub {} data files in {:?}, {} corrupted records, {} affected keys",
+            report.total_files,
+            begin_at.elapsed(),
+            report.corrupted_records.len(),
+            report.affected_keys.len()
+        );
+        Ok(report)
+    }
+
+    /// Return ids of data files not writeable any more, in order.
+    pub fn sealed_segments(&self) -> Vec<u64> {
+        let active_id = self.active_data_file.as_ref().map(|df| df.id);
+        let mut file_ids = self
+            .data_files
+            .keys()
+            .cloned()
+            .filter(|&id| Some(id) != active_id)
+            .collect::<Vec<_>>();
+        file_ids.sort_unstable();
+        file_ids
+    }
+
+    /// Scrub a sealed data file, see `scrub`. Reads are not limited.
+    pub fn scrub_segment(&self, file_id: u64) -> Result<ScrubReport> {
+        let mut scrub = self.open_segment_scrub(file_id)?;
+        scrub.verify_records(|_| {})?;
+        Ok(scrub.finish(self))
+    }
+
+    /// Open a new handle of a sealed data file to scrub it.
+    pub fn open_segment_scrub(&self, file_id: u64) -> Result<SegmentScrub> {
+        let df = self
+            .data_files
+            .get(&file_id)
+            .ok_or(TinkvError::SegmentNotFound(file_id))?;
+
+        Ok(SegmentScrub {
+            file_id,
+            entries: df.entry_iter()?,
+            records: HashMap::new(),
+            report: ScrubReport {
+                total_files: 1,
+                // size of read-only data file is not updated by writes.
+                size_of_files: self.vfs.file_size(&df.path)?,
+                ..Default::default()
+            },
+        })
+    }
+}
@@ -0,0 +1,88 @@
+//! Statistics of each data file, to find out which ones are worth
+//! compacting.
+use super::{segment_hint_file_path, Store};
+use crate::error::Result;
+use crate::segment::HEADER_SIZE;
+use std::collections::HashMap;
+
+/// Statistics data of a data file.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct SegmentStats {
+    /// data file id.
+    pub id: u64,
+    /// size (bytes) of data file.
+    pub size: u64,
+    /// total live key value pairs (and merge operands) in data file.
+    pub total_active_entries: u64,
+    /// size (bytes) of live entries in data file.
+    pub size_of_active_entries: u64,
+    /// size (bytes) of overwritten or removed entries, tomestones and
+    /// anything else can be reclaimed by compaction, the file header
+    /// is not counted.
+    pub size_of_stale_entries: u64,
+    /// data file has a hint file, which is written by compaction.
+    pub has_hint: bool,
+    /// write time (in nanoseconds) of the oldest entry, `None` if
+    /// data file is empty. Entries copied by compaction keep their
+    /// write time, so it can be older than the data file itself.
+    pub created_at: Option<u64>,
+    /// data file is the active one.
+    pub writeable: bool,
+}
+
+impl SegmentStats {
+    /// Return fraction of records in data file can be reclaimed by compaction.
+    pub fn garbage_ratio(&self) -> f64 {
+        let size_of_records = self.size.saturating_sub(HEADER_SIZE);
+        if size_of_records == 0 {
+            return 0.0;
+        }
+        self.size_of_stale_entries as f64 / size_of_records as f64
+    }
+}
+
+impl Store {
+    /// Return statistics data of all the data files, ordered by id.
+    pub fn segments(&self) -> Result<Vec<SegmentStats>> {
+        let mut live: HashMap<u64, (u64, u64)> = HashMap::new();
+        for ks in self.keyspaces.values() {
+            let live_entries = ks
+                .keydir
+                .values()
+                .chain(ks.merges.values().flat_map(|state| state.earlier_entries()));
+            for ent in live_entries {
+                let (total, size) = live.entry(ent.segment_id).or_default();
+                *total += 1;
+                *size += ent.size;
+            }
+        }
+
+        let active_id = self.active_data_file.as_ref().map(|df| df.id);
+        let mut segments = vec![];
+        for df in self.data_files.values() {
+            // size of read-only data file is not updated by writes.
+            let size = self.vfs.file_size(&df.path)?;
+            let (total_active_entries, size_of_active_entries) =
+                live.get(&df.id).cloned().unwrap_or_default();
+            // compaction copies entries in key order, the first
+            // entry is not necessarily the oldest one.
+            let mut created_at: Option<u64> = None;
+            for entry in df.entry_iter()? {
+                let timestamp = entry?.timestamp();
+                created_at = Some(created_at.map_or(timestamp, |ts| ts.min(timestamp)));
+            }
+            segments.push(SegmentStats {
+                id: df.id,
+                size,
+                total_active_entries,
+                size_of_active_entries,
+                size_of_stale_entries: size.saturating_sub(HEADER_SIZE + size_of_active_entries),
+                has_hint: self.vfs.exists(&segment_hint_file_path(&self.path, df.id)),
+                created_at,
+                writeable: Some(df.id) == active_id,
+            });
+        }
+        segments.sort_by_key(|segment| segment.id);
+        Ok(segments)
+    }
+}
@@ -0,0 +1,213 @@
+//! A typed wrapper of datastore over serde keys and values.
+use crate::error::Result;
+use crate::store::Store;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+mod key;
+
+/// Encode values into bytes and decode them back.
+pub trait Codec {
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>>;
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T>;
+}
+
+/// Values are encoded with bincode.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct BincodeCodec;
+
+impl Codec for BincodeCodec {
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Values are encoded in JSON, readable with other tools.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Encoded bytes compare in the same order as the original values,
+/// it's used to encode keys of `TypedStore`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct OrderedCodec;
+
+impl Codec for OrderedCodec {
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        key::to_bytes(value)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        key::from_bytes(bytes)
+    }
+}
+
+/// A datastore of typed keys and values.
+///
+/// Keys are encoded with `OrderedCodec`, so that they are iterated
+/// in the same order as the typed ones, values are encoded with
+/// the codec `C`.
+#[derive(Debug)]
+pub struct TypedStore<K, V, C = BincodeCodec> {
+    store: Store,
+    codec: C,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K, V> TypedStore<K, V>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+{
+    /// Open a typed datastore with the given path, values
+    /// are encoded with bincode.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(Store::open(path)?))
+    }
+
+    /// Wrap a datastore, values are encoded with bincode.
+    pub fn new(store: Store) -> Self {
+        Self::with_codec(store, BincodeCodec)
+    }
+}
+
+impl<K, V, C> TypedStore<K, V, C>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    /// Wrap a datastore, values are encoded with the given codec.
+    pub fn with_codec(store: Store, codec: C) -> Self {
+        TypedStore {
+            store,
+            codec,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Get value of a key, `None` if key not found.
+    pub fn get(&mut self, key: &K) -> Result<Option<V>> {
+        let key = OrderedCodec.encode(key)?;
+        match self.store.get(&key)? {
+            Some(value) => Ok(Some(self.codec.decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Save key value pair.
+    pub fn set(&mut self, key: &K, value: &V) -> Result<()> {
+        let key = OrderedCodec.encode(key)?;
+        let value = self.codec.encode(value)?;
+        self.store.set(&key, &value)
+    }
+
+    /// Remove a key, `TinkvError::KeyNotFound` is returned if key not found.
+    pub fn remove(&mut self, key: &K) -> Result<()> {
+        let key = OrderedCodec.encode(key)?;
+        self.store.remove(&key)
+    }
+
+    /// Return `true` if datastore contains the given key.
+    pub fn contains_key(&self, key: &K) -> Result<bool> {
+        let key = OrderedCodec.encode(key)?;
+        Ok(self.store.contains_key(&key))
+    }
+
+    /// Return key value pairs within the given range, in key order.
+    ///
+    /// Keys not written by `TypedStore` are yielded as errors.
+    pub fn range<R: RangeBounds<K>>(&mut self, range: R) -> Result<Range<'_, K, V, C>> {
+        let encode = |bound: Bound<&K>| -> Result<Bound<Vec<u8>>> {
+            Ok(match bound {
+                Bound::Included(key) => Bound::Included(OrderedCodec.encode(key)?),
+                Bound::Excluded(key) => Bound::Excluded(OrderedCodec.encode(key)?),
+                Bound::Unbounded => Bound::Unbounded,
+            })
+        };
+        let bounds = (encode(range.start_bound())?, encode(range.end_bound())?);
+        let keys = self.store.keys_range(bounds).cloned().collect::<Vec<_>>();
+
+        Ok(Range {
+            typed: self,
+            keys: keys.into_iter(),
+        })
+    }
+
+    /// Return all key value pairs, in key order.
+    pub fn iter(&mut self) -> Result<Range<'_, K, V, C>> {
+        self.range(..)
+    }
+
+    /// Return total number of keys in datastore.
+    pub fn len(&self) -> u64 {
+        self.store.len()
+    }
+
+    /// Check datastore is empty or not.
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+
+    /// Return the underlying datastore.
+    pub fn store(&mut self) -> &mut Store {
+        &mut self.store
+    }
+
+    /// Unwrap the underlying datastore.
+    pub fn into_inner(self) -> Store {
+        self.store
+    }
+}
+
+/// An iterator over key value pairs of `TypedStore`.
+///
+/// Keys are collected on creation, values are read lazily.
+#[derive(Debug)]
+pub struct Range<'a, K, V, C> {
+    typed: &'a mut TypedStore<K, V, C>,
+    keys: std::vec::IntoIter<Vec<u8>>,
+}
+
+impl<'a, K, V, C> Iterator for Range<'a, K, V, C>
+where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for raw_key in &mut self.keys {
+            let value = match self.typed.store.get(&raw_key) {
+                // expired in the meantime.
+                Ok(None) => continue,
+                Ok(Some(value)) => value,
+                Err(e) => return Some(Err(e)),
+            };
+            let decoded = OrderedCodec.decode(&raw_key).and_then(|key| {
+                let value = self.typed.codec.decode(&value)?;
+                Ok((key, value))
+            });
+            return Some(decoded);
+        }
+        None
+    }
+}
@@ -0,0 +1,748 @@
+//! Order preserving encoding of keys.
+//!
+//! Encoded keys compare bytewise in the same order as the original
+//! values, so that ranges of typed keys map to ranges of the keydir.
+//!
+//! - unsigned integers are written in big endian,
+//! - signed integers and floats are written in big endian with their
+//!   sign bit flipped (and all bits of negative floats),
+//! - strings and bytes are escaped (`0x00` -> `0x00 0xff`) and
+//!   terminated by `0x00 0x00`,
+//! - options, sequences and maps are prefixed by marker bytes,
+//! - tuples and structs are concatenation of their fields, enums
+//!   are prefixed by their variant index.
+//!
+//! The encoding is not self-describing, keys must be decoded into
+//! the same type they are encoded from.
+use crate::error::{Result, TinkvError};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use std::fmt::Display;
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ZERO: u8 = 0xff;
+const TERMINATOR: u8 = 0x00;
+// markers of options, sequences and maps.
+const NONE: u8 = 0x00;
+const SOME: u8 = 0x01;
+const END: u8 = 0x00;
+const ELEMENT: u8 = 0x01;
+
+impl ser::Error for TinkvError {
+    fn custom<T: Display>(msg: T) -> Self {
+        TinkvError::Custom(msg.to_string())
+    }
+}
+
+impl de::Error for TinkvError {
+    fn custom<T: Display>(msg: T) -> Self {
+        TinkvError::Custom(msg.to_string())
+    }
+}
+
+/// Encode a value into bytes preserving its order.
+pub(crate) fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut ser = Serializer { output: vec![] };
+    value.serialize(&mut ser)?;
+    Ok(ser.output)
+}
+
+/// Decode a value from bytes encoded by `to_bytes`.
+pub(crate) fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    let mut de = Deserializer { input: bytes };
+    let value = T::deserialize(&mut de)?;
+    if !de.input.is_empty() {
+        return Err(invalid("trailing bytes"));
+    }
+    Ok(value)
+}
+
+fn invalid(msg: &str) -> TinkvError {
+    TinkvError::Custom(format!("invalid encoded key, {}", msg))
+}
+
+struct Serializer {
+    output: Vec<u8>,
+}
+
+macro_rules! serialize_unsigned {
+    ($($method:ident: $ty:ty),*) => {
+        $(fn $method(self, v: $ty) -> Result<()> {
+            self.output.extend_from_slice(&v.to_be_bytes());
+            Ok(())
+        })*
+    };
+}
+
+macro_rules! serialize_signed {
+    ($($method:ident: $ty:ty => $uty:ty),*) => {
+        $(fn $method(self, v: $ty) -> Result<()> {
+            let v = (v as $uty) ^ (1 << (<$uty>::BITS - 1));
+            self.output.extend_from_slice(&v.to_be_bytes());
+            Ok(())
+        })*
+    };
+}
+
+impl ser::Serializer for &mut Serializer {
+    type Ok = ();
+    type Error = TinkvError;
+
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    serialize_unsigned!(
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_u128: u128
+    );
+
+    serialize_signed!(
+        serialize_i8: i8 => u8,
+        serialize_i16: i16 => u16,
+        serialize_i32: i32 => u32,
+        serialize_i64: i64 => u64,
+        serialize_i128: i128 => u128
+    );
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.output.push(v as u8);
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        let bits = v.to_bits();
+        let sign = 1 << 31;
+        let bits = if bits & sign != 0 { !bits } else { bits | sign };
+        self.serialize_u32(bits)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        let bits = v.to_bits();
+        let sign = 1 << 63;
+        let bits = if bits & sign != 0 { !bits } else { bits | sign };
+        self.serialize_u64(bits)
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_u32(v as u32)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        for &b in v {
+            self.output.push(b);
+            if b == ESCAPE {
+                self.output.push(ESCAPED_ZERO);
+            }
+        }
+        self.output.extend_from_slice(&[ESCAPE, TERMINATOR]);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.output.push(NONE);
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        self.output.push(SOME);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<()> {
+        self.serialize_u32(variant_index)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.serialize_u32(variant_index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self> {
+        self.serialize_u32(variant_index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self> {
+        self.serialize_u32(variant_index)?;
+        Ok(self)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl ser::SerializeSeq for &mut Serializer {
+    type Ok = ();
+    type Error = TinkvError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.output.push(ELEMENT);
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        self.output.push(END);
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut Serializer {
+    type Ok = ();
+    type Error = TinkvError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.output.push(ELEMENT);
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        self.output.push(END);
+        Ok(())
+    }
+}
+
+macro_rules! serialize_fields {
+    ($($trait:ident: $method:ident($($name:ident: $ty:ty),*)),*) => {
+        $(impl ser::$trait for &mut Serializer {
+            type Ok = ();
+            type Error = TinkvError;
+
+            fn $method<T: Serialize + ?Sized>(&mut self, $($name: $ty,)* value: &T) -> Result<()> {
+                value.serialize(&mut **self)
+            }
+
+            fn end(self) -> Result<()> {
+                Ok(())
+            }
+        })*
+    };
+}
+
+serialize_fields!(
+    SerializeTuple: serialize_element(),
+    SerializeTupleStruct: serialize_field(),
+    SerializeTupleVariant: serialize_field(),
+    SerializeStruct: serialize_field(_key: &'static str),
+    SerializeStructVariant: serialize_field(_key: &'static str)
+);
+
+struct Deserializer<'de> {
+    input: &'de [u8],
+}
+
+impl<'de> Deserializer<'de> {
+    fn take(&mut self, n: usize) -> Result<&'de [u8]> {
+        if self.input.len() < n {
+            return Err(invalid("unexpected end"));
+        }
+        let (head, tail) = self.input.split_at(n);
+        self.input = tail;
+        Ok(head)
+    }
+
+    fn take_byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0; N];
+        buf.copy_from_slice(self.take(N)?);
+        Ok(buf)
+    }
+
+    fn take_bytes(&mut self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        loop {
+            match self.take_byte()? {
+                ESCAPE => match self.take_byte()? {
+                    TERMINATOR => return Ok(bytes),
+                    ESCAPED_ZERO => bytes.push(ESCAPE),
+                    _ => return Err(invalid("bad escape sequence")),
+                },
+                b => bytes.push(b),
+            }
+        }
+    }
+}
+
+macro_rules! deserialize_unsigned {
+    ($($method:ident: $ty:ty => $visit:ident),*) => {
+        $(fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+            visitor.$visit(<$ty>::from_be_bytes(self.take_array()?))
+        })*
+    };
+}
+
+macro_rules! deserialize_signed {
+    ($($method:ident: $ty:ty => $uty:ty, $visit:ident),*) => {
+        $(fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+            let v = <$uty>::from_be_bytes(self.take_array()?) ^ (1 << (<$uty>::BITS - 1));
+            visitor.$visit(v as $ty)
+        })*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = TinkvError;
+
+    deserialize_unsigned!(
+        deserialize_u8: u8 => visit_u8,
+        deserialize_u16: u16 => visit_u16,
+        deserialize_u32: u32 => visit_u32,
+        deserialize_u64: u64 => visit_u64,
+        deserialize_u128: u128 => visit_u128
+    );
+
+    deserialize_signed!(
+        deserialize_i8: i8 => u8, visit_i8,
+        deserialize_i16: i16 => u16, visit_i16,
+        deserialize_i32: i32 => u32, visit_i32,
+        deserialize_i64: i64 => u64, visit_i64,
+        deserialize_i128: i128 => u128, visit_i128
+    );
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(invalid("encoding is not self-describing"))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take_byte()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            _ => Err(invalid("bad bool")),
+        }
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let bits = u32::from_be_bytes(self.take_array()?);
+        let sign = 1 << 31;
+        let bits = if bits & sign != 0 {
+            bits & !sign
+        } else {
+            !bits
+        };
+        visitor.visit_f32(f32::from_bits(bits))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let bits = u64::from_be_bytes(self.take_array()?);
+        let sign = 1 << 63;
+        let bits = if bits & sign != 0 {
+            bits & !sign
+        } else {
+            !bits
+        };
+        visitor.visit_f64(f64::from_bits(bits))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let v = u32::from_be_bytes(self.take_array()?);
+        visitor.visit_char(std::char::from_u32(v).ok_or_else(|| invalid("bad char"))?)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let s = String::from_utf8(self.take_bytes()?).map_err(|_| invalid("bad utf-8 string"))?;
+        visitor.visit_string(s)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.take_bytes()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take_byte()? {
+            NONE => visitor.visit_none(),
+            SOME => visitor.visit_some(self),
+            _ => Err(invalid("bad option marker")),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Elements { de: self })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Fields { de: self, len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_map(Elements { de: self })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(invalid("encoding is not self-describing"))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Elements of sequences and maps, each one is prefixed by a marker.
+struct Elements<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+}
+
+impl<'a, 'de> Elements<'a, 'de> {
+    fn has_next(&mut self) -> Result<bool> {
+        match self.de.take_byte()? {
+            END => Ok(false),
+            ELEMENT => Ok(true),
+            _ => Err(invalid("bad element marker")),
+        }
+    }
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for Elements<'a, 'de> {
+    type Error = TinkvError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if !self.has_next()? {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+}
+
+impl<'a, 'de> de::MapAccess<'de> for Elements<'a, 'de> {
+    type Error = TinkvError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if !self.has_next()? {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+/// Fields of tuples and structs, the number of them is known.
+struct Fields<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    len: usize,
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for Fields<'a, 'de> {
+    type Error = TinkvError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut Deserializer<'de> {
+    type Error = TinkvError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let index = u32::from_be_bytes(self.take_array()?);
+        let de: de::value::U32Deserializer<TinkvError> = index.into_deserializer();
+        let value = seed.deserialize(de)?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut Deserializer<'de> {
+    type Error = TinkvError;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::fmt::Debug;
+
+    #[derive(Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
+    enum Kind {
+        Unit,
+        Newtype(i32),
+        Tuple(u8, String),
+        Struct { id: u64, name: Option<String> },
+    }
+
+    #[derive(Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
+    struct Composite {
+        tenant: String,
+        id: i64,
+        tags: Vec<String>,
+        kind: Kind,
+    }
+
+    /// Values must be sorted, encoded keys are in the same order.
+    fn assert_ordered<T: Serialize + DeserializeOwned + PartialEq + Debug>(values: &[T]) {
+        let encoded = values
+            .iter()
+            .map(|v| to_bytes(v).unwrap())
+            .collect::<Vec<_>>();
+        for (v, bytes) in values.iter().zip(encoded.iter()) {
+            assert_eq!(&from_bytes::<T>(bytes).unwrap(), v);
+        }
+        for pair in encoded.windows(2) {
+            assert!(pair[0] < pair[1], "{:?} >= {:?}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_ordered_integers() {
+        assert_ordered(&[0u8, 1, 127, 128, 255]);
+        assert_ordered(&[0u64, 1, 256, 65536, u64::MAX]);
+        assert_ordered(&[i8::MIN, -1, 0, 1, i8::MAX]);
+        assert_ordered(&[i64::MIN, -65536, -256, -1, 0, 1, 255, i64::MAX]);
+        assert_ordered(&[i128::MIN, -1, 0, i128::MAX]);
+    }
+
+    #[test]
+    fn test_ordered_floats() {
+        assert_ordered(&[
+            f64::NEG_INFINITY,
+            -1e10,
+            -1.5,
+            -0.0,
+            0.0,
+            1e-10,
+            1.5,
+            f64::INFINITY,
+        ]);
+        assert_ordered(&[-2.5f32, -1.0, 0.0, 1.0, 2.5]);
+    }
+
+    #[test]
+    fn test_ordered_strings() {
+        let strings = ["", "\0", "\0\0", "a", "a\0", "a\0b", "aa", "ab", "b"];
+        assert_ordered(&strings.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        assert_ordered(&['a', 'b', '中']);
+        assert_ordered(&[
+            vec![],
+            vec![0u8],
+            vec![0, 0],
+            vec![0, 255],
+            vec![1],
+            vec![1, 0],
+            vec![255],
+        ]);
+    }
+
+    #[test]
+    fn test_ordered_composites() {
+        assert_ordered(&[None, Some(0i32), Some(1)]);
+        assert_ordered(&[(false, 9u8), (true, 0)]);
+        assert_ordered(&[
+            ("a".to_owned(), 2u32),
+            ("a".to_owned(), 10),
+            ("ab".to_owned(), 0),
+        ]);
+        assert_ordered(&[
+            Kind::Unit,
+            Kind::Newtype(-1),
+            Kind::Newtype(1),
+            Kind::Tuple(1, "z".to_owned()),
+            Kind::Tuple(2, "a".to_owned()),
+            Kind::Struct { id: 1, name: None },
+            Kind::Struct {
+                id: 1,
+                name: Some("x".to_owned()),
+            },
+        ]);
+        assert_ordered(&[
+            Composite {
+                tenant: "a".to_owned(),
+                id: -5,
+                tags: vec!["x".to_owned(), "y".to_owned()],
+                kind: Kind::Unit,
+            },
+            Composite {
+                tenant: "a".to_owned(),
+                id: 3,
+                tags: vec![],
+                kind: Kind::Unit,
+            },
+            Composite {
+                tenant: "a".to_owned(),
+                id: 3,
+                tags: vec!["x".to_owned()],
+                kind: Kind::Unit,
+            },
+            Composite {
+                tenant: "b".to_owned(),
+                id: i64::MIN,
+                tags: vec![],
+                kind: Kind::Newtype(0),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        assert!(from_bytes::<u32>(&[0, 1]).is_err());
+        assert!(from_bytes::<u8>(&[0, 1]).is_err());
+        assert!(from_bytes::<String>(b"abc").is_err());
+        assert!(from_bytes::<String>(&[b'a', 0, 7]).is_err());
+        assert!(from_bytes::<bool>(&[2]).is_err());
+    }
+}
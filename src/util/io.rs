@@ -1,6 +1,7 @@
 //! Some io helpers.
 
-use std::fs;
+use crate::vfs::VfsFile;
+use crc::crc32::{self, Hasher32};
 use std::io::prelude::*;
 use std::io::{self, BufReader, BufWriter, SeekFrom};
 #[derive(Debug)]
@@ -11,7 +12,7 @@ pub struct BufReaderWithOffset<R: Read + Seek> {
 
 impl<R: Read + Seek> BufReaderWithOffset<R> {
     pub fn new(mut r: R) -> io::Result<Self> {
-        r.seek(SeekFrom::Current(0))?;
+        r.stream_position()?;
         Ok(Self {
             reader: BufReader::new(r),
             offset: 0,
@@ -46,7 +47,7 @@ pub struct BufWriterWithOffset<W: Write + Seek> {
 
 impl<W: Write + Seek> BufWriterWithOffset<W> {
     pub fn new(mut w: W) -> io::Result<Self> {
-        w.seek(SeekFrom::Current(0))?;
+        w.stream_position()?;
         Ok(Self {
             writer: BufWriter::new(w),
             offset: 0,
@@ -77,29 +78,29 @@ impl<W: Write + Seek> Seek for BufWriterWithOffset<W> {
     }
 }
 
-/// A file wrapper wraps `File` and `BufWriterWithOffset<File>`.
+/// A file wrapper wraps `VfsFile` and `BufWriterWithOffset<VfsFile>`.
 /// We're using `BufWriterWithOffset` here for better writting performance.
 /// Also, we need to make sure `file.sync_all()` can be called manually to
 /// flush all pending writes to disk.
 #[derive(Debug)]
 pub struct FileWithBufWriter {
-    inner: fs::File,
-    bw: BufWriterWithOffset<fs::File>,
+    inner: Box<dyn VfsFile>,
+    bw: BufWriterWithOffset<Box<dyn VfsFile>>,
 }
 
 impl FileWithBufWriter {
-    pub fn from(inner: fs::File) -> io::Result<FileWithBufWriter> {
+    pub fn from(inner: Box<dyn VfsFile>) -> io::Result<FileWithBufWriter> {
         let bw = BufWriterWithOffset::new(inner.try_clone()?)?;
 
         Ok(FileWithBufWriter { inner, bw })
     }
 
-    pub fn inner(&self) -> &fs::File {
-        &self.inner
+    pub fn inner(&self) -> &dyn VfsFile {
+        self.inner.as_ref()
     }
 
-    pub fn inner_mut(&mut self) -> &mut fs::File {
-        &mut self.inner
+    pub fn inner_mut(&mut self) -> &mut dyn VfsFile {
+        self.inner.as_mut()
     }
 
     pub fn sync(&mut self) -> io::Result<()> {
@@ -107,6 +108,13 @@ impl FileWithBufWriter {
         self.inner.sync_all()
     }
 
+    /// Like `sync`, but file metadata not required to read data
+    /// back (e.g. modification time) is not flushed.
+    pub fn sync_data(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.inner.sync_data()
+    }
+
     pub fn offset(&self) -> u64 {
         self.bw.offset()
     }
@@ -194,3 +202,37 @@ impl<B: BufRead> BufRead for ByteLineReader<B> {
         self.reader.consume(amt);
     }
 }
+
+/// A writer computes crc32 (IEEE) checksum of the bytes written into it.
+pub struct ChecksumWriter {
+    digest: crc32::Digest,
+}
+
+impl ChecksumWriter {
+    pub fn new() -> Self {
+        Self {
+            digest: crc32::Digest::new(crc32::IEEE),
+        }
+    }
+
+    pub fn sum(&self) -> u32 {
+        self.digest.sum32()
+    }
+}
+
+impl Default for ChecksumWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Write for ChecksumWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Hasher32::write(&mut self.digest, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
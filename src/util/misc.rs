@@ -9,6 +9,14 @@ pub fn current_timestamp() -> u128 {
         .as_nanos()
 }
 
+/// Return milliseconds elapsed since unix epoch.
+pub fn current_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_millis() as u64
+}
+
 pub fn checksum(data: &[u8]) -> u32 {
     crc::crc32::checksum_ieee(data)
 }
@@ -33,7 +41,7 @@ mod tests {
     #[test]
     fn test_parse_file_id() {
         let r = parse_file_id(Path::new("path/to/12345.tinkv.data"));
-        assert_eq!(r, Some(12345 as u64));
+        assert_eq!(r, Some(12345_u64));
 
         let r = parse_file_id(Path::new("path/to/.tinkv.data"));
         assert_eq!(r, None);
@@ -1,4 +1,6 @@
-pub use io::{BufReaderWithOffset, BufWriterWithOffset, ByteLineReader, FileWithBufWriter};
+pub use io::{
+    BufReaderWithOffset, BufWriterWithOffset, ByteLineReader, ChecksumWriter, FileWithBufWriter,
+};
 pub use misc::*;
 
 mod io;
@@ -0,0 +1,501 @@
+//! A virtual filesystem layer for segment files.
+//!
+//! Data files and hint files are accessed through `Vfs`, so that the
+//! datastore can live on the real disk (`DiskFs`), in memory (`MemFs`),
+//! or on a filesystem injecting faults (`FaultFs`) for crash testing.
+//! Backups and exports always go to the real disk.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A filesystem storing segment files.
+pub trait Vfs: Send + Sync + fmt::Debug {
+    /// Create a directory and all of its parents if missing.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Return paths of all the files in a directory.
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Return `true` if the file or directory exists.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Open a file for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Open a file for appending, the file is created if not found.
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Remove a file.
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Return size of a file.
+    fn file_size(&self, path: &Path) -> io::Result<u64>;
+
+    /// Copy a file out to `dst` on the real disk, e.g. into a backup.
+    fn copy_to_disk(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        let mut reader = self.open(src)?;
+        let mut writer = fs::File::create(dst)?;
+        io::copy(&mut reader, &mut writer)?;
+        Ok(())
+    }
+}
+
+/// An opened file of `Vfs`.
+pub trait VfsFile: Read + Write + Seek + Send + Sync + fmt::Debug {
+    /// Persist written data in storage.
+    fn sync_data(&self) -> io::Result<()>;
+
+    /// Persist written data and all the metadata in storage.
+    fn sync_all(&self) -> io::Result<()> {
+        self.sync_data()
+    }
+
+    /// Return a new handle of the same file.
+    fn try_clone(&self) -> io::Result<Box<dyn VfsFile>>;
+}
+
+/// The real disk.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DiskFs;
+
+impl Vfs for DiskFs {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                paths.push(entry.path());
+            }
+        }
+        Ok(paths)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Box::new(f))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    /// Hard link `src` to `dst`, fallback to copying if it's not
+    /// possible (e.g. across filesystems).
+    fn copy_to_disk(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        if fs::hard_link(src, dst).is_err() {
+            fs::copy(src, dst)?;
+        }
+        Ok(())
+    }
+}
+
+impl VfsFile for fs::File {
+    fn sync_data(&self) -> io::Result<()> {
+        fs::File::sync_data(self)
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        fs::File::sync_all(self)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(fs::File::try_clone(self)?))
+    }
+}
+
+/// A filesystem keeps all the files in memory. Clones share the same files.
+///
+/// It remembers how much data of each file is synced, so that a crash
+/// (losing data not synced yet) can be simulated by `crash`.
+#[derive(Debug, Clone, Default)]
+pub struct MemFs {
+    inner: Arc<Mutex<MemFsState>>,
+}
+
+#[derive(Debug, Default)]
+struct MemFsState {
+    dirs: BTreeSet<PathBuf>,
+    files: BTreeMap<PathBuf, Arc<Mutex<MemFileData>>>,
+}
+
+#[derive(Debug, Default)]
+struct MemFileData {
+    data: Vec<u8>,
+    // length of data persisted by the last sync.
+    synced: usize,
+}
+
+impl MemFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Simulate a crash, data not synced yet is lost. The first
+    /// `keep_unsynced` bytes of unsynced data of each file survive,
+    /// which simulates torn writes.
+    pub fn crash(&self, keep_unsynced: usize) {
+        let state = self.inner.lock().unwrap();
+        for file in state.files.values() {
+            let mut file = file.lock().unwrap();
+            let len = file.data.len().min(file.synced + keep_unsynced);
+            file.data.truncate(len);
+            file.synced = len;
+        }
+    }
+
+    fn file(&self, path: &Path) -> io::Result<Arc<Mutex<MemFileData>>> {
+        self.inner
+            .lock()
+            .unwrap()
+            .files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| not_found(path))
+    }
+}
+
+impl Vfs for MemFs {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.inner.lock().unwrap();
+        for dir in path.ancestors() {
+            state.dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let state = self.inner.lock().unwrap();
+        if !state.dirs.contains(dir) {
+            return Err(not_found(dir));
+        }
+        Ok(state
+            .files
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let state = self.inner.lock().unwrap();
+        state.dirs.contains(path) || state.files.contains_key(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(MemFile {
+            data: self.file(path)?,
+            pos: 0,
+        }))
+    }
+
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let mut state = self.inner.lock().unwrap();
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        if !state.dirs.contains(parent) {
+            return Err(not_found(parent));
+        }
+
+        let data = state.files.entry(path.to_path_buf()).or_default().clone();
+        let pos = data.lock().unwrap().data.len() as u64;
+        Ok(Box::new(MemFile { data, pos }))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        match self.inner.lock().unwrap().files.remove(path) {
+            Some(_) => Ok(()),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.file(path)?.lock().unwrap().data.len() as u64)
+    }
+}
+
+#[derive(Debug)]
+struct MemFile {
+    data: Arc<Mutex<MemFileData>>,
+    pos: u64,
+}
+
+impl Read for MemFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let file = self.data.lock().unwrap();
+        let start = (self.pos as usize).min(file.data.len());
+        let len = buf.len().min(file.data.len() - start);
+        buf[..len].copy_from_slice(&file.data[start..start + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Write for MemFile {
+    /// Files are append only.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut file = self.data.lock().unwrap();
+        file.data.extend_from_slice(buf);
+        self.pos = file.data.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.data.lock().unwrap().data.len() as i64;
+        let pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => len + n,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+        };
+        if pos < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            ));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+impl VfsFile for MemFile {
+    fn sync_data(&self) -> io::Result<()> {
+        let mut file = self.data.lock().unwrap();
+        file.synced = file.data.len();
+        Ok(())
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(MemFile {
+            data: self.data.clone(),
+            pos: self.pos,
+        }))
+    }
+}
+
+/// An in-memory filesystem injecting faults, for crash testing.
+/// Clones share the same files and faults.
+#[derive(Debug, Clone, Default)]
+pub struct FaultFs {
+    fs: MemFs,
+    faults: Arc<Mutex<Faults>>,
+}
+
+#[derive(Debug, Default)]
+struct Faults {
+    writes: u64,
+    syncs: u64,
+    fail_write_at: Option<u64>,
+    fail_sync_at: Option<u64>,
+}
+
+impl FaultFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail the `n`th (starting from 1) write from now on.
+    pub fn fail_nth_write(&self, n: u64) {
+        let mut faults = self.faults.lock().unwrap();
+        faults.fail_write_at = Some(faults.writes + n);
+    }
+
+    /// Fail the `n`th (starting from 1) sync from now on.
+    pub fn fail_nth_sync(&self, n: u64) {
+        let mut faults = self.faults.lock().unwrap();
+        faults.fail_sync_at = Some(faults.syncs + n);
+    }
+
+    /// Return total writes made so far.
+    pub fn total_writes(&self) -> u64 {
+        self.faults.lock().unwrap().writes
+    }
+
+    /// Simulate a crash, see `MemFs::crash`. Pending faults are cleared.
+    pub fn crash(&self, keep_unsynced: usize) {
+        let mut faults = self.faults.lock().unwrap();
+        faults.fail_write_at = None;
+        faults.fail_sync_at = None;
+        self.fs.crash(keep_unsynced);
+    }
+
+    fn wrap(&self, file: Box<dyn VfsFile>) -> Box<dyn VfsFile> {
+        Box::new(FaultFile {
+            file,
+            faults: self.faults.clone(),
+        })
+    }
+}
+
+impl Vfs for FaultFs {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.fs.create_dir_all(path)
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.fs.read_dir(dir)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.fs.exists(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(self.wrap(self.fs.open(path)?))
+    }
+
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(self.wrap(self.fs.open_append(path)?))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.fs.remove_file(path)
+    }
+
+    fn file_size(&self, path: &Path) -> io::Result<u64> {
+        self.fs.file_size(path)
+    }
+}
+
+#[derive(Debug)]
+struct FaultFile {
+    file: Box<dyn VfsFile>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+impl Read for FaultFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for FaultFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut faults = self.faults.lock().unwrap();
+        faults.writes += 1;
+        if faults.fail_write_at == Some(faults.writes) {
+            return Err(injected("write"));
+        }
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for FaultFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl VfsFile for FaultFile {
+    fn sync_data(&self) -> io::Result<()> {
+        let mut faults = self.faults.lock().unwrap();
+        faults.syncs += 1;
+        if faults.fail_sync_at == Some(faults.syncs) {
+            return Err(injected("sync"));
+        }
+        self.file.sync_data()
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(FaultFile {
+            file: self.file.try_clone()?,
+            faults: self.faults.clone(),
+        }))
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("'{}' not found", path.display()),
+    )
+}
+
+fn injected(op: &str) -> io::Error {
+    io::Error::other(format!("injected {} failure", op))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mem_fs() {
+        let fs = MemFs::new();
+        let dir = Path::new("/db");
+        let path = dir.join("000000000001.tinkv.data");
+        assert!(fs.open_append(&path).is_err());
+
+        fs.create_dir_all(dir).unwrap();
+        let mut w = fs.open_append(&path).unwrap();
+        w.write_all(b"hello").unwrap();
+        w.sync_data().unwrap();
+        w.write_all(b" world").unwrap();
+        assert_eq!(fs.read_dir(dir).unwrap(), vec![path.clone()]);
+        assert_eq!(fs.file_size(&path).unwrap(), 11);
+
+        let mut r = fs.open(&path).unwrap();
+        r.seek(SeekFrom::Start(6)).unwrap();
+        let mut buf = String::new();
+        r.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "world");
+
+        fs.crash(2);
+        assert_eq!(fs.file_size(&path).unwrap(), 7);
+        fs.crash(0);
+        assert_eq!(fs.file_size(&path).unwrap(), 7);
+
+        fs.remove_file(&path).unwrap();
+        assert!(!fs.exists(&path));
+        assert!(fs.exists(dir));
+    }
+
+    #[test]
+    fn test_fault_fs() {
+        let fs = FaultFs::new();
+        let path = Path::new("/db/a");
+        fs.create_dir_all(Path::new("/db")).unwrap();
+        let mut w = fs.open_append(path).unwrap();
+
+        fs.fail_nth_write(2);
+        w.write_all(b"a").unwrap();
+        assert!(w.write_all(b"b").is_err());
+        w.write_all(b"c").unwrap();
+        assert_eq!(fs.total_writes(), 3);
+
+        fs.fail_nth_sync(1);
+        assert!(w.sync_data().is_err());
+        w.sync_data().unwrap();
+        assert_eq!(fs.file_size(path).unwrap(), 2);
+    }
+}
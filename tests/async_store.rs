@@ -0,0 +1,87 @@
+#![cfg(feature = "async")]
+use futures_core::Stream;
+use std::future::poll_fn;
+use std::pin::Pin;
+use tempfile::TempDir;
+use tinkv::{AsyncStore, OpenOptions, Result, Store};
+
+async fn collect<S>(mut stream: S) -> Result<Vec<(Vec<u8>, Vec<u8>)>>
+where
+    S: Stream<Item = Result<(Vec<u8>, Vec<u8>)>> + Unpin,
+{
+    let mut pairs = vec![];
+    while let Some(pair) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+        pairs.push(pair?);
+    }
+    Ok(pairs)
+}
+
+#[tokio::test]
+async fn async_store() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let store = AsyncStore::open(tmpdir.path()).await?;
+
+    store.set(b"hello", b"world").await?;
+    assert_eq!(store.get(b"hello").await?, Some(b"world".to_vec()));
+    assert!(store.contains_key(b"hello").await?);
+    store.remove(b"hello").await?;
+    assert_eq!(store.get(b"hello").await?, None);
+    assert!(matches!(
+        store.remove(b"hello").await,
+        Err(tinkv::TinkvError::KeyNotFound(_))
+    ));
+    assert!(store.is_empty().await?);
+
+    // handles are shared by tasks.
+    let tasks = (0..4u8)
+        .map(|i| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                for j in 0..100u8 {
+                    store.set(&[i, j], &[j]).await?;
+                }
+                Ok::<_, tinkv::TinkvError>(())
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        task.await.unwrap()?;
+    }
+    assert_eq!(store.len().await?, 400);
+    store.compact().await?;
+    assert_eq!(store.stats().await?.total_stale_entries, 0);
+
+    // streams fetch pairs in batches.
+    let pairs = collect(store.iter()).await?;
+    assert_eq!(pairs.len(), 400);
+    assert!(pairs.windows(2).all(|w| w[0].0 < w[1].0));
+    let pairs = collect(store.range(vec![1, 50]..vec![2])).await?;
+    assert_eq!(pairs.len(), 50);
+    assert_eq!(pairs[0], (vec![1, 50], vec![50]));
+    assert!(collect(store.range(vec![9]..)).await?.is_empty());
+
+    let total = store
+        .call(|store: &mut Store| Ok(store.keys().count()))
+        .await?;
+    assert_eq!(total, 400);
+    drop(store);
+
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+    store.set(b"a", b"1")?;
+    drop(store);
+    let mut options = OpenOptions::new();
+    options.read_only(true);
+    let store = AsyncStore::open_with_options(tmpdir.path(), &options).await?;
+    assert_eq!(store.get(b"a").await?, Some(b"1".to_vec()));
+    assert!(matches!(
+        store.set(b"a", b"2").await,
+        Err(tinkv::TinkvError::ReadOnly)
+    ));
+    assert!(
+        AsyncStore::open_with_options(tmpdir.path().join("x"), &options)
+            .await
+            .is_err()
+    );
+    Ok(())
+}
@@ -0,0 +1,280 @@
+//! Crash tests, the datastore lives in a filesystem injecting faults.
+//!
+//! Writes fail at arbitrary points, then the process "crashes" (the
+//! store is leaked without being dropped, and data not synced yet is
+//! lost). The datastore must be recovered on reopening:
+//!
+//! - acknowledged durable writes are never lost,
+//! - values are never corrupted, each key has one of the values written,
+//! - sequence numbers never go backwards.
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use tinkv::{FaultFs, OpenOptions, Result, Store, SyncPolicy};
+
+const DIR: &str = "/tinkv";
+// key written after recovery to check the datastore is still writeable.
+const PROBE_KEY: &[u8] = b"after-crash";
+
+/// What has been written to the datastore.
+#[derive(Default)]
+struct Model {
+    // the last acknowledged value of each key, `None` if removed.
+    acked: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    // all the values ever written (or attempted) of each key.
+    written: BTreeMap<Vec<u8>, BTreeSet<Vec<u8>>>,
+    // sequence number of the last acknowledged write.
+    last_seq: u64,
+}
+
+impl Model {
+    fn set(&mut self, store: &mut Store, key: &[u8], value: &[u8]) -> Result<()> {
+        self.written
+            .entry(key.to_vec())
+            .or_default()
+            .insert(value.to_vec());
+        store.set(key, value)?;
+        self.acked.insert(key.to_vec(), Some(value.to_vec()));
+        self.last_seq = store.last_seq();
+        Ok(())
+    }
+
+    fn remove(&mut self, store: &mut Store, key: &[u8]) -> Result<()> {
+        store.remove(key)?;
+        self.acked.insert(key.to_vec(), None);
+        self.last_seq = store.last_seq();
+        Ok(())
+    }
+
+    /// Check recovery invariants against the reopened datastore.
+    fn check(&self, store: &mut Store, durable: bool) -> Result<()> {
+        if durable {
+            for (key, value) in self.acked.iter() {
+                assert_eq!(
+                    &store.get(key)?,
+                    value,
+                    "acknowledged write of key '{}' is lost",
+                    String::from_utf8_lossy(key)
+                );
+            }
+            assert!(store.last_seq() >= self.last_seq);
+        }
+
+        let keys = store.keys().cloned().collect::<Vec<_>>();
+        for key in keys.into_iter().filter(|k| k != PROBE_KEY) {
+            let value = store.get(&key)?.expect("value not found");
+            let written = self.written.get(&key).expect("unknown key");
+            assert!(written.contains(&value), "value is corrupted");
+        }
+
+        // datastore is still writeable, new writes win.
+        let seq = store.last_seq();
+        store.set(PROBE_KEY, b"ok")?;
+        assert!(store.last_seq() > seq);
+        let (value, meta) = store.get_with_meta(PROBE_KEY)?.unwrap();
+        assert_eq!(value, b"ok".to_vec());
+        assert!(meta.version > seq);
+        Ok(())
+    }
+}
+
+fn open(fs: &FaultFs, policy: SyncPolicy, max_data_file_size: u64) -> Result<Store> {
+    OpenOptions::new()
+        .vfs(fs.clone())
+        .sync_policy(policy)
+        .max_data_file_size(max_data_file_size)
+        .open(DIR)
+}
+
+/// Leak the store as if the process is killed, nothing is flushed.
+fn crash(store: Store, fs: &FaultFs, keep_unsynced: usize) {
+    std::mem::forget(store);
+    fs.crash(keep_unsynced);
+}
+
+/// Fail the `n`th write after a few acknowledged writes, then crash.
+fn crash_during_writes(n: u64, keep_unsynced: usize, max_data_file_size: u64) -> Result<()> {
+    let fs = FaultFs::new();
+    let mut model = Model::default();
+    let mut store = open(&fs, SyncPolicy::Always, max_data_file_size)?;
+
+    for i in 0..10 {
+        model.set(&mut store, format!("key_{}", i % 4).as_bytes(), &[i; 32])?;
+    }
+    model.remove(&mut store, b"key_0")?;
+
+    fs.fail_nth_write(n);
+    for i in 10..30 {
+        let key = format!("key_{}", i % 6);
+        if model.set(&mut store, key.as_bytes(), &[i; 32]).is_err() {
+            break;
+        }
+    }
+
+    crash(store, &fs, keep_unsynced);
+    let mut store = open(&fs, SyncPolicy::Always, max_data_file_size)?;
+    model.check(&mut store, true)
+}
+
+#[test]
+fn crash_during_set() -> Result<()> {
+    for n in 1..=20 {
+        for &keep in &[0, 7, 64] {
+            crash_during_writes(n, keep, 1024 * 1024)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn crash_during_rotation() -> Result<()> {
+    // each data file only holds a few entries.
+    for n in 1..=20 {
+        for &keep in &[0, 7, 64] {
+            crash_during_writes(n, keep, 200)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn crash_with_failed_sync() -> Result<()> {
+    for n in 1..=10 {
+        let fs = FaultFs::new();
+        let mut model = Model::default();
+        let mut store = open(&fs, SyncPolicy::Always, 200)?;
+
+        fs.fail_nth_sync(n);
+        for i in 0..20 {
+            if model
+                .set(&mut store, format!("key_{}", i).as_bytes(), &[i; 32])
+                .is_err()
+            {
+                break;
+            }
+        }
+
+        crash(store, &fs, 0);
+        let mut store = open(&fs, SyncPolicy::Always, 200)?;
+        model.check(&mut store, true)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn crash_without_sync() -> Result<()> {
+    // nothing is durable, but the survived writes are
+    // a prefix of all the writes, torn ones are dropped.
+    for &keep in &[0, 1, 13, 100, 1000] {
+        let fs = FaultFs::new();
+        let mut model = Model::default();
+        let mut store = open(&fs, SyncPolicy::Never, 1024 * 1024)?;
+        for i in 0..20 {
+            model.set(&mut store, format!("key_{:02}", i).as_bytes(), &[i; 32])?;
+        }
+
+        crash(store, &fs, keep);
+        let mut store = open(&fs, SyncPolicy::Never, 1024 * 1024)?;
+        let total = store.len();
+        for i in 0..20 {
+            let key = format!("key_{:02}", i);
+            assert_eq!(store.contains_key(key.as_bytes()), (i as u64) < total);
+        }
+        model.check(&mut store, false)?;
+    }
+    Ok(())
+}
+
+/// Fail the `n`th write of compaction, for increasing `n` until
+/// compaction succeeds, then crash (or drop the store).
+fn crash_during_compaction(retain_versions: usize) -> Result<()> {
+    let open = |fs: &FaultFs| {
+        OpenOptions::new()
+            .vfs(fs.clone())
+            .sync_policy(SyncPolicy::Always)
+            .max_data_file_size(256)
+            .retain_versions(retain_versions)
+            .open(DIR)
+    };
+
+    let mut n = 1;
+    loop {
+        // `None` drops the store without crashing.
+        for &keep in &[None, Some(0), Some(5), Some(64)] {
+            let fs = FaultFs::new();
+            let mut model = Model::default();
+            let mut store = open(&fs)?;
+            for i in 0..30 {
+                model.set(&mut store, format!("key_{}", i % 8).as_bytes(), &[i; 24])?;
+            }
+            model.remove(&mut store, b"key_3")?;
+            model.remove(&mut store, b"key_5")?;
+
+            let writes = fs.total_writes();
+            fs.fail_nth_write(n);
+            let compacted = store.compact().is_ok();
+            if compacted && fs.total_writes() - writes < n {
+                // no fault injected, all the crash points are covered.
+                return Ok(());
+            }
+
+            match keep {
+                Some(keep) => crash(store, &fs, keep),
+                None => drop(store),
+            }
+            let mut store = open(&fs)?;
+            model.check(&mut store, true)?;
+
+            // recovered datastore can be compacted again.
+            store.compact()?;
+            drop(store);
+            let mut store = open(&fs)?;
+            model.check(&mut store, true)?;
+        }
+        n += 1;
+        assert!(n < 1000, "too many writes in compaction");
+    }
+}
+
+#[test]
+fn crash_during_compact() -> Result<()> {
+    crash_during_compaction(1)
+}
+
+#[test]
+fn crash_during_compact_with_retention() -> Result<()> {
+    crash_during_compaction(3)
+}
+
+#[test]
+fn mem_fs_store() -> Result<()> {
+    let fs = tinkv::MemFs::new();
+    let mut store = OpenOptions::new().vfs(fs.clone()).open(DIR)?;
+    store.set(b"hello", b"world")?;
+    store.compact()?;
+    drop(store);
+
+    assert!(!Path::new(DIR).exists());
+    let mut store = OpenOptions::new().vfs(fs).open(DIR)?;
+    assert_eq!(store.get(b"hello")?, Some(b"world".to_vec()));
+    Ok(())
+}
+
+#[test]
+fn mem_fs_checkpoint() -> Result<()> {
+    let fs = tinkv::MemFs::new();
+    let mut store = OpenOptions::new().vfs(fs).open(DIR)?;
+    store.set(b"hello", b"world")?;
+    store.compact()?;
+    store.set(b"foo", b"bar")?;
+
+    // checkpoint is written to the real disk.
+    let tmpdir = tempfile::TempDir::new().expect("unable to create tmp dir");
+    let dir = tmpdir.path().join("checkpoint");
+    let manifest = store.checkpoint(&dir)?;
+    assert!(!manifest.files.is_empty());
+
+    let mut checkpoint = Store::open(&dir)?;
+    assert_eq!(checkpoint.get(b"hello")?, Some(b"world".to_vec()));
+    assert_eq!(checkpoint.get(b"foo")?, Some(b"bar".to_vec()));
+    Ok(())
+}
@@ -1,10 +1,17 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 use tempfile::TempDir;
-use tinkv::{self, Result, Store};
+use tinkv::{
+    self, AddOperator, AppendOperator, ChangeKind, EvictionPolicy, JsonCodec, OpenOptions, Result,
+    Store, SyncPolicy, TypedStore,
+};
 
 #[test]
 fn get_stored_value() -> Result<()> {
     let tmpdir = TempDir::new().expect("unable to create tmp dir");
-    let mut store = Store::open(&tmpdir.path())?;
+    let mut store = Store::open(tmpdir.path())?;
 
     store.set(b"version", b"1.0")?;
     store.set(b"name", b"tinkv")?;
@@ -16,7 +23,7 @@ fn get_stored_value() -> Result<()> {
     store.close()?;
 
     // open again, check persisted data.
-    let mut store = Store::open(&tmpdir.path())?;
+    let mut store = Store::open(tmpdir.path())?;
     assert_eq!(store.get(b"version")?, Some(b"1.0".to_vec()));
     assert_eq!(store.get(b"name")?, Some(b"tinkv".to_vec()));
     assert_eq!(store.len(), 2);
@@ -27,7 +34,7 @@ fn get_stored_value() -> Result<()> {
 #[test]
 fn overwrite_value() -> Result<()> {
     let tmpdir = TempDir::new().expect("unable to create tmp dir");
-    let mut store = Store::open(&tmpdir.path())?;
+    let mut store = Store::open(tmpdir.path())?;
 
     store.set(b"version", b"1.0")?;
     assert_eq!(store.get(b"version")?, Some(b"1.0".to_vec()));
@@ -38,7 +45,7 @@ fn overwrite_value() -> Result<()> {
     store.close()?;
 
     // open again and check data
-    let mut store = Store::open(&tmpdir.path())?;
+    let mut store = Store::open(tmpdir.path())?;
     assert_eq!(store.get(b"version")?, Some(b"2.0".to_vec()));
 
     Ok(())
@@ -47,13 +54,13 @@ fn overwrite_value() -> Result<()> {
 #[test]
 fn get_non_existent_key() -> Result<()> {
     let tmpdir = TempDir::new().expect("unable to create tmp dir");
-    let mut store = Store::open(&tmpdir.path())?;
+    let mut store = Store::open(tmpdir.path())?;
 
     store.set(b"version", b"1.0")?;
     assert_eq!(store.get(b"version_foo")?, None);
     store.close()?;
 
-    let mut store = Store::open(&tmpdir.path())?;
+    let mut store = Store::open(tmpdir.path())?;
     assert_eq!(store.get(b"version_foo")?, None);
 
     Ok(())
@@ -62,7 +69,7 @@ fn get_non_existent_key() -> Result<()> {
 #[test]
 fn remove_key() -> Result<()> {
     let tmpdir = TempDir::new().expect("unable to create tmp dir");
-    let mut store = Store::open(&tmpdir.path())?;
+    let mut store = Store::open(tmpdir.path())?;
 
     store.set(b"version", b"1.0")?;
     assert!(store.remove(b"version").is_ok());
@@ -74,7 +81,7 @@ fn remove_key() -> Result<()> {
 #[test]
 fn remove_non_existent_key() -> Result<()> {
     let tmpdir = TempDir::new().expect("unable to create tmp dir");
-    let mut store = Store::open(&tmpdir.path())?;
+    let mut store = Store::open(tmpdir.path())?;
 
     assert!(store.remove(b"version").is_err());
 
@@ -84,7 +91,7 @@ fn remove_non_existent_key() -> Result<()> {
 #[test]
 fn compaction() -> Result<()> {
     let tmpdir = TempDir::new().expect("unable to create tmp dir");
-    let mut store = Store::open(&tmpdir.path())?;
+    let mut store = Store::open(tmpdir.path())?;
 
     for it in 0..100 {
         for id in 0..1000 {
@@ -109,7 +116,7 @@ fn compaction() -> Result<()> {
         // close and reopen, chack persisted data
         store.close()?;
 
-        store = Store::open(&tmpdir.path())?;
+        store = Store::open(tmpdir.path())?;
 
         let stats = store.stats();
         assert_eq!(stats.size_of_stale_entries, 0);
@@ -127,3 +134,1354 @@ fn compaction() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn keyspaces_are_isolated() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+
+    store.set(b"name", b"default")?;
+    store.keyspace("users")?.set(b"name", b"users")?;
+    store.keyspace("sessions")?.set(b"token", b"abc")?;
+
+    assert_eq!(store.get(b"name")?, Some(b"default".to_vec()));
+    assert_eq!(
+        store.keyspace("users")?.get(b"name")?,
+        Some(b"users".to_vec())
+    );
+    assert_eq!(store.keyspace("users")?.get(b"token")?, None);
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.stats().total_active_entries, 3);
+
+    store.close()?;
+
+    // open again, check persisted data.
+    let mut store = Store::open(tmpdir.path())?;
+    assert_eq!(store.get(b"name")?, Some(b"default".to_vec()));
+    let mut users = store.keyspace("users")?;
+    assert_eq!(users.get(b"name")?, Some(b"users".to_vec()));
+    assert_eq!(users.len(), 1);
+    assert_eq!(users.stats().total_active_entries, 1);
+    assert_eq!(
+        store.keyspace("sessions")?.get(b"token")?,
+        Some(b"abc".to_vec())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn drop_keyspace() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+
+    store.set(b"version", b"1.0")?;
+    for id in 0..100 {
+        let k = format!("key_{}", id);
+        store.keyspace("sessions")?.set(k.as_bytes(), b"value")?;
+    }
+
+    store.drop_keyspace("sessions")?;
+    assert!(store.drop_keyspace("sessions").is_err());
+    assert!(store.drop_keyspace("default").is_err());
+    assert_eq!(store.keyspace("sessions")?.len(), 0);
+    assert_eq!(store.stats().total_active_entries, 1);
+
+    // recreate the keyspace after it was dropped.
+    store.keyspace("sessions")?.set(b"key_new", b"value")?;
+    store.close()?;
+
+    let mut store = Store::open(tmpdir.path())?;
+    let mut sessions = store.keyspace("sessions")?;
+    assert_eq!(sessions.get(b"key_0")?, None);
+    assert_eq!(sessions.get(b"key_new")?, Some(b"value".to_vec()));
+
+    store.compact()?;
+    store.close()?;
+
+    let mut store = Store::open(tmpdir.path())?;
+    assert_eq!(store.stats().total_active_entries, 2);
+    assert_eq!(store.get(b"version")?, Some(b"1.0".to_vec()));
+    assert_eq!(store.keyspace("sessions")?.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn keyspace_options() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+
+    let mut sessions = store.keyspace_with_options(
+        "sessions",
+        tinkv::KeyspaceOptions::new()
+            .max_value_size(4)
+            .default_ttl(Duration::from_millis(50)),
+    )?;
+    assert!(sessions.set(b"token", b"too large").is_err());
+
+    sessions.set(b"token", b"abc")?;
+    sessions.set_with_ttl(b"forever", b"abc", Duration::from_secs(3600))?;
+    assert!(sessions.contains_key(b"token"));
+
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(sessions.get(b"token")?, None);
+    assert!(!sessions.contains_key(b"token"));
+    assert_eq!(sessions.get(b"forever")?, Some(b"abc".to_vec()));
+
+    // options of other keyspaces are not affected.
+    store.set(b"token", b"too large")?;
+
+    Ok(())
+}
+
+#[test]
+fn checkpoint() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let backup_dir = TempDir::new().expect("unable to create tmp dir");
+    let backup_path = backup_dir.path().join("checkpoint");
+    let mut store = Store::open(tmpdir.path())?;
+
+    store.set(b"version", b"1.0")?;
+    store.keyspace("users")?.set(b"name", b"tinkv")?;
+
+    let manifest = store.checkpoint(&backup_path)?;
+    assert!(!manifest.files.is_empty());
+    assert!(store.checkpoint(&backup_path).is_err());
+
+    // writes after checkpoint are not included.
+    store.set(b"version", b"2.0")?;
+    assert_eq!(store.get(b"version")?, Some(b"2.0".to_vec()));
+
+    let mut backup = Store::open(&backup_path)?;
+    assert_eq!(backup.get(b"version")?, Some(b"1.0".to_vec()));
+    assert_eq!(
+        backup.keyspace("users")?.get(b"name")?,
+        Some(b"tinkv".to_vec())
+    );
+    assert_eq!(tinkv::Manifest::load(&backup_path)?, manifest);
+
+    Ok(())
+}
+
+#[test]
+fn incremental_backup() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let backup_dir = TempDir::new().expect("unable to create tmp dir");
+    let full = backup_dir.path().join("full");
+    let incr1 = backup_dir.path().join("incr1");
+    let incr2 = backup_dir.path().join("incr2");
+    let mut store = Store::open(tmpdir.path())?;
+
+    store.set(b"version", b"1.0")?;
+    store.set(b"name", b"tinkv")?;
+    let base = store.checkpoint(&full)?;
+
+    store.set(b"version", b"2.0")?;
+    let manifest = store.incremental_backup(&incr1, &base)?;
+    assert!(manifest.files.iter().any(|f| f.inherited));
+    assert!(manifest.removed.is_empty());
+
+    // compaction removes the segments of previous backups.
+    store.remove(b"name")?;
+    store.compact()?;
+    store.set(b"version", b"3.0")?;
+    let manifest = store.incremental_backup(&incr2, &manifest)?;
+    assert!(!manifest.removed.is_empty());
+
+    let restored = tmpdir.path().join("restored");
+    tinkv::restore_backup(&restored, &[full.clone(), incr1.clone()])?;
+    let mut store = Store::open(&restored)?;
+    assert_eq!(store.get(b"version")?, Some(b"2.0".to_vec()));
+    assert_eq!(store.get(b"name")?, Some(b"tinkv".to_vec()));
+
+    let restored = tmpdir.path().join("restored2");
+    tinkv::restore_backup(&restored, &[full, incr1, incr2])?;
+    let mut store = Store::open(&restored)?;
+    assert_eq!(store.get(b"version")?, Some(b"3.0".to_vec()));
+    assert_eq!(store.get(b"name")?, None);
+
+    Ok(())
+}
+
+#[test]
+fn export_and_import() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+
+    store.set(b"user:1", b"\x00\x01")?;
+    store.set(b"user:2", b"tinkv")?;
+    store.set(b"flag:1", b"on")?;
+
+    for format in [tinkv::Format::JsonLines, tinkv::Format::Csv].iter() {
+        let mut options = tinkv::ExportOptions::new();
+        options
+            .format(*format)
+            .encoding(tinkv::Encoding::Hex)
+            .prefix(b"user:");
+
+        let mut buf = vec![];
+        assert_eq!(store.export(&mut buf, &options)?, 2);
+
+        let mut options = options.clone();
+        options.keyspace("imported").batch_size(1);
+        assert_eq!(store.import(buf.as_slice(), &options)?, 2);
+
+        let mut imported = store.keyspace("imported")?;
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported.get(b"user:1")?, Some(b"\x00\x01".to_vec()));
+        assert_eq!(imported.get(b"user:2")?, Some(b"tinkv".to_vec()));
+        store.drop_keyspace("imported")?;
+    }
+
+    // keys are exported in chunks of `batch_size`.
+    store.set(b"user:3", b"x")?;
+    store.set(b"user:4", b"y")?;
+    store.set_with_ttl(b"user:5", b"z", Duration::from_millis(1))?;
+    std::thread::sleep(Duration::from_millis(10));
+    let mut options = tinkv::ExportOptions::new();
+    options.prefix(b"user:").batch_size(2);
+    let mut buf = vec![];
+    assert_eq!(store.export(&mut buf, &options)?, 4);
+    let keys = String::from_utf8(buf)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["key"].clone())
+        .collect::<Vec<_>>();
+    let expected = ["user:1", "user:2", "user:3", "user:4"]
+        .iter()
+        .map(|key| serde_json::Value::from(base64::encode(key)))
+        .collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+
+    Ok(())
+}
+
+#[test]
+fn import_rdb() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+
+    let mut rdb = b"REDIS0009".to_vec();
+    // db 0: a string, an expired string and a set.
+    rdb.extend_from_slice(&[0xFE, 0, 0, 1, b'a', 2, b'h', b'i']);
+    rdb.push(0xFC);
+    rdb.extend_from_slice(&1_000u64.to_le_bytes());
+    rdb.extend_from_slice(&[0, 1, b'b', 1, b'x']);
+    rdb.extend_from_slice(&[2, 1, b's', 1, 1, b'm']);
+    // db 1: a string.
+    rdb.extend_from_slice(&[0xFE, 1, 0, 1, b'c', 2, b'h', b'o']);
+    rdb.push(0xFF);
+    rdb.extend_from_slice(&[0u8; 8]);
+
+    let stats = store.import_rdb(rdb.as_slice())?;
+    assert_eq!(stats.imported, 2);
+    assert_eq!(stats.expired, 1);
+    assert_eq!(stats.skipped.get("set"), Some(&1));
+
+    assert_eq!(store.get(b"a")?, Some(b"hi".to_vec()));
+    assert_eq!(store.get(b"b")?, None);
+    assert_eq!(store.keyspace("db1")?.get(b"c")?, Some(b"ho".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn subscribe_changes() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+
+    store.set(b"a", b"1")?;
+    let seq = store.last_seq();
+
+    let sub = store.subscribe();
+    store.set(b"b", b"2")?;
+    store.keyspace("users")?.set(b"c", b"3")?;
+    store.remove(b"a")?;
+
+    let events = std::iter::from_fn(|| sub.try_recv()).collect::<Vec<_>>();
+    assert_eq!(events.len(), 3);
+    assert!(events.windows(2).all(|w| w[0].seq < w[1].seq));
+    assert_eq!(events[0].seq, seq + 1);
+    assert_eq!(
+        events[0].kind,
+        ChangeKind::Put {
+            value: b"2".to_vec(),
+            expires_at: None
+        }
+    );
+    assert_eq!(events[1].keyspace, "users");
+    assert_eq!(events[2].key, b"a".to_vec());
+    assert_eq!(events[2].kind, ChangeKind::Delete);
+
+    // resume from a sequence number after reopening.
+    drop(sub);
+    drop(store);
+    let mut store = Store::open(tmpdir.path())?;
+    assert_eq!(store.last_seq(), events[2].seq);
+
+    // events are replayed in background.
+    let mut sub = store.subscribe_from(seq)?;
+    let replayed = sub.by_ref().take(events.len()).collect::<Vec<_>>();
+    assert_eq!(replayed, events);
+
+    // sequence numbers never go backwards after compaction.
+    store.remove(b"b")?;
+    let last_seq = store.last_seq();
+    store.compact()?;
+    drop(sub);
+    drop(store);
+    let mut store = Store::open(tmpdir.path())?;
+    assert_eq!(store.last_seq(), last_seq);
+
+    let sub = store.subscribe_from(seq)?;
+    let timeout = Duration::from_secs(5);
+    assert_eq!(
+        sub.recv_timeout(timeout).map(|e| e.key),
+        Some(b"c".to_vec())
+    );
+    store.set(b"d", b"4")?;
+    assert_eq!(sub.recv_timeout(timeout).map(|e| e.seq), Some(last_seq + 1));
+
+    Ok(())
+}
+
+#[test]
+fn subscribe_dropped_keyspaces() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+    let timeout = Duration::from_secs(5);
+
+    let sub = store.subscribe();
+    store.keyspace("users")?.set(b"a", b"1")?;
+    store.drop_keyspace("users")?;
+    assert_eq!(sub.try_recv().map(|e| e.key), Some(b"a".to_vec()));
+    let event = sub.try_recv().unwrap();
+    assert_eq!(event.keyspace, "users");
+    assert_eq!(event.key, Vec::<u8>::new());
+    assert_eq!(event.kind, ChangeKind::DropKeyspace);
+
+    let replayed = store.subscribe_from(0)?;
+    assert_eq!(replayed.recv_timeout(timeout).map(|e| e.seq), Some(1));
+    assert_eq!(replayed.recv_timeout(timeout), Some(event));
+
+    // subscriptions falling behind are cancelled.
+    for i in 0..tinkv::config::SUBSCRIPTION_BUFFER_SIZE as u32 {
+        store.set(&i.to_be_bytes(), b"")?;
+    }
+    assert!(!sub.is_cancelled());
+    store.set(b"full", b"")?;
+    let received = std::iter::from_fn(|| sub.try_recv()).count();
+    assert_eq!(received, tinkv::config::SUBSCRIPTION_BUFFER_SIZE);
+    assert!(sub.is_cancelled());
+
+    Ok(())
+}
+
+#[test]
+fn get_with_meta() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+
+    assert_eq!(store.get_with_meta(b"a")?, None);
+
+    store.set(b"a", b"1")?;
+    let (value, meta1) = store.get_with_meta(b"a")?.unwrap();
+    assert_eq!(value, b"1".to_vec());
+    assert_eq!(meta1.version, store.last_seq());
+    assert!(meta1.timestamp_ns > 0);
+    assert_eq!(meta1.expires_at_ms, None);
+
+    thread::sleep(Duration::from_millis(1));
+    store.set_with_ttl(b"a", b"2", Duration::from_secs(60))?;
+    let (_, meta2) = store.get_with_meta(b"a")?.unwrap();
+    assert!(meta2.version > meta1.version);
+    assert!(meta2.timestamp_ns > meta1.timestamp_ns);
+    assert!(meta2.expires_at_ms.is_some());
+
+    // metadata is kept after compaction and reopening.
+    store.compact()?;
+    drop(store);
+    let mut store = Store::open(tmpdir.path())?;
+    assert_eq!(store.get_with_meta(b"a")?.unwrap().1, meta2);
+
+    Ok(())
+}
+
+#[test]
+fn history_and_get_at() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+
+    store.set(b"a", b"1")?;
+    let v1 = store.last_seq();
+    store.set(b"b", b"x")?;
+    store.set(b"a", b"2")?;
+    let v2 = store.last_seq();
+    store.remove(b"a")?;
+    let v3 = store.last_seq();
+    store.set(b"a", b"3")?;
+
+    let history = store.history(b"a")?;
+    let values = history.iter().map(|v| v.value.clone()).collect::<Vec<_>>();
+    assert_eq!(
+        values,
+        vec![
+            Some(b"1".to_vec()),
+            Some(b"2".to_vec()),
+            None,
+            Some(b"3".to_vec())
+        ]
+    );
+    assert_eq!(history[0].meta.version, v1);
+
+    assert_eq!(store.get_at(b"a", v1 - 1)?, None);
+    assert_eq!(store.get_at(b"a", v1)?, Some(b"1".to_vec()));
+    assert_eq!(store.get_at(b"a", v2)?, Some(b"2".to_vec()));
+    assert_eq!(store.get_at(b"a", v3)?, None);
+    assert_eq!(store.get_at(b"a", store.last_seq())?, Some(b"3".to_vec()));
+
+    // old versions are dropped by compaction by default.
+    store.compact()?;
+    assert_eq!(store.history(b"a")?.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn compaction_with_retention() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = OpenOptions::new().retain_versions(3).open(tmpdir.path())?;
+
+    for i in 0..5 {
+        store.set(b"a", format!("{}", i).as_bytes())?;
+    }
+    store.set(b"b", b"1")?;
+    store.remove(b"b")?;
+    // versions of expired keys are not kept.
+    store.set(b"c", b"1")?;
+    store.set_with_ttl(b"c", b"2", Duration::from_millis(1))?;
+    std::thread::sleep(Duration::from_millis(10));
+
+    store.compact()?;
+    assert_eq!(store.history(b"c")?.len(), 0);
+    let values = store
+        .history(b"a")?
+        .into_iter()
+        .map(|v| v.value.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(values, vec![b"2".to_vec(), b"3".to_vec(), b"4".to_vec()]);
+    assert_eq!(store.history(b"b")?.len(), 2);
+    assert_eq!(store.stats().total_stale_entries, 4);
+    let stats = *store.stats();
+
+    // a second compaction keeps the same versions.
+    store.compact()?;
+    assert_eq!(store.history(b"a")?.len(), 3);
+    assert_eq!(store.history(b"b")?.len(), 2);
+    assert_eq!(store.stats().total_stale_entries, 4);
+
+    // live versions win on reopening, old versions are stale.
+    drop(store);
+    let mut store = OpenOptions::new()
+        .retain_window(Duration::from_secs(0))
+        .open(tmpdir.path())?;
+    assert_eq!(store.get(b"a")?, Some(b"4".to_vec()));
+    assert_eq!(store.get(b"b")?, None);
+    assert_eq!(store.len(), 1);
+    assert_eq!(store.stats().total_active_entries, 1);
+    assert_eq!(store.stats().total_stale_entries, 4);
+    assert_eq!(
+        store.stats().size_of_stale_entries,
+        stats.size_of_stale_entries
+    );
+
+    store.compact()?;
+    assert_eq!(store.history(b"a")?.len(), 1);
+    assert_eq!(store.history(b"b")?.len(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn conditional_writes() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+
+    assert!(!store.set_if_present(b"a", b"0")?);
+    assert!(store.set_if_absent(b"a", b"1")?);
+    assert!(!store.set_if_absent(b"a", b"2")?);
+    assert!(store.set_if_present(b"a", b"3")?);
+    assert_eq!(store.get(b"a")?, Some(b"3".to_vec()));
+
+    let err = store
+        .compare_and_swap(b"a", Some(b"1"), Some(b"4"))?
+        .unwrap_err();
+    assert_eq!(err.current, Some(b"3".to_vec()));
+    assert_eq!(store.get(b"a")?, Some(b"3".to_vec()));
+
+    assert!(store
+        .compare_and_swap(b"a", Some(b"3"), Some(b"4"))?
+        .is_ok());
+    assert_eq!(store.get(b"a")?, Some(b"4".to_vec()));
+
+    // remove key.
+    assert!(store.compare_and_swap(b"a", Some(b"4"), None)?.is_ok());
+    assert!(!store.contains_key(b"a"));
+
+    // create key.
+    let err = store.compare_and_swap(b"b", Some(b"1"), None)?.unwrap_err();
+    assert_eq!(err.current, None);
+    assert!(store.compare_and_swap(b"b", None, Some(b"1"))?.is_ok());
+    assert_eq!(store.get(b"b")?, Some(b"1".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn merge_operators() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+    assert!(store.merge(b"counter", b"1").is_err());
+    drop(store);
+
+    let mut store = OpenOptions::new()
+        .merge_operator(AddOperator)
+        .open(tmpdir.path())?;
+    store.merge(b"counter", b"1")?;
+    store.merge(b"counter", b"2")?;
+    assert_eq!(store.get(b"counter")?, Some(b"3".to_vec()));
+    assert!(store.contains_key(b"counter"));
+
+    store.set(b"counter", b"10")?;
+    store.merge(b"counter", b"-1")?;
+    assert_eq!(store.get(b"counter")?, Some(b"9".to_vec()));
+
+    // operands are replayed on reopening.
+    drop(store);
+    let mut store = OpenOptions::new()
+        .merge_operator(AddOperator)
+        .open(tmpdir.path())?;
+    assert_eq!(store.get(b"counter")?, Some(b"9".to_vec()));
+
+    // and collapsed by compaction.
+    store.merge(b"counter", b"1")?;
+    store.compact()?;
+    assert_eq!(store.get(b"counter")?, Some(b"10".to_vec()));
+    assert_eq!(store.stats().total_stale_entries, 0);
+    store.merge(b"counter", b"1")?;
+    assert_eq!(store.get(b"counter")?, Some(b"11".to_vec()));
+
+    store.remove(b"counter")?;
+    store.merge(b"counter", b"5")?;
+    assert_eq!(store.get(b"counter")?, Some(b"5".to_vec()));
+    drop(store);
+
+    let mut store = OpenOptions::new()
+        .merge_operator(AppendOperator)
+        .open(tmpdir.path())?;
+    store.set(b"log", b"a")?;
+    store.merge(b"log", b"b")?;
+    store.keyspace("logs")?.merge(b"log", b"c")?;
+    assert_eq!(store.get(b"log")?, Some(b"ab".to_vec()));
+    assert_eq!(store.keyspace("logs")?.get(b"log")?, Some(b"c".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn stats_of_pending_merge_operands() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let open = || {
+        OpenOptions::new()
+            .merge_operator(AddOperator)
+            .open(tmpdir.path())
+    };
+    let check = |store: &mut Store, stale: u64| -> Result<()> {
+        let active = store.keyspace("default")?.stats().size_of_active_entries;
+        let segments = store.segments()?;
+        assert_eq!(store.stats().total_active_entries, 1);
+        assert_eq!(store.stats().total_stale_entries, stale);
+        assert_eq!(
+            segments
+                .iter()
+                .map(|s| s.size_of_active_entries)
+                .sum::<u64>(),
+            active
+        );
+        assert_eq!(
+            segments
+                .iter()
+                .map(|s| s.size_of_stale_entries)
+                .sum::<u64>(),
+            store.stats().size_of_stale_entries
+        );
+        Ok(())
+    };
+
+    // base value and operands are all needed to fold the value.
+    let mut store = open()?;
+    store.set(b"counter", b"10")?;
+    store.merge(b"counter", b"1")?;
+    store.merge(b"counter", b"2")?;
+    check(&mut store, 0)?;
+    drop(store);
+
+    let mut store = open()?;
+    check(&mut store, 0)?;
+    assert_eq!(store.get(b"counter")?, Some(b"13".to_vec()));
+
+    // all of them become stale once the key is overwritten.
+    store.set(b"counter", b"0")?;
+    check(&mut store, 3)?;
+    drop(store);
+
+    let mut store = open()?;
+    check(&mut store, 3)?;
+
+    Ok(())
+}
+
+#[test]
+fn values_look_like_internal_records() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let values: Vec<&[u8]> = vec![
+        b"%TINKV_MERGE_OPERAND%hello",
+        b"%TINKV_REMOVE_TOMESTOME%",
+        b"%TINKV_DROP_KEYSPACE_TOMESTONE%",
+        b"",
+    ];
+    let mut store = Store::open(tmpdir.path())?;
+    for (i, value) in values.iter().enumerate() {
+        store.set(&[i as u8], value)?;
+        store.keyspace("users")?.set(b"", value)?;
+    }
+    for (i, value) in values.iter().enumerate() {
+        assert_eq!(store.get(&[i as u8])?.as_deref(), Some(*value));
+    }
+
+    // kinds of records don't depend on values on reopening.
+    drop(store);
+    let mut store = Store::open(tmpdir.path())?;
+    assert_eq!(store.len(), values.len() as u64);
+    for (i, value) in values.iter().enumerate() {
+        assert_eq!(store.get(&[i as u8])?.as_deref(), Some(*value));
+    }
+    assert_eq!(store.keyspace("users")?.get(b"")?, Some(vec![]));
+    Ok(())
+}
+
+#[test]
+fn group_commit() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = OpenOptions::new().sync(true).open(tmpdir.path())?;
+    store.set_defer_commits(true);
+    let commit = store.commit_handle();
+    let store = Arc::new(Mutex::new(store));
+
+    let (threads, writes) = (8, 50);
+    let handles = (0..threads)
+        .map(|t| {
+            let store = store.clone();
+            let commit = commit.clone();
+            thread::spawn(move || -> Result<()> {
+                for i in 0..writes {
+                    let key = format!("key_{}_{}", t, i);
+                    let seq = {
+                        let mut store = store.lock().unwrap();
+                        store.set(key.as_bytes(), b"value")?;
+                        store.last_seq()
+                    };
+                    commit.wait(seq)?;
+                }
+                Ok(())
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap()?;
+    }
+
+    assert!(commit.total_syncs() > 0);
+    assert!(commit.total_syncs() <= threads * writes);
+    drop(store);
+
+    let mut store = Store::open(tmpdir.path())?;
+    assert_eq!(store.len(), threads * writes);
+    assert_eq!(store.get(b"key_7_49")?, Some(b"value".to_vec()));
+
+    // writes wait for syncing by themselves by default.
+    let mut store = OpenOptions::new().sync(true).open(tmpdir.path())?;
+    let commit = store.commit_handle();
+    store.set(b"key", b"value")?;
+    assert_eq!(commit.total_syncs(), 1);
+    Ok(())
+}
+
+#[test]
+fn sync_policies() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+
+    let mut store = OpenOptions::new()
+        .sync_policy(SyncPolicy::Never)
+        .open(tmpdir.path())?;
+    let commit = store.commit_handle();
+    store.set(b"a", b"1")?;
+    assert_eq!(commit.policy(), SyncPolicy::Never);
+    assert_eq!(commit.total_syncs(), 0);
+    drop(store);
+
+    let mut store = OpenOptions::new()
+        .sync_policy(SyncPolicy::Bytes(1024))
+        .open(tmpdir.path())?;
+    let commit = store.commit_handle();
+    for i in 0..100 {
+        store.set(format!("key_{}", i).as_bytes(), &[0; 64])?;
+    }
+    assert!(commit.total_syncs() > 0);
+    assert!(commit.total_syncs() < 100);
+    assert!(commit.synced_seq() > 0);
+    drop(store);
+
+    let mut store = OpenOptions::new()
+        .sync_policy(SyncPolicy::Interval(Duration::from_millis(10)))
+        .open(tmpdir.path())?;
+    let commit = store.commit_handle();
+    store.set(b"b", b"2")?;
+    let seq = store.last_seq();
+    for _ in 0..100 {
+        if commit.synced_seq() >= seq {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(commit.synced_seq(), seq);
+    drop(store);
+
+    let mut store = Store::open(tmpdir.path())?;
+    assert_eq!(store.len(), 102);
+    assert_eq!(store.get(b"b")?, Some(b"2".to_vec()));
+    Ok(())
+}
+
+#[test]
+fn read_only_mode() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    assert!(OpenOptions::new()
+        .read_only(true)
+        .open(tmpdir.path().join("not-found"))
+        .is_err());
+    assert!(!tmpdir.path().join("not-found").exists());
+
+    let mut writer = Store::open(tmpdir.path())?;
+    writer.set(b"a", b"1")?;
+    writer.sync()?;
+
+    let files = || walkdir::WalkDir::new(tmpdir.path()).into_iter().count();
+    let total_files = files();
+
+    let mut reader = OpenOptions::new().read_only(true).open(tmpdir.path())?;
+    assert_eq!(files(), total_files);
+    assert_eq!(reader.get(b"a")?, Some(b"1".to_vec()));
+    assert!(matches!(
+        reader.set(b"b", b"2"),
+        Err(tinkv::TinkvError::ReadOnly)
+    ));
+    assert!(matches!(
+        reader.remove(b"a"),
+        Err(tinkv::TinkvError::ReadOnly)
+    ));
+    assert!(matches!(reader.compact(), Err(tinkv::TinkvError::ReadOnly)));
+    assert!(writer.refresh().is_err());
+
+    // new writes are visible after refreshing.
+    writer.set(b"b", b"2")?;
+    writer.remove(b"a")?;
+    writer.sync()?;
+    assert_eq!(reader.get(b"b")?, None);
+    reader.refresh()?;
+    assert_eq!(reader.get(b"a")?, None);
+    assert_eq!(reader.get(b"b")?, Some(b"2".to_vec()));
+
+    writer.compact()?;
+    writer.set(b"c", b"3")?;
+    writer.sync()?;
+    reader.refresh()?;
+    assert_eq!(reader.len(), 2);
+    assert_eq!(reader.get(b"c")?, Some(b"3".to_vec()));
+
+    drop(reader);
+    drop(writer);
+    let total_files_after = files();
+    let mut reader = OpenOptions::new().read_only(true).open(tmpdir.path())?;
+    assert_eq!(reader.get(b"c")?, Some(b"3".to_vec()));
+    drop(reader);
+    assert_eq!(files(), total_files_after);
+    Ok(())
+}
+
+#[test]
+fn refresh_keeps_state_on_failure() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut writer = Store::open(tmpdir.path())?;
+    writer.set(b"a", b"1")?;
+    writer.sync()?;
+    let mut reader = OpenOptions::new().read_only(true).open(tmpdir.path())?;
+    writer.set(b"b", b"2")?;
+    writer.sync()?;
+    drop(writer);
+
+    // garbage at the end of the data file can't be decoded.
+    let path = std::fs::read_dir(tmpdir.path())?
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.to_string_lossy().ends_with(".tinkv.data"))
+        .expect("data file not found");
+    let mut bytes = std::fs::read(&path)?;
+    bytes.extend_from_slice(&[0xff; 64]);
+    std::fs::write(&path, &bytes)?;
+
+    assert!(reader.refresh().is_err());
+    assert_eq!(reader.len(), 1);
+    assert_eq!(reader.get(b"a")?, Some(b"1".to_vec()));
+    assert_eq!(reader.stats().total_active_entries, 1);
+    assert_eq!(reader.stats().total_data_files, 1);
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct User {
+    name: String,
+    age: u32,
+}
+
+#[test]
+fn typed_store() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut users = TypedStore::<(String, i64), User>::open(tmpdir.path())?;
+    for &(tenant, id) in &[("b", 1i64), ("a", 10), ("a", -3), ("a", 2), ("c", 0)] {
+        let user = User {
+            name: format!("{}{}", tenant, id),
+            age: id.unsigned_abs() as u32,
+        };
+        users.set(&(tenant.to_owned(), id), &user)?;
+    }
+
+    let key = ("a".to_owned(), 2);
+    assert!(users.contains_key(&key)?);
+    assert_eq!(users.get(&key)?.unwrap().name, "a2");
+    users.remove(&key)?;
+    assert_eq!(users.get(&key)?, None);
+    assert!(matches!(
+        users.remove(&key),
+        Err(tinkv::TinkvError::KeyNotFound(_))
+    ));
+    assert_eq!(users.len(), 4);
+
+    // keys are iterated in order of the typed ones.
+    let start = ("a".to_owned(), i64::MIN);
+    let end = ("b".to_owned(), i64::MIN);
+    let names = users
+        .range(start..end)?
+        .map(|r| r.map(|(_, user)| user.name))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(names, vec!["a-3", "a10"]);
+    let keys = users
+        .iter()?
+        .map(|r| r.map(|(key, _)| key))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        keys,
+        vec![
+            ("a".to_owned(), -3),
+            ("a".to_owned(), 10),
+            ("b".to_owned(), 1),
+            ("c".to_owned(), 0)
+        ]
+    );
+    let end = ("a".to_owned(), 0);
+    assert_eq!(users.range(end.clone()..end)?.count(), 0);
+
+    // reopen with the values encoded in JSON.
+    let mut store = users.into_inner();
+    store.set(b"raw", b"value")?;
+    let mut users = TypedStore::<(String, i64), User, _>::with_codec(store, JsonCodec);
+    users.set(
+        &("d".to_owned(), 1),
+        &User {
+            name: "d1".to_owned(),
+            age: 1,
+        },
+    )?;
+    assert_eq!(users.get(&("d".to_owned(), 1))?.unwrap().age, 1);
+    assert!(users.get(&("a".to_owned(), 10)).is_err());
+    assert!(users.iter()?.any(|r| r.is_err()));
+    Ok(())
+}
+
+fn open_with_eviction(path: &std::path::Path, policy: EvictionPolicy) -> Result<Store> {
+    OpenOptions::new()
+        .max_keys(3)
+        .eviction_policy(policy)
+        .open(path)
+}
+
+#[test]
+fn evict_keys() -> Result<()> {
+    // no eviction, writes of new keys are rejected.
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = open_with_eviction(tmpdir.path(), EvictionPolicy::NoEviction)?;
+    for key in &["a", "b", "c"] {
+        store.set(key.as_bytes(), b"v")?;
+    }
+    assert!(matches!(
+        store.set(b"d", b"v"),
+        Err(tinkv::TinkvError::StoreFull)
+    ));
+    store.set(b"a", b"v2")?;
+    store.remove(b"b")?;
+    store.set(b"d", b"v")?;
+    assert_eq!(store.stats().total_evicted_keys, 0);
+
+    // least recently used.
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = open_with_eviction(tmpdir.path(), EvictionPolicy::Lru)?;
+    for key in &["a", "b", "c"] {
+        store.set(key.as_bytes(), b"v")?;
+    }
+    store.get(b"a")?;
+    store.set(b"d", b"v")?;
+    store.set(b"a", b"v2")?;
+    store.set(b"e", b"v")?;
+    assert_eq!(
+        store.keys().cloned().collect::<Vec<_>>(),
+        vec![b"a", b"d", b"e"]
+    );
+    assert_eq!(store.stats().total_evicted_keys, 2);
+
+    // evicted keys are removed from data files.
+    drop(store);
+    let mut store = Store::open(tmpdir.path())?;
+    assert_eq!(store.len(), 3);
+    assert_eq!(store.get(b"b")?, None);
+    assert_eq!(store.get(b"a")?, Some(b"v2".to_vec()));
+
+    // least frequently used.
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = open_with_eviction(tmpdir.path(), EvictionPolicy::Lfu)?;
+    for key in &["a", "b", "c"] {
+        store.set(key.as_bytes(), b"v")?;
+    }
+    for key in &["a", "a", "c", "b", "c"] {
+        store.get(key.as_bytes())?;
+    }
+    store.set(b"d", b"v")?;
+    assert!(!store.contains_key(b"b"));
+    store.set(b"e", b"v")?;
+    assert!(!store.contains_key(b"d"));
+
+    // keys nearest to expire, others are never evicted.
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = open_with_eviction(tmpdir.path(), EvictionPolicy::VolatileTtl)?;
+    store.set_with_ttl(b"a", b"v", Duration::from_secs(100))?;
+    store.set_with_ttl(b"b", b"v", Duration::from_secs(10))?;
+    store.set(b"c", b"v")?;
+    store.set(b"d", b"v")?;
+    assert!(!store.contains_key(b"b"));
+    store.set(b"e", b"v")?;
+    assert!(!store.contains_key(b"a"));
+    assert!(matches!(
+        store.set(b"f", b"v"),
+        Err(tinkv::TinkvError::StoreFull)
+    ));
+
+    // random keys, keyspaces share the limits.
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = open_with_eviction(tmpdir.path(), EvictionPolicy::Random)?;
+    for i in 0..50u32 {
+        store.set(&i.to_be_bytes(), b"v")?;
+        store.keyspace("ks")?.set(&i.to_be_bytes(), b"v")?;
+    }
+    assert_eq!(store.len() + store.keyspace("ks")?.len(), 3);
+    assert_eq!(store.stats().total_evicted_keys, 97);
+    assert!(store.keyspace("ks")?.contains_key(&49u32.to_be_bytes()));
+    Ok(())
+}
+
+#[test]
+fn evict_keys_by_size() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = OpenOptions::new()
+        .max_total_size(1000)
+        .eviction_policy(EvictionPolicy::Lru)
+        .open(tmpdir.path())?;
+    for i in 0..100u32 {
+        store.set(&i.to_be_bytes(), &[0; 100])?;
+    }
+    let total_size = |store: &Store| {
+        let stats = store.stats();
+        stats.size_of_all_data_files - stats.size_of_stale_entries
+    };
+    assert!(store.len() < 10);
+    assert!(store.stats().total_evicted_keys > 90);
+    assert!(store.contains_key(&99u32.to_be_bytes()));
+
+    // disk space is reclaimed on compaction.
+    store.compact()?;
+    assert!(total_size(&store) < 1200);
+    assert!(store.stats().size_of_all_data_files < 1200);
+    Ok(())
+}
+
+#[test]
+fn unknown_and_unreadable_files() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+    store.set(b"hello", b"world")?;
+    drop(store);
+
+    // stray files are skipped.
+    std::fs::write(tmpdir.path().join("foo.tinkv.data"), b"foo")?;
+    let mut store = Store::open(tmpdir.path())?;
+    assert_eq!(store.get(b"hello")?, Some(b"world".to_vec()));
+    drop(store);
+
+    // keyspace of the entry is not valid utf-8.
+    // the header is copied from a valid data file.
+    let mut bytes = std::fs::read(tmpdir.path().join("000000000001.tinkv.data"))?[..8].to_vec();
+    // seq, kind (put) and timestamp.
+    bytes.extend_from_slice(&[0; 20]);
+    bytes.extend_from_slice(&2u64.to_le_bytes());
+    bytes.extend_from_slice(&[0xff, 0xff]);
+    std::fs::write(tmpdir.path().join("000000000099.tinkv.data"), &bytes)?;
+    match Store::open(tmpdir.path()) {
+        Err(tinkv::TinkvError::SegmentRead { offset, .. }) => assert_eq!(offset, 8),
+        r => panic!("unexpected result: {:?}", r.map(|_| ())),
+    }
+    Ok(())
+}
+
+#[test]
+fn unsupported_format() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    // a record written before segment files had headers:
+    // key, value and checksum.
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&5u64.to_le_bytes());
+    bytes.extend_from_slice(b"hello");
+    bytes.extend_from_slice(&5u64.to_le_bytes());
+    bytes.extend_from_slice(b"world");
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    std::fs::write(tmpdir.path().join("000000000001.tinkv.data"), &bytes)?;
+
+    match Store::open(tmpdir.path()) {
+        Err(e @ tinkv::TinkvError::UnsupportedFormat { version: 0, .. }) => {
+            assert!(e.to_string().contains("000000000001.tinkv.data"));
+        }
+        r => panic!("unexpected result: {:?}", r.map(|_| ())),
+    }
+    // files are left untouched.
+    assert_eq!(
+        std::fs::read(tmpdir.path().join("000000000001.tinkv.data"))?,
+        bytes
+    );
+    Ok(())
+}
+
+#[test]
+fn scrub() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = OpenOptions::new()
+        .max_data_file_size(200)
+        .open(tmpdir.path())?;
+    for i in 0..10u8 {
+        store.set(&[b'k', i], &[b'v', i, 0xaa, 0xbb, 0xcc])?;
+    }
+    assert!(store.sealed_segments().len() > 1);
+    let report = store.scrub()?;
+    assert!(report.is_clean());
+    assert_eq!(report.total_files, store.sealed_segments().len() as u64);
+    // keydir is built from hint files of compacted data files.
+    store.compact()?;
+    drop(store);
+
+    // flip a byte of value of `k1` in data file.
+    let (path, mut bytes, pos) = std::fs::read_dir(tmpdir.path())?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let bytes = std::fs::read(&path).ok()?;
+            let pos = bytes
+                .windows(5)
+                .position(|w| w == [b'v', 1, 0xaa, 0xbb, 0xcc])?;
+            Some((path, bytes, pos))
+        })
+        .next()
+        .expect("value not found");
+    let file_id = tinkv::util::parse_file_id(&path).unwrap();
+    bytes[pos + 2] = 0;
+    std::fs::write(&path, &bytes)?;
+
+    let mut options = OpenOptions::new();
+    options.read_only(true);
+    let store = options.open(tmpdir.path())?;
+    let report = store.scrub()?;
+    assert_eq!(report.corrupted_records.len(), 1);
+    assert_eq!(report.corrupted_records[0].file_id, file_id);
+    assert_eq!(
+        report.corrupted_records[0].key,
+        Some(("default".to_owned(), vec![b'k', 1]))
+    );
+    assert_eq!(
+        report.affected_keys,
+        vec![("default".to_owned(), vec![b'k', 1])]
+    );
+    assert!(matches!(
+        store.scrub_segment(99),
+        Err(tinkv::TinkvError::SegmentNotFound(99))
+    ));
+    Ok(())
+}
+
+#[test]
+fn scrub_corrupted_key() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = OpenOptions::new()
+        .max_data_file_size(200)
+        .open(tmpdir.path())?;
+    for i in 0..10u8 {
+        store.set(&[b'k', b'e', b'y', i], &[b'v', i])?;
+    }
+    store.compact()?;
+    drop(store);
+
+    // flip a byte of key `key2` in data file, its value stays intact.
+    let (path, mut bytes, pos) = std::fs::read_dir(tmpdir.path())?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if !path.to_string_lossy().ends_with(".tinkv.data") {
+                return None;
+            }
+            let bytes = std::fs::read(&path).ok()?;
+            let pos = bytes.windows(4).position(|w| w == [b'k', b'e', b'y', 2])?;
+            Some((path, bytes, pos))
+        })
+        .next()
+        .expect("key not found");
+    let file_id = tinkv::util::parse_file_id(&path).unwrap();
+    bytes[pos + 1] = b'a';
+    std::fs::write(&path, &bytes)?;
+
+    let mut options = OpenOptions::new();
+    options.read_only(true);
+    let store = options.open(tmpdir.path())?;
+    let report = store.scrub()?;
+    assert_eq!(report.corrupted_records.len(), 1);
+    assert_eq!(report.corrupted_records[0].file_id, file_id);
+    assert_eq!(
+        report.affected_keys,
+        vec![("default".to_owned(), vec![b'k', b'e', b'y', 2])]
+    );
+    Ok(())
+}
+
+#[test]
+fn limit_background_io() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = OpenOptions::new()
+        .background_io_rate(50_000)
+        .open(tmpdir.path())?;
+    for i in 0..100u8 {
+        store.set(&[i], &[0; 1000])?;
+    }
+
+    // a burst of one second is allowed, the rest waits for tokens.
+    let begin_at = std::time::Instant::now();
+    store.compact()?;
+    assert!(begin_at.elapsed() >= Duration::from_millis(500));
+    assert_eq!(store.len(), 100);
+
+    let limiter = store.rate_limiter();
+    assert_eq!(limiter.rate(), Some(50_000));
+    limiter.set_rate(None);
+    let begin_at = std::time::Instant::now();
+    store.compact()?;
+    assert!(begin_at.elapsed() < Duration::from_millis(500));
+    Ok(())
+}
+
+#[test]
+fn segments() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = OpenOptions::new()
+        .max_data_file_size(200)
+        .open(tmpdir.path())?;
+    for i in 0..10u8 {
+        store.set(&[i], &[i; 20])?;
+    }
+    for i in 0..5u8 {
+        store.set(&[i], &[i; 20])?;
+    }
+
+    let segments = store.segments()?;
+    assert!(segments.len() > 1);
+    assert!(segments.windows(2).all(|w| w[0].id < w[1].id));
+    assert_eq!(segments.iter().filter(|s| s.writeable).count(), 1);
+    assert_eq!(
+        segments.iter().map(|s| s.total_active_entries).sum::<u64>(),
+        10
+    );
+    let stats = *store.stats();
+    assert_eq!(
+        segments.iter().map(|s| s.size).sum::<u64>(),
+        stats.size_of_all_data_files
+    );
+    assert_eq!(
+        segments
+            .iter()
+            .map(|s| s.size_of_stale_entries)
+            .sum::<u64>(),
+        stats.size_of_stale_entries
+    );
+    // the first data file only holds overwritten keys.
+    assert_eq!(segments[0].total_active_entries, 0);
+    assert_eq!(segments[0].garbage_ratio(), 1.0);
+    assert!(segments[0].created_at.is_some());
+    assert!(segments.iter().all(|s| !s.has_hint));
+
+    store.compact()?;
+    let segments = store.segments()?;
+    assert!(segments.iter().any(|s| s.has_hint));
+    assert!(segments.iter().all(|s| s.garbage_ratio() < 0.5));
+
+    // data file written by compaction holds `a` before the older `b`.
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+    store.set(b"a", b"1")?;
+    store.set(b"b", b"1")?;
+    store.set(b"a", b"2")?;
+    store.compact()?;
+    let (_, meta) = store.get_with_meta(b"b")?.unwrap();
+    let segments = store.segments()?;
+    let compacted = segments.iter().find(|s| s.has_hint).unwrap();
+    assert_eq!(compacted.created_at, Some(meta.timestamp_ns));
+    Ok(())
+}
+
+#[test]
+fn dump_segment() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+    store.set(b"a", b"1")?;
+    store.set(b"b", &[b'x'; 40])?;
+    store.remove(b"a")?;
+    drop(store);
+
+    let path = tmpdir.path().join("000000000001.tinkv.data");
+    let dump = |options: &tinkv::DumpOptions| -> Result<Vec<serde_json::Value>> {
+        let mut buf = vec![];
+        tinkv::dump_segment(&path, &mut buf, options)?;
+        Ok(String::from_utf8_lossy(&buf)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect())
+    };
+
+    let mut options = tinkv::DumpOptions::new();
+    options.json(true);
+    let records = dump(&options)?;
+    let kinds = records
+        .iter()
+        .map(|r| r["kind"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(kinds, vec!["put", "put", "remove"]);
+    assert_eq!(records[0]["key"], "a");
+    assert_eq!(records[0]["value"], "1");
+    assert_eq!(records[0]["checksum_valid"], true);
+    assert_eq!(records[1]["value_size"], 40);
+    assert!(records[1]["value"].as_str().unwrap().ends_with("..."));
+
+    let offset = records[1]["offset"].as_u64().unwrap();
+    options.offsets(offset..u64::MAX);
+    assert_eq!(dump(&options)?.len(), 2);
+    options.offsets(0..offset);
+    assert_eq!(dump(&options)?.len(), 1);
+    options.offsets(0..u64::MAX).key_pattern("a*")?;
+    assert_eq!(dump(&options)?.len(), 2);
+
+    // a corrupted value is dumped as well.
+    let mut bytes = std::fs::read(&path)?;
+    let pos = bytes.windows(3).position(|w| w == b"xxx").unwrap();
+    bytes[pos] = b'y';
+    std::fs::write(&path, &bytes)?;
+    options.key_pattern("b")?;
+    assert_eq!(dump(&options)?[0]["checksum_valid"], false);
+
+    let mut buf = vec![];
+    assert!(matches!(
+        tinkv::dump_segment(tmpdir.path().join("foo"), &mut buf, &options),
+        Err(tinkv::TinkvError::UnknownFile(_))
+    ));
+
+    // hint records are dumped with their kinds.
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = Store::open(tmpdir.path())?;
+    store.set(b"a", b"1")?;
+    store.compact()?;
+    drop(store);
+    let path = std::fs::read_dir(tmpdir.path())?
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.to_string_lossy().ends_with(".tinkv.hint"))
+        .expect("hint file not found");
+    let mut buf = vec![];
+    tinkv::dump_segment(&path, &mut buf, tinkv::DumpOptions::new().json(true))?;
+    let kinds = String::from_utf8_lossy(&buf)
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["kind"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(kinds, vec!["put", "seq-mark"]);
+    Ok(())
+}
+
+#[test]
+fn key_stats() -> Result<()> {
+    let tmpdir = TempDir::new().expect("unable to create tmp dir");
+    let mut store = OpenOptions::new().open(tmpdir.path())?;
+    store.set(b"user:1", &[0; 10])?;
+    store.set(b"user:2", &[0; 100])?;
+    store.set(b"post:1", &[0; 1000])?;
+    store.set(b"counter", b"")?;
+    store.keyspace("sessions")?.set(b"session:1", &[0; 10])?;
+
+    let stats = store.key_stats().clone();
+    assert_eq!(stats.key_sizes.count(), 5);
+    assert_eq!(stats.value_sizes.count(), 5);
+    assert_eq!(stats.value_sizes.percentile(100.0), 1023);
+    assert_eq!(stats.value_sizes.buckets()[0], (0, 0, 1));
+    assert_eq!(stats.prefixes.get(&b"user"[..]), Some(&2));
+    assert_eq!(stats.prefixes.get(&b"session"[..]), Some(&1));
+    assert_eq!(stats.prefixes.get(&b""[..]), Some(&1));
+
+    // overwritten and removed keys are not counted.
+    store.set(b"post:1", &[0; 10])?;
+    store.remove(b"user:2")?;
+    store.drop_keyspace("sessions")?;
+    let stats = store.key_stats().clone();
+    assert_eq!(stats.key_sizes.count(), 3);
+    assert_eq!(stats.value_sizes.percentile(100.0), 15);
+    assert_eq!(stats.prefixes.get(&b"user"[..]), Some(&1));
+    assert_eq!(stats.prefixes.get(&b"session"[..]), None);
+    drop(store);
+
+    // rebuilt on opening, with or without hint files.
+    let mut store = OpenOptions::new().open(tmpdir.path())?;
+    assert_eq!(store.key_stats(), &stats);
+    store.compact()?;
+    assert_eq!(store.key_stats(), &stats);
+    drop(store);
+    let store = OpenOptions::new()
+        .key_prefix_delimiter(b'/')
+        .open(tmpdir.path())?;
+    assert_eq!(store.key_stats().key_sizes, stats.key_sizes);
+    assert_eq!(store.key_stats().value_sizes, stats.value_sizes);
+    assert_eq!(store.key_stats().prefixes.get(&b""[..]), Some(&3));
+    Ok(())
+}